@@ -47,3 +47,15 @@ fn parse_2_9(b: &mut Bencher) {
         buf.set_position(0);
     });
 }
+
+#[bench]
+fn view_2_9(b: &mut Bencher) {
+    let parser = NatNet::new(Version::parse("2.9.0").unwrap());
+    let buf = help_open(format!("tests/data/frame-motive-1.9.0-001.bin")).into_inner();
+    b.iter(|| {
+        let view = parser.view(&buf).unwrap();
+        for body in view.rigid_bodies() {
+            test::black_box(body);
+        }
+    });
+}
@@ -0,0 +1,62 @@
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use marker::LabeledMarker;
+use rigid_body::RigidBody;
+use semver::Version;
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, capacity, read_count};
+
+/// An `Asset` is a trained marker set
+///
+/// # `NatNet` version
+/// This structure is new in 4.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// ID of asset
+    pub id: i32,
+    /// Rigid bodies of this asset
+    pub rigid_bodies: Vec<RigidBody>,
+    /// Markers of this asset
+    pub markers: Vec<LabeledMarker>,
+}
+
+impl Unpack<Asset> for Asset {
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Asset> {
+        // Asset rigid bodies and markers share the layout of regular rigid
+        // bodies and labeled markers
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num_bodies = try!(read_count(bytes, "max_bodies", limits.max_bodies)
+            .field("rigid_bodies"));
+        let mut bodies = Vec::with_capacity(capacity(num_bodies));
+        for i in 0..num_bodies as usize {
+            let body = RigidBody::unpack(ver, limits, bytes);
+            bodies.push(try!(body.index(i).field("rigid_bodies")));
+        }
+        let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers)
+            .field("markers"));
+        let mut markers = Vec::with_capacity(capacity(num_markers));
+        for i in 0..num_markers as usize {
+            let marker = LabeledMarker::unpack(ver, limits, bytes);
+            markers.push(try!(marker.index(i).field("markers")));
+        }
+        Ok(Asset {
+            id: id,
+            rigid_bodies: bodies,
+            markers: markers,
+        })
+    }
+}
+
+impl Pack for Asset {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_i32::<LittleEndian>(self.rigid_bodies.len() as i32));
+        for body in &self.rigid_bodies {
+            try!(body.pack(ver, bytes));
+        }
+        try!(bytes.write_i32::<LittleEndian>(self.markers.len() as i32));
+        for marker in &self.markers {
+            try!(marker.pack(ver, bytes));
+        }
+        Ok(())
+    }
+}
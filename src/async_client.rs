@@ -0,0 +1,497 @@
+//! Asynchronous `NatNet` client for `tokio`
+//!
+//! This module is only available with the `tokio` feature. The client is a
+//! `Stream` of frames of data, while commands can be sent concurrently
+//! through a `Requester`, each with its own timeout.
+//!
+//! `NatNet` has no way to tell which request a reply belongs to, but a server
+//! answers requests in order. Replies are therefore matched to the oldest
+//! request waiting for that type of reply. A request which times out keeps
+//! its place for as long again as its timeout, so that a late reply is not
+//! mistaken for the reply to a later request.
+//!
+//! # Example
+//! ```rust,ignore
+//! use futures_util::StreamExt;
+//! use natnet_decode::async_client::AsyncClient;
+//! use natnet_decode::client::ClientConfig;
+//!
+//! let mut client = AsyncClient::connect(ClientConfig::default()).await?;
+//! let requester = client.requester();
+//! tokio::spawn(requester.request(Command::StartRecording, Duration::from_secs(1)));
+//! while let Some(frame) = client.next().await {
+//!     // Do stuff with frame
+//! }
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+use client::{self, ClientConfig};
+use futures_util::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use futures_util::stream::Stream;
+use model::DataSet;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::{self as std_net, Ipv4Addr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use super::{AutoNatNet, Command, CommandResponse, FrameOfData, NatNetMsgType, NatNetRequest,
+            NatNetResponse, ParseError, Result, Sender, ServerDescription, lock};
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+// Number of frames of data buffered for a slow consumer, the oldest frame is
+// dropped to make room for a new one after this
+const FRAME_BUFFER: usize = 16;
+
+/// Type of reply a request is waiting for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reply {
+    Handshake,
+    Models,
+    Command,
+}
+
+impl Reply {
+    fn matches(&self, msg: &NatNetResponse) -> bool {
+        match (*self, msg) {
+            (Reply::Handshake, &NatNetResponse::Ping(_)) |
+            (Reply::Handshake, &NatNetResponse::ServerInfo(_)) |
+            (Reply::Models, &NatNetResponse::ModelDef(_)) |
+            (Reply::Command, &NatNetResponse::Response(_)) |
+            (Reply::Command, &NatNetResponse::ResponseString(_)) |
+            (Reply::Command, &NatNetResponse::UnrecognizedRequest) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A request waiting for its reply
+struct Pending {
+    reply: Reply,
+    tx: oneshot::Sender<NatNetResponse>,
+    // The place of the request is kept until this point, even after it
+    // timed out
+    retire: Instant,
+}
+
+/// State shared between the client and its background tasks
+struct Shared {
+    parser: AutoNatNet,
+    pending: VecDeque<Pending>,
+    sender: Option<Sender>,
+    description: Option<ServerDescription>,
+    models: Vec<DataSet>,
+    frames: VecDeque<Result<FrameOfData>>,
+    // Task of the client waiting for the next frame of data
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    /// Hand a reply to the oldest request waiting for it
+    fn complete(&mut self, msg: NatNetResponse) {
+        let now = Instant::now();
+        self.pending.retain(|p| p.retire > now);
+        match msg {
+            NatNetResponse::ModelDef(ref models) => self.models = models.clone(),
+            NatNetResponse::Ping(ref sender) => self.sender = Some(sender.clone()),
+            NatNetResponse::ServerInfo(ref desc) => {
+                self.sender = Some(desc.sender.clone());
+                self.description = Some(desc.clone());
+            }
+            _ => {}
+        }
+        match self.pending.iter().position(|p| p.reply.matches(&msg)) {
+            Some(i) => {
+                if let Some(pending) = self.pending.remove(i) {
+                    // The request may have timed out already
+                    let _ = pending.tx.send(msg);
+                }
+            }
+            None => debug!("Dropping unrequested message: {:?}", msg),
+        }
+    }
+}
+
+/// Background task receiving on the command socket
+///
+/// Replies are handed to the waiting requests and, in unicast mode, frames
+/// of data are forwarded to the client. Keep-alive messages are also sent
+/// from here in unicast mode.
+struct CommandTask {
+    socket: Arc<UdpSocket>,
+    shared: Arc<Mutex<Shared>>,
+    keep_alive: Option<Interval>,
+    buf: Vec<u8>,
+}
+
+impl Future for CommandTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        loop {
+            if let Some(ref mut keep_alive) = this.keep_alive {
+                while keep_alive.poll_tick(cx).is_ready() {
+                    let bytes: Vec<u8> = NatNetRequest::KeepAlive.into();
+                    if let Err(err) = this.socket.try_send(&bytes) {
+                        debug!("Could not send keep-alive: {}", err);
+                    }
+                }
+            }
+            let len = {
+                let mut buf = ReadBuf::new(&mut this.buf);
+                match this.socket.poll_recv(cx, &mut buf) {
+                    Poll::Pending => return Poll::Pending,
+                    // Errors such as an unreachable server are reported
+                    // through the requests timing out
+                    Poll::Ready(Err(err)) => {
+                        debug!("Error on command socket: {}", err);
+                        continue;
+                    }
+                    Poll::Ready(Ok(())) => buf.filled().len(),
+                }
+            };
+            let msg = lock(&this.shared).parser.unpack(&mut &this.buf[..len]);
+            match msg {
+                Ok(NatNetResponse::FrameOfData(frame)) => forward(&this.shared, Ok(frame)),
+                Ok(NatNetResponse::Disconnect) |
+                Ok(NatNetResponse::DisconnectByTimeout) => {
+                    warn!("Disconnected by server");
+                }
+                Ok(msg) => lock(&this.shared).complete(msg),
+                Err(err) => {
+                    if is_frame(&this.buf[..len]) {
+                        forward(&this.shared, Err(err));
+                    } else {
+                        debug!("Dropping message on command socket: {}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Background task receiving frames of data on the data socket
+struct DataTask {
+    socket: UdpSocket,
+    shared: Arc<Mutex<Shared>>,
+    buf: Vec<u8>,
+}
+
+impl Future for DataTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        loop {
+            let len = {
+                let mut buf = ReadBuf::new(&mut this.buf);
+                match this.socket.poll_recv(cx, &mut buf) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(err)) => {
+                        debug!("Error on data socket: {}", err);
+                        continue;
+                    }
+                    Poll::Ready(Ok(())) => buf.filled().len(),
+                }
+            };
+            let parser = lock(&this.shared).parser.parser();
+            let parser = match parser {
+                Some(parser) => parser,
+                None => continue,
+            };
+            match parser.unpack(&mut &this.buf[..len]) {
+                Ok(NatNetResponse::FrameOfData(frame)) => forward(&this.shared, Ok(frame)),
+                Ok(NatNetResponse::ModelDef(models)) => lock(&this.shared).models = models,
+                Ok(msg) => debug!("Dropping message on data socket: {:?}", msg),
+                Err(err) => forward(&this.shared, Err(err)),
+            }
+        }
+    }
+}
+
+/// Pass a frame on to the client, dropping the oldest frame if the client is
+/// not keeping up
+fn forward(shared: &Mutex<Shared>, frame: Result<FrameOfData>) {
+    let mut shared = lock(shared);
+    if shared.frames.len() >= FRAME_BUFFER {
+        debug!("Dropping oldest frame of data, client is not keeping up");
+        shared.frames.pop_front();
+    }
+    shared.frames.push_back(frame);
+    if let Some(waker) = shared.waker.take() {
+        waker.wake();
+    }
+}
+
+fn is_frame(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && LittleEndian::read_u16(bytes) == NatNetMsgType::FrameOfData as u16
+}
+
+fn timed_out() -> ParseError {
+    ParseError::IO(io::Error::new(io::ErrorKind::TimedOut, "No reply from NatNet server"))
+}
+
+fn closed() -> ParseError {
+    ParseError::IO(io::Error::new(io::ErrorKind::BrokenPipe, "NatNet client is closed"))
+}
+
+/// The parts of a client needed to send requests
+struct Connection {
+    config: ClientConfig,
+    socket: Arc<UdpSocket>,
+    data_addr: Option<std_net::SocketAddr>,
+    shared: Arc<Mutex<Shared>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Connection {
+    /// Stop the background tasks, and disconnect in unicast mode
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if self.config.unicast {
+            let bytes: Vec<u8> = NatNetRequest::Disconnect.into();
+            let _ = self.socket.try_send(&bytes);
+        }
+    }
+}
+
+/// Handle for sending requests to the server of an `AsyncClient`
+///
+/// Requesters can be cloned and moved to other tasks. The background tasks
+/// of the client stop when the client and all requesters are dropped.
+#[derive(Clone)]
+pub struct Requester {
+    conn: Arc<Connection>,
+}
+
+impl Requester {
+    /// Send a command and wait at most `timeout` for the response
+    pub fn request(&self,
+                   cmd: Command,
+                   timeout: Duration)
+                   -> BoxFuture<'static, Result<CommandResponse>> {
+        let bytes: Vec<u8> = NatNetRequest::Command(cmd.clone()).into();
+        self.send(bytes, Reply::Command, timeout)
+            .and_then(move |reply| future::ready(cmd.decode_response(&reply)))
+            .boxed()
+    }
+
+    /// Fetch the model definitions and wait at most `timeout` for them
+    pub fn request_models(&self, timeout: Duration) -> BoxFuture<'static, Result<Vec<DataSet>>> {
+        let bytes: Vec<u8> = NatNetRequest::ModelDefinitions.into();
+        self.send(bytes, Reply::Models, timeout)
+            .map_ok(|reply| match reply {
+                NatNetResponse::ModelDef(models) => models,
+                _ => Vec::new(),
+            })
+            .boxed()
+    }
+
+    /// Send a request and wait for the matching reply
+    fn send(&self,
+            bytes: Vec<u8>,
+            reply: Reply,
+            timeout: Duration)
+            -> BoxFuture<'static, Result<NatNetResponse>> {
+        let (tx, rx) = oneshot::channel();
+        lock(&self.conn.shared).pending.push_back(Pending {
+            reply: reply,
+            tx: tx,
+            retire: Instant::now() + timeout * 2,
+        });
+        let socket = self.conn.socket.clone();
+        future::poll_fn(move |cx| socket.poll_send(cx, &bytes))
+            .map_err(ParseError::from)
+            .and_then(move |_| {
+                time::timeout(timeout, rx).map(|res| match res {
+                    Ok(Ok(msg)) => Ok(msg),
+                    Ok(Err(_)) => Err(closed()),
+                    Err(_) => Err(timed_out()),
+                })
+            })
+            .boxed()
+    }
+}
+
+/// Asynchronous `NatNet` client
+///
+/// Connecting does the same handshake as `client::Client`, after which the
+/// client is a `Stream` of the frames of data sent by the server. Frames of
+/// data which can not be decoded are returned as errors. If the stream is
+/// not polled often enough only the 16 most recent frames are kept.
+///
+/// The client does not reconnect by itself, create a new client instead.
+pub struct AsyncClient {
+    requester: Requester,
+}
+
+impl AsyncClient {
+    /// Connect to the server given in `config`
+    ///
+    /// This must be polled from within a `tokio` runtime.
+    pub fn connect(config: ClientConfig) -> BoxFuture<'static, Result<AsyncClient>> {
+        future::lazy(move |_| AsyncClient::start(config))
+            .and_then(|client| {
+                let requester = client.requester();
+                let conn = &requester.conn;
+                let handshake = client::handshake_request(&conn.config);
+                let timeout = conn.config.timeout;
+                future::ready(handshake)
+                    .and_then(move |bytes| requester.send(bytes, Reply::Handshake, timeout))
+                    .map_ok(move |_| client)
+            })
+            .and_then(|client| {
+                let requester = client.requester();
+                let config = &requester.conn.config;
+                let subscriptions = if config.unicast {
+                    config.subscriptions
+                        .iter()
+                        .map(|&(data_type, ref name)| {
+                            let cmd = Command::SubscribeToData {
+                                data_type: data_type,
+                                name: name.clone(),
+                            };
+                            requester.request(cmd, config.timeout)
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                future::try_join_all(subscriptions).map_ok(move |res| {
+                    for r in res.iter().filter(|r| **r != CommandResponse::Success) {
+                        warn!("Subscription failed: {:?}", r);
+                    }
+                    client
+                })
+            })
+            .and_then(|client| {
+                let timeout = client.requester.conn.config.timeout;
+                client.requester().request_models(timeout).map_ok(move |_| client)
+            })
+            .boxed()
+    }
+
+    /// Bind the sockets and start the background tasks
+    fn start(config: ClientConfig) -> Result<AsyncClient> {
+        let command = try!(std_net::UdpSocket::bind((config.interface, 0)));
+        try!(command.connect((config.server, config.command_port)));
+        try!(command.set_nonblocking(true));
+        let socket = Arc::new(try!(UdpSocket::from_std(command)));
+        let shared = Arc::new(Mutex::new(Shared {
+            parser: AutoNatNet::new(),
+            pending: VecDeque::new(),
+            sender: None,
+            description: None,
+            models: Vec::new(),
+            frames: VecDeque::with_capacity(FRAME_BUFFER),
+            waker: None,
+        }));
+        let keep_alive = if config.unicast {
+            let mut interval = time::interval(config.keep_alive);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Some(interval)
+        } else {
+            None
+        };
+        let mut tasks = vec![::tokio::spawn(CommandTask {
+                                 socket: socket.clone(),
+                                 shared: shared.clone(),
+                                 keep_alive: keep_alive,
+                                 buf: vec![0; client::MAX_MESSAGE],
+                             })];
+        let mut data_addr = None;
+        if !config.unicast {
+            let data = try!(std_net::UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0),
+                                                      config.data_port)));
+            if let Some(ref group) = config.multicast_group {
+                try!(data.join_multicast_v4(group, &config.interface));
+            }
+            try!(data.set_nonblocking(true));
+            data_addr = Some(try!(data.local_addr()));
+            tasks.push(::tokio::spawn(DataTask {
+                socket: try!(UdpSocket::from_std(data)),
+                shared: shared.clone(),
+                buf: vec![0; client::MAX_MESSAGE],
+            }));
+        }
+        Ok(AsyncClient {
+            requester: Requester {
+                conn: Arc::new(Connection {
+                    config: config,
+                    socket: socket,
+                    data_addr: data_addr,
+                    shared: shared,
+                    tasks: tasks,
+                }),
+            },
+        })
+    }
+
+    /// Handle for sending requests concurrently with receiving frames
+    pub fn requester(&self) -> Requester {
+        self.requester.clone()
+    }
+
+    /// Send a command and wait at most `timeout` for the response
+    pub fn request(&self,
+                   cmd: Command,
+                   timeout: Duration)
+                   -> BoxFuture<'static, Result<CommandResponse>> {
+        self.requester.request(cmd, timeout)
+    }
+
+    /// The server application
+    pub fn sender(&self) -> Option<Sender> {
+        lock(&self.requester.conn.shared).sender.clone()
+    }
+
+    /// Description of the server, only sent by servers using `NatNet` 3.0
+    /// and later
+    pub fn server_description(&self) -> Option<ServerDescription> {
+        lock(&self.requester.conn.shared).description.clone()
+    }
+
+    /// The most recent model definitions from the server
+    pub fn models(&self) -> Vec<DataSet> {
+        lock(&self.requester.conn.shared).models.clone()
+    }
+
+    /// Local address frames of data are received on
+    ///
+    /// In unicast mode this is the address of the command socket.
+    pub fn data_addr(&self) -> Result<std_net::SocketAddr> {
+        match self.requester.conn.data_addr {
+            Some(addr) => Ok(addr),
+            None => self.command_addr(),
+        }
+    }
+
+    /// Local address of the command socket
+    pub fn command_addr(&self) -> Result<std_net::SocketAddr> {
+        Ok(try!(self.requester.conn.socket.local_addr()))
+    }
+}
+
+impl Stream for AsyncClient {
+    type Item = Result<FrameOfData>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<FrameOfData>>> {
+        let mut shared = lock(&self.requester.conn.shared);
+        match shared.frames.pop_front() {
+            Some(frame) => Poll::Ready(Some(frame)),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Blocking UDP client for `NatNet` servers
+//!
+//! A `NatNet` server, such as Motive, answers requests on its command port
+//! (`1510` by default) and sends frames of data to its data port (`1511` by
+//! default), usually over multicast.
+//!
+//! In unicast mode the client instead connects to the server, which then
+//! sends frames of data directly to the command socket of the client for as
+//! long as the client sends keep-alive messages. The client takes care of
+//! this, so unicast and multicast clients are used in the same way.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::client::{Client, ClientConfig};
+//!
+//! let config = ClientConfig {
+//!     server: "192.168.1.10".parse().unwrap(),
+//!     ..ClientConfig::default()
+//! };
+//! let mut client = try!(Client::connect(config));
+//! println!("Connected to {:?} with models {:?}", client.sender(), client.models());
+//! loop {
+//!     let frame = try!(client.recv_frame());
+//!     // Do stuff with frame
+//! }
+//! ```
+
+use model::DataSet;
+use std::cmp;
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use super::{AutoNatNet, Command, CommandResponse, ConnectOptions, DataType, FrameOfData, NatNet,
+            NatNetRequest, NatNetResponse, ParseError, Result, Sender, ServerDescription};
+
+/// Default port of the command socket of a `NatNet` server
+pub const COMMAND_PORT: u16 = 1510;
+/// Default port frames of data are sent to
+pub const DATA_PORT: u16 = 1511;
+/// Default multicast group frames of data are sent to
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+
+// Largest possible `NatNet` message, header included
+pub(crate) const MAX_MESSAGE: usize = 4 + 65535;
+
+/// Configuration of a `Client`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientConfig {
+    /// Address of the `NatNet` server
+    pub server: IpAddr,
+    /// Command port of the server
+    pub command_port: u16,
+    /// Local port to receive frames of data on, `0` for any free port
+    ///
+    /// Not used in unicast mode.
+    pub data_port: u16,
+    /// Multicast group to join for frames of data, `None` if the server
+    /// sends frames of data directly to the client
+    ///
+    /// Not used in unicast mode.
+    pub multicast_group: Option<Ipv4Addr>,
+    /// Local interface to receive data on
+    pub interface: Ipv4Addr,
+    /// How long to wait for the server to answer a request
+    pub timeout: Duration,
+    /// How long to wait for a frame of data, `None` to wait forever
+    pub frame_timeout: Option<Duration>,
+    /// Name the client identifies itself with
+    pub name: String,
+    /// Connect to the server in unicast mode
+    pub unicast: bool,
+    /// Interval between keep-alive messages in unicast mode
+    ///
+    /// Keep-alive messages are sent from a thread of their own, so the
+    /// server keeps the client even when frames are not received for a
+    /// while.
+    pub keep_alive: Duration,
+    /// Data to subscribe to in unicast mode, empty to receive all data
+    ///
+    /// See `Command::SubscribeToData`.
+    pub subscriptions: Vec<(DataType, String)>,
+    /// Reconnect when nothing has been received from the server for this
+    /// long in unicast mode, `None` to never reconnect
+    ///
+    /// Not used in multicast mode, where a quiet server is no different from
+    /// a server which is not sending any data.
+    pub server_timeout: Option<Duration>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            server: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            command_port: COMMAND_PORT,
+            data_port: DATA_PORT,
+            multicast_group: Some(MULTICAST_GROUP),
+            interface: Ipv4Addr::new(0, 0, 0, 0),
+            timeout: Duration::from_secs(1),
+            frame_timeout: None,
+            name: "NatNetLib".to_string(),
+            unicast: false,
+            keep_alive: Duration::from_secs(1),
+            subscriptions: Vec::new(),
+            server_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+/// Blocking `NatNet` client
+///
+/// Connecting pings the server to find its `NatNet` version, which is then
+/// used to decode everything else, and fetches the model definitions. In
+/// unicast mode a connect request is sent instead of the ping, followed by
+/// the subscriptions.
+///
+/// In unicast mode keep-alive messages are sent in the background for as long
+/// as the client lives. If the server disconnects the client, or in unicast
+/// mode nothing is received from the server within the configured server
+/// timeout, the client connects again while waiting for frames of data with
+/// `recv_frame`.
+#[derive(Debug)]
+pub struct Client {
+    config: ClientConfig,
+    parser: NatNet,
+    command: UdpSocket,
+    data: Option<UdpSocket>,
+    sender: Sender,
+    description: Option<ServerDescription>,
+    models: Vec<DataSet>,
+    buf: Vec<u8>,
+    // Size of the last packet received by `recv_frame`
+    received: usize,
+    keep_alive: Option<KeepAlive>,
+    last_message: Instant,
+}
+
+/// Thread sending keep-alive messages in unicast mode
+#[derive(Debug)]
+struct KeepAlive {
+    // Nothing is ever sent, dropping the sender stops the thread
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Client {
+    /// Connect to the server given in `config`
+    pub fn connect(config: ClientConfig) -> Result<Client> {
+        let command = try!(UdpSocket::bind((config.interface, 0)));
+        try!(command.connect((config.server, config.command_port)));
+        let data = if config.unicast {
+            None
+        } else {
+            let data = try!(UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), config.data_port)));
+            if let Some(ref group) = config.multicast_group {
+                try!(data.join_multicast_v4(group, &config.interface));
+            }
+            Some(data)
+        };
+        let mut buf = vec![0; MAX_MESSAGE];
+        let (sender, description) = try!(Client::handshake(&config, &command, &mut buf));
+        let mut client = Client {
+            parser: NatNet::new(sender.natnet_version.clone()),
+            config: config,
+            command: command,
+            data: data,
+            sender: sender,
+            description: description,
+            models: Vec::new(),
+            buf: buf,
+            received: 0,
+            keep_alive: None,
+            last_message: Instant::now(),
+        };
+        try!(client.setup());
+        if client.config.unicast {
+            client.keep_alive = Some(try!(KeepAlive::start(&client.command,
+                                                           client.config.keep_alive)));
+        }
+        Ok(client)
+    }
+
+    /// Ping, or connect to, the server and wait for its reply
+    fn handshake(config: &ClientConfig,
+                 command: &UdpSocket,
+                 buf: &mut [u8])
+                 -> Result<(Sender, Option<ServerDescription>)> {
+        try!(command.send(&try!(handshake_request(config))));
+        // The reply is the same for all versions
+        let mut parser = AutoNatNet::new();
+        let (sender, description) = try!(wait_for(command, config.timeout, buf, |bytes| {
+            match try!(parser.unpack(bytes)) {
+                NatNetResponse::ServerInfo(desc) => Ok((desc.sender.clone(), Some(desc))),
+                NatNetResponse::Ping(sender) => Ok((sender, None)),
+                _ => Err(ParseError::UnexpectedResponse),
+            }
+        }));
+        debug!("Connected to {:?} using NatNet version {}",
+               sender.name,
+               sender.natnet_version);
+        Ok((sender, description))
+    }
+
+    /// Subscribe to data and fetch model definitions after a handshake
+    fn setup(&mut self) -> Result<()> {
+        if self.config.unicast {
+            for &(data_type, ref name) in self.config.subscriptions.clone().iter() {
+                let cmd = Command::SubscribeToData {
+                    data_type: data_type,
+                    name: name.clone(),
+                };
+                match try!(self.request(cmd)) {
+                    CommandResponse::Success => {}
+                    res => warn!("Subscription to {} {:?} failed: {:?}", data_type, name, res),
+                }
+            }
+        }
+        try!(self.request_models());
+        Ok(())
+    }
+
+    /// Connect to the server again
+    ///
+    /// This is done automatically by `recv_frame` when the server is lost.
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.received = 0;
+        let (sender, description) =
+            try!(Client::handshake(&self.config, &self.command, &mut self.buf));
+        self.parser = NatNet::new(sender.natnet_version.clone());
+        self.sender = sender;
+        self.description = description;
+        self.last_message = Instant::now();
+        self.setup()
+    }
+
+    /// The parser used for messages from the server
+    pub fn parser(&self) -> &NatNet {
+        &self.parser
+    }
+
+    /// The server application
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Description of the server, only sent by servers using `NatNet` 3.0
+    /// and later
+    pub fn server_description(&self) -> Option<&ServerDescription> {
+        self.description.as_ref()
+    }
+
+    /// The most recent model definitions from the server
+    pub fn models(&self) -> &[DataSet] {
+        &self.models
+    }
+
+    /// Raw bytes of the frame of data last returned by `recv_frame`
+    ///
+    /// Empty until the first frame of data has been received, and after
+    /// requests to the server since, which reuse the receive buffer.
+    pub fn last_packet(&self) -> &[u8] {
+        &self.buf[..self.received]
+    }
+
+    /// Local address frames of data are received on
+    ///
+    /// In unicast mode this is the address of the command socket.
+    pub fn data_addr(&self) -> Result<SocketAddr> {
+        Ok(try!(self.frame_socket().local_addr()))
+    }
+
+    /// Local address of the command socket
+    pub fn command_addr(&self) -> Result<SocketAddr> {
+        Ok(try!(self.command.local_addr()))
+    }
+
+    /// Send a request to the server without waiting for a reply
+    pub fn send(&self, request: NatNetRequest) -> Result<()> {
+        let bytes: Vec<u8> = request.into();
+        try!(self.command.send(&bytes));
+        Ok(())
+    }
+
+    /// Fetch the model definitions from the server
+    pub fn request_models(&mut self) -> Result<&[DataSet]> {
+        try!(self.send(NatNetRequest::ModelDefinitions));
+        let reply = try!(self.wait_for_reply(|msg| match *msg {
+            NatNetResponse::ModelDef(_) => true,
+            _ => false,
+        }));
+        if let NatNetResponse::ModelDef(models) = reply {
+            self.models = models;
+        }
+        Ok(&self.models)
+    }
+
+    /// Send a command to the server and wait for the response
+    ///
+    /// In unicast mode frames of data received while waiting are dropped.
+    pub fn request(&mut self, cmd: Command) -> Result<CommandResponse> {
+        try!(self.send(NatNetRequest::Command(cmd.clone())));
+        let reply = try!(self.wait_for_reply(|msg| match *msg {
+            NatNetResponse::Response(_) |
+            NatNetResponse::ResponseString(_) |
+            NatNetResponse::UnrecognizedRequest => true,
+            _ => false,
+        }));
+        cmd.decode_response(&reply)
+    }
+
+    /// Wait for a reply on the command socket accepted by `f`
+    ///
+    /// Other messages are logged and dropped.
+    fn wait_for_reply<F>(&mut self, f: F) -> Result<NatNetResponse>
+        where F: Fn(&NatNetResponse) -> bool
+    {
+        self.received = 0;
+        let reply = {
+            let parser = &self.parser;
+            try!(wait_for(&self.command,
+                          self.config.timeout,
+                          &mut self.buf,
+                          |bytes| match parser.unpack(bytes) {
+                              Ok(ref msg) if !f(msg) => Err(ParseError::UnexpectedResponse),
+                              res => res,
+                          }))
+        };
+        self.last_message = Instant::now();
+        Ok(reply)
+    }
+
+    /// Socket frames of data are received on
+    fn frame_socket(&self) -> &UdpSocket {
+        match self.data {
+            Some(ref data) => data,
+            None => &self.command,
+        }
+    }
+
+    /// Receive the next frame of data
+    ///
+    /// Model definitions received with the frames replace the current ones,
+    /// other messages are dropped.
+    pub fn recv_frame(&mut self) -> Result<FrameOfData> {
+        let deadline = self.config.frame_timeout.map(|t| Instant::now() + t);
+        let server_timeout = if self.config.unicast {
+            self.config.server_timeout
+        } else {
+            None
+        };
+        loop {
+            let mut wait = None;
+            let now = Instant::now();
+            if let Some(timeout) = server_timeout {
+                let lost = self.last_message + timeout;
+                if now >= lost {
+                    warn!("Nothing received from server in {:?}, reconnecting", timeout);
+                    try!(self.reconnect());
+                    continue;
+                }
+                wait = Some(shortest(wait, lost - now));
+            }
+            if let Some(deadline) = deadline {
+                if now >= deadline {
+                    return Err(timed_out("No frame of data from NatNet server"));
+                }
+                wait = Some(shortest(wait, deadline - now));
+            }
+            let received = {
+                let socket = match self.data {
+                    Some(ref data) => data,
+                    None => &self.command,
+                };
+                try!(socket.set_read_timeout(wait));
+                socket.recv(&mut self.buf)
+            };
+            let len = match received {
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(From::from(e)),
+            };
+            self.last_message = Instant::now();
+            match try!(self.parser.unpack(&mut &self.buf[..len])) {
+                NatNetResponse::FrameOfData(frame) => {
+                    self.received = len;
+                    return Ok(frame);
+                }
+                NatNetResponse::ModelDef(models) => self.models = models,
+                NatNetResponse::Disconnect |
+                NatNetResponse::DisconnectByTimeout => {
+                    warn!("Disconnected by server, reconnecting");
+                    try!(self.reconnect());
+                }
+                msg => debug!("Dropping message while waiting for frame: {:?}", msg),
+            }
+        }
+    }
+}
+
+impl KeepAlive {
+    /// Send keep-alive messages on `socket` every `interval`
+    fn start(socket: &UdpSocket, interval: Duration) -> Result<KeepAlive> {
+        let socket = try!(socket.try_clone());
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let bytes: Vec<u8> = NatNetRequest::KeepAlive.into();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                trace!("Sending keep-alive");
+                if let Err(err) = socket.send(&bytes) {
+                    warn!("Could not send keep-alive: {}", err);
+                }
+            }
+        });
+        Ok(KeepAlive {
+            stop: stop,
+            thread: thread,
+        })
+    }
+}
+
+impl Drop for Client {
+    /// Disconnect from the server in unicast mode
+    fn drop(&mut self) {
+        // Stop first so that no keep-alive follows the disconnect message
+        if let Some(keep_alive) = self.keep_alive.take() {
+            drop(keep_alive.stop);
+            let _ = keep_alive.thread.join();
+        }
+        if self.config.unicast {
+            let _ = self.send(NatNetRequest::Disconnect);
+        }
+    }
+}
+
+/// The shortest of `a` and `b`, never zero since that is not a valid timeout
+fn shortest(a: Option<Duration>, b: Duration) -> Duration {
+    let min = a.map_or(b, |a| cmp::min(a, b));
+    cmp::max(min, Duration::from_millis(1))
+}
+
+/// Ping, or in unicast mode connect request, sent to start a session
+pub(crate) fn handshake_request(config: &ClientConfig) -> Result<Vec<u8>> {
+    let name = try!(CString::new(config.name.clone()));
+    let request = if config.unicast {
+        NatNetRequest::Connect(ConnectOptions {
+            name: name,
+            subscribed_data_only: !config.subscriptions.is_empty(),
+            bitstream_version: None,
+        })
+    } else {
+        NatNetRequest::Ping(name)
+    };
+    Ok(request.into())
+}
+
+fn timed_out(msg: &str) -> ParseError {
+    ParseError::IO(io::Error::new(io::ErrorKind::TimedOut, msg))
+}
+
+/// Receive datagrams from `socket` until `f` accepts one or `timeout` runs out
+///
+/// Datagrams which can not be decoded are logged and dropped.
+fn wait_for<T, F>(socket: &UdpSocket, timeout: Duration, buf: &mut [u8], mut f: F) -> Result<T>
+    where F: FnMut(&mut &[u8]) -> Result<T>
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(timed_out("No reply from NatNet server"));
+        }
+        try!(socket.set_read_timeout(Some(shortest(None, deadline - now))));
+        let len = match socket.recv(buf) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(From::from(e)),
+        };
+        match f(&mut &buf[..len]) {
+            Ok(msg) => return Ok(msg),
+            Err(err) => debug!("Dropping message on command socket: {}", err),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use byteorder::{WriteBytesExt, LittleEndian};
+use messages::NatNetResponse;
+use std::fmt;
+use std::str;
+use super::{NatNetMsgType, ParseError, Result};
+
+/// Commands accepted by Motive
+///
+/// Commands are sent as strings in a `NatNetMsgType::Request` message, use
+/// `NatNetRequest::Command` to encode a command. The response to a command
+/// can be decoded with `Command::decode_response`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Start recording a take
+    StartRecording,
+    /// Stop recording
+    StopRecording,
+    /// Set the name of the next take to record
+    SetRecordTakeName(String),
+    /// Switch to live mode
+    LiveMode,
+    /// Switch to edit mode
+    EditMode,
+    /// Start playback of the timeline in edit mode
+    TimelinePlay,
+    /// Stop playback of the timeline in edit mode
+    TimelineStop,
+    /// Set the frame at which playback of the timeline starts
+    SetPlaybackStartFrame(i32),
+    /// Set a property of a node in Motive
+    SetProperty {
+        /// Name of the node, empty for the application itself
+        node: String,
+        /// Name of the property
+        name: String,
+        /// New value of the property
+        value: String,
+    },
+    /// Get a property of a node in Motive
+    GetProperty {
+        /// Name of the node, empty for the application itself
+        node: String,
+        /// Name of the property
+        name: String,
+    },
+    /// Limit frames of data sent to a unicast client to the named data
+    ///
+    /// Only supported by servers using `NatNet` 4.0 and later. The name
+    /// `"All"` subscribes to all data of the given type.
+    SubscribeToData {
+        /// Type of data to subscribe to
+        data_type: DataType,
+        /// Name of the rigid body, skeleton or other data
+        name: String,
+    },
+}
+
+/// Type of data in a `Command::SubscribeToData`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    /// Marker sets
+    MarkerSet,
+    /// Rigid bodies
+    RigidBody,
+    /// Skeletons
+    Skeleton,
+    /// Force plates
+    ForcePlate,
+    /// Devices
+    Device,
+    /// All types of data
+    AllTypes,
+}
+
+/// Typed response to a `Command`
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandResponse {
+    /// The command succeeded
+    Success,
+    /// The command failed with the given result code
+    Failure(i32),
+    /// Value of a property requested with `Command::GetProperty`
+    Property(String),
+    /// The command was not recognized by Motive
+    Unrecognized,
+}
+
+impl Command {
+    /// Encode the command as a complete `NatNet` message, including header
+    ///
+    /// The command string is cut at the first null byte, since Motive would
+    /// not read past it anyway.
+    pub fn encode(&self) -> Vec<u8> {
+        let cmd = self.to_string();
+        let cmd = cmd.as_bytes();
+        let len = cmd.iter().position(|b| *b == 0).unwrap_or(cmd.len());
+        // The size of the message must fit the header, including null byte
+        let len = ::std::cmp::min(len, u16::max_value() as usize - 1);
+        let mut bytes = Vec::with_capacity(len + 5);
+        bytes.write_u16::<LittleEndian>(NatNetMsgType::Request as u16).unwrap();
+        bytes.write_u16::<LittleEndian>(len as u16 + 1).unwrap();
+        bytes.extend_from_slice(&cmd[..len]);
+        bytes.push(b'\0');
+        bytes
+    }
+
+    /// Decode the response from Motive to this command
+    ///
+    /// Commands answer with a result code where `0` means success, except
+    /// for `Command::GetProperty` which answers with the value of the
+    /// property. Any other response results in
+    /// `ParseError::UnexpectedResponse`.
+    pub fn decode_response(&self, response: &NatNetResponse) -> Result<CommandResponse> {
+        match (self, response) {
+            (_, &NatNetResponse::UnrecognizedRequest) => Ok(CommandResponse::Unrecognized),
+            (&Command::GetProperty { .. }, &NatNetResponse::ResponseString(ref value)) => {
+                Ok(CommandResponse::Property(value.clone()))
+            }
+            (&Command::GetProperty { .. }, &NatNetResponse::Response(code)) => {
+                // A value of three characters has the same size as a result
+                // code and is unpacked as one, see `NatNet::pack_with`
+                let mut raw = Vec::with_capacity(4);
+                raw.write_i32::<LittleEndian>(code).unwrap();
+                if raw[3] == 0 {
+                    if let Ok(value) = str::from_utf8(&raw[..3]) {
+                        if !value.contains('\0') {
+                            return Ok(CommandResponse::Property(value.to_string()));
+                        }
+                    }
+                }
+                Err(ParseError::UnexpectedResponse)
+            }
+            (_, &NatNetResponse::Response(0)) => Ok(CommandResponse::Success),
+            (_, &NatNetResponse::Response(code)) => Ok(CommandResponse::Failure(code)),
+            _ => Err(ParseError::UnexpectedResponse),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    /// Format the command as the string sent to Motive
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::StartRecording => write!(f, "StartRecording"),
+            Command::StopRecording => write!(f, "StopRecording"),
+            Command::SetRecordTakeName(ref name) => write!(f, "SetRecordTakeName,{}", name),
+            Command::LiveMode => write!(f, "LiveMode"),
+            Command::EditMode => write!(f, "EditMode"),
+            Command::TimelinePlay => write!(f, "TimelinePlay"),
+            Command::TimelineStop => write!(f, "TimelineStop"),
+            Command::SetPlaybackStartFrame(frame) => write!(f, "SetPlaybackStartFrame,{}", frame),
+            Command::SetProperty { ref node, ref name, ref value } => {
+                write!(f, "SetProperty,{},{},{}", node, name, value)
+            }
+            Command::GetProperty { ref node, ref name } => {
+                write!(f, "GetProperty,{},{}", node, name)
+            }
+            Command::SubscribeToData { data_type, ref name } => {
+                write!(f, "SubscribeToData,{},{}", data_type, name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    /// Format the data type as used in commands
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DataType::MarkerSet => write!(f, "MarkerSetMarkers"),
+            DataType::RigidBody => write!(f, "RigidBody"),
+            DataType::Skeleton => write!(f, "Skeleton"),
+            DataType::ForcePlate => write!(f, "ForcePlate"),
+            DataType::Device => write!(f, "Device"),
+            DataType::AllTypes => write!(f, "AllTypes"),
+        }
+    }
+}
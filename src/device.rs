@@ -0,0 +1,51 @@
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use model;
+use semver::Version;
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, pack_channels, unpack_channels};
+
+/// Peripheral device, such as EMG or analog input
+///
+/// # `NatNet` version
+/// This structure is new in 2.11
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    /// ID of device
+    pub id: i32,
+    /// Channels from device, each with one or more samples
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Device {
+    /// Find the description of this device in a list of model definitions
+    ///
+    /// The description is matched by `id`.
+    pub fn description<'a>(&self, models: &'a [model::DataSet]) -> Option<&'a model::Device> {
+        models.iter()
+            .filter_map(|m| match *m {
+                model::DataSet::Device(ref device) => Some(device),
+                _ => None,
+            })
+            .find(|device| device.id == self.id)
+    }
+}
+
+impl Unpack<Device> for Device {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Device> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let chans = try!(unpack_channels(bytes, "max_device_samples", limits.max_device_samples)
+            .field("channels"));
+        Ok(Device {
+            id: id,
+            channels: chans,
+        })
+    }
+}
+
+impl Pack for Device {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(pack_channels(&self.channels, bytes));
+        Ok(())
+    }
+}
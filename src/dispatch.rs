@@ -0,0 +1,88 @@
+use byteorder::{ReadBytesExt, LittleEndian};
+use std::io::{self, BufRead, Read};
+use super::{NatNet, NatNetMsgType, NatNetResponse, ParseError, Result};
+
+/// Decode only the `NatNet` messages a caller is interested in
+///
+/// Messages of types which are not subscribed to, including message types
+/// unknown to this crate, are skipped using the size in their header without
+/// being decoded. The source is always left at the start of the next message,
+/// also when a subscribed message fails to decode.
+///
+/// # Example
+/// ```rust,ignore
+/// use natnet_decode::{Dispatcher, NatNet, NatNetMsgType};
+///
+/// let frames = Dispatcher::new(NatNet::new(ver)).subscribe(NatNetMsgType::FrameOfData);
+/// while let Some(msg) = try!(frames.dispatch(&mut source)) {
+///     // Only frames of data end up here
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Dispatcher {
+    parser: NatNet,
+    subscribed: Vec<NatNetMsgType>,
+}
+
+impl Dispatcher {
+    /// Create a new dispatcher without any subscriptions
+    ///
+    /// Subscribed messages are decoded with the given parser.
+    pub fn new(parser: NatNet) -> Dispatcher {
+        Dispatcher {
+            parser: parser,
+            subscribed: Vec::new(),
+        }
+    }
+
+    /// Subscribe to messages of type `t`
+    pub fn subscribe(mut self, t: NatNetMsgType) -> Dispatcher {
+        if !self.subscribed.contains(&t) {
+            self.subscribed.push(t);
+        }
+        self
+    }
+
+    /// Stop decoding messages of type `t`
+    pub fn unsubscribe(mut self, t: NatNetMsgType) -> Dispatcher {
+        self.subscribed.retain(|s| *s != t);
+        self
+    }
+
+    /// Is the dispatcher subscribed to messages of type `t`?
+    pub fn is_subscribed(&self, t: NatNetMsgType) -> bool {
+        self.subscribed.contains(&t)
+    }
+
+    /// The parser used for subscribed messages
+    pub fn parser(&self) -> &NatNet {
+        &self.parser
+    }
+
+    /// Read the next message from `bytes`
+    ///
+    /// Returns `Ok(None)` if the message was skipped. After an error decoding a
+    /// subscribed message the next message can still be dispatched.
+    pub fn dispatch<B: BufRead>(&self, bytes: &mut B) -> Result<Option<NatNetResponse>> {
+        let msg_id = try!(bytes.read_u16::<LittleEndian>());
+        let num_bytes = try!(bytes.read_u16::<LittleEndian>());
+        let subscribed = self.subscribed.iter().any(|t| *t as u16 == msg_id);
+        if subscribed {
+            let parser = &self.parser;
+            NatNet::unpack_rest(msg_id,
+                                num_bytes,
+                                &parser.ver,
+                                &parser.limits,
+                                parser.check,
+                                bytes)
+                .map(Some)
+        } else {
+            trace!("Skipping message with type: {}, size: {}", msg_id, num_bytes);
+            let skipped = try!(io::copy(&mut bytes.take(num_bytes as u64), &mut io::sink()));
+            if skipped < num_bytes as u64 {
+                return Err(ParseError::NotEnoughBytes);
+            }
+            Ok(None)
+        }
+    }
+}
@@ -1,7 +1,8 @@
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use model;
 use semver::Version;
-use std::io::BufRead;
-use super::{Result, Unpack};
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, pack_channels, unpack_channels};
 
 /// Force plate
 ///
@@ -15,22 +16,38 @@ pub struct ForcePlate {
     pub channels: Vec<Vec<f32>>,
 }
 
+impl ForcePlate {
+    /// Find the description of this plate in a list of model definitions
+    ///
+    /// The description is matched by `id`.
+    pub fn description<'a>(&self, models: &'a [model::DataSet]) -> Option<&'a model::ForcePlate> {
+        models.iter()
+            .filter_map(|m| match *m {
+                model::DataSet::ForcePlate(ref plate) => Some(plate),
+                _ => None,
+            })
+            .find(|plate| plate.id == self.id)
+    }
+}
+
 impl Unpack<ForcePlate> for ForcePlate {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<ForcePlate> {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<ForcePlate> {
         let id = try!(bytes.read_i32::<LittleEndian>());
-        let num_channels = try!(bytes.read_i32::<LittleEndian>());
-        let mut chans = Vec::with_capacity(num_channels as usize);
-        for _ in 0..num_channels {
-            let num_frames = try!(bytes.read_i32::<LittleEndian>());
-            let mut frame = Vec::with_capacity(num_frames as usize);
-            for _ in 0..num_frames {
-                frame.push(try!(bytes.read_f32::<LittleEndian>()));
-            }
-            chans.push(frame);
-        }
+        let chans = try!(unpack_channels(bytes,
+                                         "max_force_plate_samples",
+                                         limits.max_force_plate_samples)
+            .field("channels"));
         Ok(ForcePlate {
             id: id,
             channels: chans,
         })
     }
 }
+
+impl Pack for ForcePlate {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(pack_channels(&self.channels, bytes));
+        Ok(())
+    }
+}
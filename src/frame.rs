@@ -1,12 +1,15 @@
-use byteorder::{ReadBytesExt, LittleEndian};
+use asset::Asset;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use device::Device;
 use force_plate::ForcePlate;
 use marker::{Marker, LabeledMarker};
 use rigid_body::RigidBody;
 use semver::Version;
 use skeleton::Skeleton;
 use std::collections::BTreeMap;
-use std::io::BufRead;
-use super::{Result, Unpack, ParseError, read_cstring};
+use std::io::{self, BufRead, Read, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, ParseError, capacity,
+            read_count, read_cstring, write_cstring};
 
 /// Frame of Data
 ///
@@ -23,69 +26,240 @@ pub struct FrameOfData {
     pub rigid_bodies: Vec<RigidBody>,
     /// List of skeletons
     pub skeletons: Vec<Skeleton>,
+    /// List of assets (NatNet >= 4.1)
+    pub assets: Option<Vec<Asset>>,
     /// List of labeled markers
     pub labeled_markers: Vec<LabeledMarker>,
     /// List of Force plate data (NatNet >= 2.9)
     pub force_plates: Option<Vec<ForcePlate>>,
-    pub latency: f32,
+    /// List of device data (NatNet >= 2.11)
+    pub devices: Option<Vec<Device>>,
+    /// Software latency (NatNet < 3.0)
+    pub latency: Option<f32>,
     pub timecode: (u32, u32),
     /// Time stamp of data (NatNet >= 2.6)
     pub timestamp: Option<f64>,
+    /// High resolution time stamp of camera mid exposure (NatNet >= 3.0)
+    pub stamp_camera_mid_exposure: Option<u64>,
+    /// High resolution time stamp of when data was received (NatNet >= 3.0)
+    pub stamp_data_received: Option<u64>,
+    /// High resolution time stamp of when data was transmitted (NatNet >= 3.0)
+    pub stamp_transmit: Option<u64>,
+    /// Precision time stamp as seconds and fractional seconds (NatNet >= 4.1)
+    pub precision_timestamp: Option<(u32, u32)>,
     /// Is Motive recording data? (NatNet >= 2.6)
     pub is_recording: Option<bool>,
     /// Has the list of actively tracked models changed? (NatNet >= 2.6)
     pub tracked_models_changed: Option<bool>,
 }
 
-fn unpack_vec<R, T: Unpack<R>, B: BufRead>(ver: &Version, bytes: &mut B) -> Result<Vec<R>> {
-    let num = try!(bytes.read_i32::<LittleEndian>());
-    trace!("Unpacking vector of length {}", num);
-    let mut result = Vec::with_capacity(num as usize);
-    for _ in 0..num {
-        result.push(try!(T::unpack(ver, bytes)));
+/// Helper function to unpack a data block with `f`
+///
+/// `f` is given the number of items in the block, checked against `max`
+/// named `name` in `DecodeLimits`, and a source limited to the block. In
+/// version 4.1 and later the number of items is followed by the size of the
+/// block in bytes, which the items must fill exactly.
+fn unpack_block<B, T, F>(ver: &Version,
+                         limits: &DecodeLimits,
+                         name: &'static str,
+                         max: usize,
+                         bytes: &mut B,
+                         f: F)
+                         -> Result<T>
+    where B: BufRead,
+          F: FnOnce(i32, &mut io::Take<&mut B>) -> Result<T>
+{
+    let num = try!(read_count(bytes, name, max));
+    if *ver < Version::parse("4.1.0").unwrap() {
+        return f(num, &mut bytes.take(u64::max_value()));
+    }
+    let size = try!(read_count(bytes, "max_message_size", limits.max_message_size)) as usize;
+    trace!("Data block of {} bytes", size);
+    let mut block = bytes.take(size as u64);
+    let res = try!(f(num, &mut block));
+    if block.limit() > 0 {
+        debug!("Data block has {} trailing bytes", block.limit());
+        return Err(ParseError::BlockSizeMismatch {
+            size: size,
+            used: size - block.limit() as usize,
+        });
     }
-    Ok(result)
+    Ok(res)
+}
+
+/// Helper function to pack a data block of `num` items
+///
+/// In version 4.1 and later the block must be prefixed by its size in bytes
+/// so items are packed into a temporary buffer first
+fn pack_block<W, F>(ver: &Version, num: usize, bytes: &mut W, f: F) -> Result<()>
+    where W: Write,
+          F: FnOnce(&mut Vec<u8>) -> Result<()>
+{
+    let mut block = Vec::new();
+    try!(f(&mut block));
+    try!(bytes.write_i32::<LittleEndian>(num as i32));
+    if *ver >= Version::parse("4.1.0").unwrap() {
+        try!(bytes.write_i32::<LittleEndian>(block.len() as i32));
+    }
+    try!(bytes.write_all(&block));
+    Ok(())
+}
+
+/// Helper function to unpack the data block `field` of a frame
+fn unpack_vec<R, T: Unpack<R>, B: BufRead>(ver: &Version,
+                                           limits: &DecodeLimits,
+                                           field: &str,
+                                           name: &'static str,
+                                           max: usize,
+                                           bytes: &mut B)
+                                           -> Result<Vec<R>> {
+    unpack_block(ver, limits, name, max, bytes, |num, block| {
+            trace!("Unpacking vector of length {}", num);
+            let mut result = Vec::with_capacity(capacity(num));
+            for i in 0..num as usize {
+                result.push(try!(T::unpack(ver, limits, block).index(i)));
+            }
+            Ok(result)
+        })
+        .field(field)
+}
+
+fn pack_vec<T: Pack, W: Write>(ver: &Version, data: &[T], bytes: &mut W) -> Result<()> {
+    pack_block(ver, data.len(), bytes, |block| {
+        for d in data {
+            try!(d.pack(ver, block));
+        }
+        Ok(())
+    })
+}
+
+/// Helper function to unpack a named marker set
+fn unpack_marker_set<B: BufRead>(ver: &Version,
+                                 limits: &DecodeLimits,
+                                 bytes: &mut B)
+                                 -> Result<(String, Vec<Marker>)> {
+    let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+    let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers).field("markers"));
+    let mut markers = Vec::with_capacity(capacity(num_markers));
+    for i in 0..num_markers as usize {
+        markers.push(try!(Marker::unpack(ver, limits, bytes).index(i).field("markers")));
+    }
+    Ok((name, markers))
 }
 
 impl Unpack<FrameOfData> for FrameOfData {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<FrameOfData> {
+    fn unpack<B: BufRead>(ver: &Version,
+                          limits: &DecodeLimits,
+                          bytes: &mut B)
+                          -> Result<FrameOfData> {
         debug!("Unpacking frame of data");
         // Unpack Frame of Data, ref: line 618
-        let frame_num = try!(bytes.read_i32::<LittleEndian>());
+        let frame_num = try!(bytes.read_i32::<LittleEndian>().field("frame_number"));
         trace!("Frame number: {}", frame_num);
         // Read marker sets, line 625:648
-        let num_marker_sets = try!(bytes.read_i32::<LittleEndian>());
-        trace!("Number of marker sets: {}", num_marker_sets);
-        let mut sets = BTreeMap::new();
-        for _ in 0..num_marker_sets {
-            let name = try!(read_cstring(bytes));
-            let num_markers = try!(bytes.read_i32::<LittleEndian>());
-            let mut markers = Vec::with_capacity(num_markers as usize);
-            for _ in 0..num_markers {
-                markers.push(try!(Marker::unpack(ver, bytes)));
-            }
-            sets.insert(name, markers);
-        }
-        let others = try!(unpack_vec::<Marker, Marker, _>(ver, bytes));
-        let bodies = try!(unpack_vec::<RigidBody, RigidBody, _>(ver, bytes));
-        let skels = try!(unpack_vec::<Skeleton, Skeleton, _>(ver, bytes));
-        let labeled = try!(unpack_vec::<LabeledMarker, LabeledMarker, _>(ver, bytes));
+        let sets = try!(unpack_block(ver,
+                                     limits,
+                                     "max_marker_sets",
+                                     limits.max_marker_sets,
+                                     bytes,
+                                     |num, block| {
+                trace!("Number of marker sets: {}", num);
+                let mut sets = BTreeMap::new();
+                for i in 0..num as usize {
+                    let (name, markers) = try!(unpack_marker_set(ver, limits, block).index(i));
+                    sets.insert(name, markers);
+                }
+                Ok(sets)
+            })
+            .field("marker_sets"));
+        let others = try!(unpack_vec::<Marker, Marker, _>(ver,
+                                                          limits,
+                                                          "other_markers",
+                                                          "max_markers",
+                                                          limits.max_markers,
+                                                          bytes));
+        let bodies = try!(unpack_vec::<RigidBody, RigidBody, _>(ver,
+                                                                limits,
+                                                                "rigid_bodies",
+                                                                "max_bodies",
+                                                                limits.max_bodies,
+                                                                bytes));
+        let skels = try!(unpack_vec::<Skeleton, Skeleton, _>(ver,
+                                                             limits,
+                                                             "skeletons",
+                                                             "max_bodies",
+                                                             limits.max_bodies,
+                                                             bytes));
+        // Assets added in version 4.1
+        let assets = if *ver >= Version::parse("4.1.0").unwrap() {
+            Some(try!(unpack_vec::<Asset, Asset, _>(ver,
+                                                    limits,
+                                                    "assets",
+                                                    "max_bodies",
+                                                    limits.max_bodies,
+                                                    bytes)))
+        } else {
+            None
+        };
+        let labeled = try!(unpack_vec::<LabeledMarker, LabeledMarker, _>(ver,
+                                                                         limits,
+                                                                         "labeled_markers",
+                                                                         "max_markers",
+                                                                         limits.max_markers,
+                                                                         bytes));
         // Force plates added in version 2.9
         let plates = if *ver >= Version::parse("2.9.0").unwrap() {
-            Some(try!(unpack_vec::<ForcePlate, ForcePlate, _>(ver, bytes)))
+            Some(try!(unpack_vec::<ForcePlate, ForcePlate, _>(ver,
+                                                              limits,
+                                                              "force_plates",
+                                                              "max_bodies",
+                                                              limits.max_bodies,
+                                                              bytes)))
+        } else {
+            None
+        };
+        // Devices added in version 2.11
+        let devices = if *ver >= Version::parse("2.11.0").unwrap() {
+            Some(try!(unpack_vec::<Device, Device, _>(ver,
+                                                      limits,
+                                                      "devices",
+                                                      "max_bodies",
+                                                      limits.max_bodies,
+                                                      bytes)))
+        } else {
+            None
+        };
+        // Software latency was removed in version 3.0
+        let latency = if *ver < Version::parse("3.0.0").unwrap() {
+            Some(try!(bytes.read_f32::<LittleEndian>().field("latency")))
         } else {
             None
         };
-        let latency = try!(bytes.read_f32::<LittleEndian>());
-        trace!("Latency: {}", latency);
-        let tc = try!(bytes.read_u32::<LittleEndian>());
-        let tcs = try!(bytes.read_u32::<LittleEndian>());
+        trace!("Latency: {:?}", latency);
+        let tc = try!(bytes.read_u32::<LittleEndian>().field("timecode"));
+        let tcs = try!(bytes.read_u32::<LittleEndian>().field("timecode"));
         trace!("Time code: ({}, {})", tc, tcs);
         // Timestamp changed from f32 to f64 in version >= 2.7
         let ts = if *ver >= Version::parse("2.7.0").unwrap() {
-            Some(try!(bytes.read_f64::<LittleEndian>()))
+            Some(try!(bytes.read_f64::<LittleEndian>().field("timestamp")))
         } else if *ver >= Version::parse("2.6.0").unwrap() {
-            Some(try!(bytes.read_f32::<LittleEndian>()) as f64)
+            Some(try!(bytes.read_f32::<LittleEndian>().field("timestamp")) as f64)
+        } else {
+            None
+        };
+        // High resolution time stamps added in version 3.0
+        let (mid_exp, received, transmit) = if *ver >= Version::parse("3.0.0").unwrap() {
+            (Some(try!(bytes.read_u64::<LittleEndian>().field("stamp_camera_mid_exposure"))),
+             Some(try!(bytes.read_u64::<LittleEndian>().field("stamp_data_received"))),
+             Some(try!(bytes.read_u64::<LittleEndian>().field("stamp_transmit"))))
+        } else {
+            (None, None, None)
+        };
+        // Precision time stamp added in version 4.1
+        let precision_ts = if *ver >= Version::parse("4.1.0").unwrap() {
+            let secs = try!(bytes.read_u32::<LittleEndian>().field("precision_timestamp"));
+            let frac = try!(bytes.read_u32::<LittleEndian>().field("precision_timestamp"));
+            Some((secs, frac))
         } else {
             None
         };
@@ -93,13 +267,13 @@ impl Unpack<FrameOfData> for FrameOfData {
         // are simply extracted, however they seem to have been added
         // between 2.5 and 2.6 and so must be checked
         let (is_rec, tmc) = if *ver >= Version::parse("2.6.0").unwrap() {
-            let params = try!(bytes.read_i16::<LittleEndian>());
+            let params = try!(bytes.read_i16::<LittleEndian>().field("params"));
             (Some(params & 0x01 > 0), Some(params & 0x02 > 0))
         } else {
             (None, None)
         };
         // End of data tag, must be `0` for valid message
-        let eod = try!(bytes.read_i32::<LittleEndian>());
+        let eod = try!(bytes.read_i32::<LittleEndian>().field("end_of_data"));
         if eod == 0 {
             trace!("Parsed complete frame of data");
             Ok(FrameOfData {
@@ -108,17 +282,95 @@ impl Unpack<FrameOfData> for FrameOfData {
                 other_markers: others,
                 rigid_bodies: bodies,
                 skeletons: skels,
+                assets: assets,
                 labeled_markers: labeled,
                 force_plates: plates,
+                devices: devices,
                 latency: latency,
                 timecode: (tc, tcs),
                 timestamp: ts,
+                stamp_camera_mid_exposure: mid_exp,
+                stamp_data_received: received,
+                stamp_transmit: transmit,
+                precision_timestamp: precision_ts,
                 is_recording: is_rec,
                 tracked_models_changed: tmc,
             })
         } else {
             debug!("End of data tag, 0 != {}", eod);
-            Err(ParseError::UnknownError)
+            Err(ParseError::EndOfDataMismatch { found: eod }).field("end_of_data")
+        }
+    }
+}
+
+impl Pack for FrameOfData {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        debug!("Packing frame of data");
+        try!(bytes.write_i32::<LittleEndian>(self.frame_number));
+        try!(pack_block(ver, self.marker_sets.len(), bytes, |block| {
+            for (name, markers) in &self.marker_sets {
+                try!(write_cstring(name, block));
+                try!(block.write_i32::<LittleEndian>(markers.len() as i32));
+                for marker in markers {
+                    try!(marker.pack(ver, block));
+                }
+            }
+            Ok(())
+        }));
+        try!(pack_vec(ver, &self.other_markers, bytes));
+        try!(pack_vec(ver, &self.rigid_bodies, bytes));
+        try!(pack_vec(ver, &self.skeletons, bytes));
+        if *ver >= Version::parse("4.1.0").unwrap() {
+            match self.assets {
+                Some(ref assets) => try!(pack_vec(ver, assets, bytes)),
+                None => try!(pack_vec::<Asset, _>(ver, &[], bytes)),
+            }
+        }
+        try!(pack_vec(ver, &self.labeled_markers, bytes));
+        if *ver >= Version::parse("2.9.0").unwrap() {
+            match self.force_plates {
+                Some(ref plates) => try!(pack_vec(ver, plates, bytes)),
+                None => try!(pack_vec::<ForcePlate, _>(ver, &[], bytes)),
+            }
+        }
+        if *ver >= Version::parse("2.11.0").unwrap() {
+            match self.devices {
+                Some(ref devices) => try!(pack_vec(ver, devices, bytes)),
+                None => try!(pack_vec::<Device, _>(ver, &[], bytes)),
+            }
+        }
+        if *ver < Version::parse("3.0.0").unwrap() {
+            try!(bytes.write_f32::<LittleEndian>(self.latency.unwrap_or(0.0)));
+        }
+        try!(bytes.write_u32::<LittleEndian>(self.timecode.0));
+        try!(bytes.write_u32::<LittleEndian>(self.timecode.1));
+        if *ver >= Version::parse("2.7.0").unwrap() {
+            try!(bytes.write_f64::<LittleEndian>(self.timestamp.unwrap_or(0.0)));
+        } else if *ver >= Version::parse("2.6.0").unwrap() {
+            try!(bytes.write_f32::<LittleEndian>(self.timestamp.unwrap_or(0.0) as f32));
+        }
+        if *ver >= Version::parse("3.0.0").unwrap() {
+            try!(bytes.write_u64::<LittleEndian>(self.stamp_camera_mid_exposure.unwrap_or(0)));
+            try!(bytes.write_u64::<LittleEndian>(self.stamp_data_received.unwrap_or(0)));
+            try!(bytes.write_u64::<LittleEndian>(self.stamp_transmit.unwrap_or(0)));
+        }
+        if *ver >= Version::parse("4.1.0").unwrap() {
+            let (secs, frac) = self.precision_timestamp.unwrap_or((0, 0));
+            try!(bytes.write_u32::<LittleEndian>(secs));
+            try!(bytes.write_u32::<LittleEndian>(frac));
+        }
+        if *ver >= Version::parse("2.6.0").unwrap() {
+            let mut params = 0;
+            if self.is_recording.unwrap_or(false) {
+                params |= 0x01;
+            }
+            if self.tracked_models_changed.unwrap_or(false) {
+                params |= 0x02;
+            }
+            try!(bytes.write_i16::<LittleEndian>(params));
         }
+        // End of data tag
+        try!(bytes.write_i32::<LittleEndian>(0));
+        Ok(())
     }
 }
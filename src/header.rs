@@ -0,0 +1,56 @@
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use semver::Version;
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, NatNetMsgType, Result, Pack, Unpack};
+
+/// Header of a `NatNet` message
+///
+/// Every message starts with the type of the message followed by the number
+/// of bytes in the payload, not counting the header itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageHeader {
+    /// Type of message
+    pub msg_type: NatNetMsgType,
+    /// Number of bytes in the payload following the header
+    pub num_bytes: u16,
+}
+
+impl MessageHeader {
+    /// Total size of the message in bytes, including the 4 byte header
+    pub fn message_len(&self) -> usize {
+        4 + self.num_bytes as usize
+    }
+}
+
+/// Read the header of a message without consuming it
+///
+/// Messages with a type unknown to this crate result in
+/// `ParseError::UnknownResponse`.
+pub fn peek_header(bytes: &[u8]) -> Result<MessageHeader> {
+    let mut bytes = bytes;
+    // The header is the same for all versions
+    MessageHeader::unpack(&Version::parse("0.0.0").unwrap(),
+                          &DecodeLimits::default(),
+                          &mut bytes)
+}
+
+impl Unpack<MessageHeader> for MessageHeader {
+    fn unpack<B: BufRead>(_: &Version, _: &DecodeLimits, bytes: &mut B) -> Result<MessageHeader> {
+        // According to `PacketClient.cpp` line 609:615
+        let msg_id = try!(bytes.read_u16::<LittleEndian>());
+        let num_bytes = try!(bytes.read_u16::<LittleEndian>());
+        Ok(MessageHeader {
+            msg_type: try!(NatNetMsgType::try_from(msg_id)),
+            num_bytes: num_bytes,
+        })
+    }
+}
+
+impl Pack for MessageHeader {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_u16::<LittleEndian>(self.msg_type as u16));
+        try!(bytes.write_u16::<LittleEndian>(self.num_bytes));
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+//! Index of the frames of data in a file for random access
+//!
+//! A `FrameIndex` maps the frame number and timestamp of each frame of data
+//! in a recording, or in a file of `NatNet` messages stored back to back, to
+//! its offset in bytes. An `IndexedReader` uses the index to jump to a frame
+//! without decoding everything before it.
+//!
+//! Indices are stored next to the file they index, with `.idx` appended to
+//! its name. They are only a cache: an index which is missing, corrupt or
+//! made for a file of a different size is rebuilt from the data.
+//!
+//! Frame numbers and timestamps are expected to increase through the file,
+//! as they do within a session of Motive.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::index::IndexedReader;
+//!
+//! let mut reader = try!(IndexedReader::open_recording("take.nnrec"));
+//! reader.seek_time(60.0);
+//! while let Some(frame) = try!(reader.next_frame()) {
+//!     // Frames from one minute into the take
+//! }
+//! ```
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LittleEndian};
+use recording::{FILE_HEADER_SIZE, Record, RecordingReader};
+use std::cmp::{self, Ordering};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use super::{FrameOfData, NatNet, NatNetResponse, ParseError, Result};
+
+const MAGIC: &'static [u8] = b"NNIDX";
+const FORMAT_VERSION: u8 = 1;
+
+/// Layout of an indexed file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexedFormat {
+    /// A recording written by `RecordingWriter`
+    Recording,
+    /// `NatNet` messages stored back to back
+    Messages,
+}
+
+/// Position of a single frame of data in an indexed file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexEntry {
+    /// Frame number of the frame
+    pub frame_number: i32,
+    /// Timestamp of the frame in seconds
+    ///
+    /// Frames without a timestamp, from versions of `NatNet` before 2.6, get
+    /// the timestamp of the frame before them.
+    pub timestamp: f64,
+    /// Offset in bytes of the message, or record, holding the frame
+    pub offset: u64,
+}
+
+/// Index of the frames of data in a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameIndex {
+    format: IndexedFormat,
+    data_len: u64,
+    entries: Vec<IndexEntry>,
+    // Offsets of the sender and model definition records of a recording,
+    // needed to decode the frames following them
+    senders: Vec<u64>,
+    models: Vec<u64>,
+}
+
+impl FrameIndex {
+    fn new(format: IndexedFormat, data_len: u64) -> FrameIndex {
+        FrameIndex {
+            format: format,
+            data_len: data_len,
+            entries: Vec::new(),
+            senders: Vec::new(),
+            models: Vec::new(),
+        }
+    }
+
+    /// Build the index of a recording
+    ///
+    /// Packets which can not be decoded are left out of the index. A record
+    /// which can not be read results in its error, such as
+    /// `ParseError::CorruptRecording`, since the reader can not continue
+    /// after it and the index would silently miss the rest of the recording.
+    pub fn build_recording<R: Read + Seek>(mut reader: R) -> Result<FrameIndex> {
+        let data_len = try!(reader.seek(SeekFrom::End(0)));
+        try!(reader.seek(SeekFrom::Start(0)));
+        let mut index = FrameIndex::new(IndexedFormat::Recording, data_len);
+        let mut recording = try!(RecordingReader::new(reader));
+        // Index the header records like all others
+        try!(recording.seek(FILE_HEADER_SIZE as u64));
+        loop {
+            let offset = recording.offset();
+            let record = match try!(recording.next_record()) {
+                Some((_, record)) => record,
+                None => break,
+            };
+            match record {
+                Record::Sender(_) => index.senders.push(offset),
+                Record::Models(_) => index.models.push(offset),
+                Record::Packet(bytes) => {
+                    match recording.parser().unpack(&mut bytes.as_slice()) {
+                        Ok(NatNetResponse::FrameOfData(frame)) => index.push(&frame, offset),
+                        Ok(_) => {}
+                        Err(err) => {
+                            debug!("Leaving packet at byte {} out of index: {}", offset, err)
+                        }
+                    }
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Build the index of a file of `NatNet` messages decoded with `parser`
+    ///
+    /// Messages which can not be decoded are left out of the index.
+    pub fn build_messages<R: Read + Seek>(parser: &NatNet, mut reader: R) -> Result<FrameIndex> {
+        let data_len = try!(reader.seek(SeekFrom::End(0)));
+        try!(reader.seek(SeekFrom::Start(0)));
+        let mut index = FrameIndex::new(IndexedFormat::Messages, data_len);
+        let mut messages = parser.messages(reader);
+        while let Some(msg) = messages.next() {
+            match msg {
+                Ok(NatNetResponse::FrameOfData(frame)) => {
+                    index.push(&frame, messages.offset() as u64)
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    debug!("Leaving message at byte {} out of index: {}",
+                           messages.offset(),
+                           err)
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    fn push(&mut self, frame: &FrameOfData, offset: u64) {
+        let last = self.entries.last().map_or(0.0, |e| e.timestamp);
+        self.entries.push(IndexEntry {
+            frame_number: frame.frame_number,
+            timestamp: frame.timestamp.unwrap_or(last),
+            offset: offset,
+        });
+    }
+
+    /// Load the index of the recording at `path`, building it if needed
+    ///
+    /// A newly built index is stored next to the recording, failing to do so
+    /// is only logged. Nothing is stored when the recording can not be read.
+    pub fn open_recording<P: AsRef<Path>>(path: P) -> Result<FrameIndex> {
+        let path = path.as_ref();
+        let data_len = try!(fs::metadata(path)).len();
+        if let Some(index) = FrameIndex::load(path, IndexedFormat::Recording, data_len) {
+            return Ok(index);
+        }
+        let index = try!(FrameIndex::build_recording(BufReader::new(try!(File::open(path)))));
+        index.store(path);
+        Ok(index)
+    }
+
+    /// Load the index of the file of messages at `path`, building it if
+    /// needed
+    ///
+    /// A newly built index is stored next to the file, failing to do so is
+    /// only logged.
+    pub fn open_messages<P: AsRef<Path>>(parser: &NatNet, path: P) -> Result<FrameIndex> {
+        let path = path.as_ref();
+        let data_len = try!(fs::metadata(path)).len();
+        if let Some(index) = FrameIndex::load(path, IndexedFormat::Messages, data_len) {
+            return Ok(index);
+        }
+        let file = BufReader::new(try!(File::open(path)));
+        let index = try!(FrameIndex::build_messages(parser, file));
+        index.store(path);
+        Ok(index)
+    }
+
+    /// Path of the index stored next to the file at `path`
+    pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+        let mut name = OsString::from(path.as_ref().as_os_str());
+        name.push(".idx");
+        PathBuf::from(name)
+    }
+
+    /// Load the stored index of the file at `path`, if it is up to date
+    fn load(path: &Path, format: IndexedFormat, data_len: u64) -> Option<FrameIndex> {
+        let sidecar = FrameIndex::sidecar_path(path);
+        let file = match File::open(&sidecar) {
+            Ok(file) => file,
+            Err(_) => return None,
+        };
+        match FrameIndex::read_from(BufReader::new(file)) {
+            Ok(ref index) if index.format != format || index.data_len != data_len => {
+                debug!("Index {} is out of date, rebuilding", sidecar.display());
+                None
+            }
+            Ok(index) => Some(index),
+            Err(err) => {
+                warn!("Failed to read index {}, rebuilding: {}", sidecar.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Store the index next to the file at `path`
+    fn store(&self, path: &Path) {
+        let sidecar = FrameIndex::sidecar_path(path);
+        // Readers never see a partly written index
+        let mut tmp = OsString::from(sidecar.as_os_str());
+        tmp.push(".tmp");
+        let stored = File::create(&tmp)
+            .map_err(From::from)
+            .and_then(|file| self.write_to(BufWriter::new(file)))
+            .and_then(|_| fs::rename(&tmp, &sidecar).map_err(From::from));
+        if let Err(err) = stored {
+            warn!("Failed to store index {}: {}", sidecar.display(), err);
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    /// Read an index written by `write_to`
+    pub fn read_from<R: Read>(mut reader: R) -> Result<FrameIndex> {
+        let mut magic = [0u8; 6];
+        try!(reader.read_exact(&mut magic));
+        if &magic[..MAGIC.len()] != MAGIC || magic[MAGIC.len()] != FORMAT_VERSION {
+            return Err(ParseError::CorruptRecording { offset: 0 });
+        }
+        let format = match try!(reader.read_u8()) {
+            0 => IndexedFormat::Recording,
+            1 => IndexedFormat::Messages,
+            _ => return Err(ParseError::CorruptRecording { offset: magic.len() as u64 }),
+        };
+        let mut index = FrameIndex::new(format, try!(reader.read_u64::<LittleEndian>()));
+        let count = try!(reader.read_u64::<LittleEndian>());
+        // The count is not trusted for the allocation
+        index.entries.reserve(cmp::min(count, 1 << 16) as usize);
+        for _ in 0..count {
+            index.entries.push(IndexEntry {
+                frame_number: try!(reader.read_i32::<LittleEndian>()),
+                timestamp: try!(reader.read_f64::<LittleEndian>()),
+                offset: try!(reader.read_u64::<LittleEndian>()),
+            });
+        }
+        index.senders = try!(read_offsets(&mut reader));
+        index.models = try!(read_offsets(&mut reader));
+        Ok(index)
+    }
+
+    /// Write the index
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        try!(writer.write_all(MAGIC));
+        try!(writer.write_u8(FORMAT_VERSION));
+        try!(writer.write_u8(match self.format {
+            IndexedFormat::Recording => 0,
+            IndexedFormat::Messages => 1,
+        }));
+        try!(writer.write_u64::<LittleEndian>(self.data_len));
+        try!(writer.write_u64::<LittleEndian>(self.entries.len() as u64));
+        for entry in &self.entries {
+            try!(writer.write_i32::<LittleEndian>(entry.frame_number));
+            try!(writer.write_f64::<LittleEndian>(entry.timestamp));
+            try!(writer.write_u64::<LittleEndian>(entry.offset));
+        }
+        for offsets in &[&self.senders, &self.models] {
+            try!(writer.write_u64::<LittleEndian>(offsets.len() as u64));
+            for &offset in offsets.iter() {
+                try!(writer.write_u64::<LittleEndian>(offset));
+            }
+        }
+        Ok(try!(writer.flush()))
+    }
+
+    /// Layout of the indexed file
+    pub fn format(&self) -> IndexedFormat {
+        self.format
+    }
+
+    /// Size in bytes of the indexed file when it was indexed
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// The indexed frames of data in the order they appear in the file
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Position in `entries` of the first frame with a frame number of at
+    /// least `frame_number`
+    pub fn find_frame(&self, frame_number: i32) -> usize {
+        lower_bound(&self.entries, |e| e.frame_number < frame_number)
+    }
+
+    /// Position in `entries` of the first frame with a timestamp of at least
+    /// `timestamp`
+    pub fn find_time(&self, timestamp: f64) -> usize {
+        lower_bound(&self.entries, |e| e.timestamp < timestamp)
+    }
+
+    /// Offsets of the sender and model definition records needed to decode
+    /// the record at `offset` in a recording
+    ///
+    /// The model definitions are decoded with the sender before them, which
+    /// need not be the sender of the record itself.
+    fn state_at(&self, offset: u64) -> Option<[u64; 3]> {
+        let sender = last_before(&self.senders, offset);
+        let models = last_before(&self.models, offset);
+        let models_sender = models.and_then(|m| last_before(&self.senders, m));
+        match (models_sender, models, sender) {
+            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+            _ => None,
+        }
+    }
+}
+
+fn read_offsets<R: Read>(reader: &mut R) -> Result<Vec<u64>> {
+    let count = try!(reader.read_u64::<LittleEndian>());
+    let mut offsets = Vec::with_capacity(cmp::min(count, 1 << 16) as usize);
+    for _ in 0..count {
+        offsets.push(try!(reader.read_u64::<LittleEndian>()));
+    }
+    Ok(offsets)
+}
+
+/// Position of the first item for which `before` is false
+fn lower_bound<T, F: Fn(&T) -> bool>(items: &[T], before: F) -> usize {
+    let search = items.binary_search_by(|item| {
+        if before(item) {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }
+    });
+    match search {
+        Ok(pos) | Err(pos) => pos,
+    }
+}
+
+/// The last of the sorted `offsets` before `offset`
+fn last_before(offsets: &[u64], offset: u64) -> Option<u64> {
+    match lower_bound(offsets, |o| *o < offset) {
+        0 => None,
+        pos => Some(offsets[pos - 1]),
+    }
+}
+
+/// Source of frames of data read by an `IndexedReader`
+#[derive(Debug)]
+enum Source<R> {
+    Recording {
+        reader: RecordingReader<R>,
+        // Sender and model definition records last read, see `state_at`
+        state: Option<[u64; 3]>,
+    },
+    Messages { parser: NatNet, reader: R },
+}
+
+/// Read frames of data from an indexed file in any order
+///
+/// Seeking only looks up the index, the frame is read by the next call to
+/// `next_frame`. Frames are read in the order of the index.
+#[derive(Debug)]
+pub struct IndexedReader<R> {
+    source: Source<R>,
+    index: FrameIndex,
+    next: usize,
+}
+
+impl IndexedReader<BufReader<File>> {
+    /// Open the recording at `path` with its index, building it if needed
+    pub fn open_recording<P: AsRef<Path>>(path: P) -> Result<IndexedReader<BufReader<File>>> {
+        let index = try!(FrameIndex::open_recording(path.as_ref()));
+        IndexedReader::recording(BufReader::new(try!(File::open(path))), index)
+    }
+
+    /// Open the file of messages at `path` with its index, building it if
+    /// needed
+    pub fn open_messages<P: AsRef<Path>>(parser: NatNet,
+                                         path: P)
+                                         -> Result<IndexedReader<BufReader<File>>> {
+        let index = try!(FrameIndex::open_messages(&parser, path.as_ref()));
+        IndexedReader::messages(parser, BufReader::new(try!(File::open(path))), index)
+    }
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// Read the recording in `reader` using `index`
+    ///
+    /// Results in `ParseError::CorruptRecording` if `index` is not the index
+    /// of a recording.
+    pub fn recording(mut reader: R, index: FrameIndex) -> Result<IndexedReader<R>> {
+        if index.format != IndexedFormat::Recording {
+            return Err(ParseError::CorruptRecording { offset: 0 });
+        }
+        try!(reader.seek(SeekFrom::Start(0)));
+        Ok(IndexedReader {
+            source: Source::Recording {
+                reader: try!(RecordingReader::new(reader)),
+                state: None,
+            },
+            index: index,
+            next: 0,
+        })
+    }
+
+    /// Read the messages in `reader` using `parser` and `index`
+    ///
+    /// Results in `ParseError::CorruptRecording` if `index` is not the index
+    /// of a file of messages.
+    pub fn messages(parser: NatNet, reader: R, index: FrameIndex) -> Result<IndexedReader<R>> {
+        if index.format != IndexedFormat::Messages {
+            return Err(ParseError::CorruptRecording { offset: 0 });
+        }
+        Ok(IndexedReader {
+            source: Source::Messages {
+                parser: parser,
+                reader: reader,
+            },
+            index: index,
+            next: 0,
+        })
+    }
+
+    /// The index of the file
+    pub fn index(&self) -> &FrameIndex {
+        &self.index
+    }
+
+    /// Position in the index of the next frame to read
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    /// Continue reading at the first frame with a frame number of at least
+    /// `frame_number`
+    pub fn seek_frame(&mut self, frame_number: i32) {
+        self.next = self.index.find_frame(frame_number);
+    }
+
+    /// Continue reading at the first frame with a timestamp of at least
+    /// `timestamp`
+    pub fn seek_time(&mut self, timestamp: f64) {
+        self.next = self.index.find_time(timestamp);
+    }
+
+    /// Read the next frame of data
+    ///
+    /// Returns `Ok(None)` after the last frame.
+    pub fn next_frame(&mut self) -> Result<Option<FrameOfData>> {
+        let entry = match self.index.entries.get(self.next) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        self.next += 1;
+        let msg = match self.source {
+            Source::Recording { ref mut reader, ref mut state } => {
+                let needed = try!(self.index
+                    .state_at(entry.offset)
+                    .ok_or(ParseError::CorruptRecording { offset: entry.offset }));
+                if *state != Some(needed) {
+                    for &offset in &needed {
+                        try!(reader.seek(offset));
+                        try!(reader.next_record());
+                    }
+                    *state = Some(needed);
+                }
+                try!(reader.seek(entry.offset));
+                match try!(reader.next_record()) {
+                    Some((_, Record::Packet(bytes))) => {
+                        try!(reader.parser().unpack(&mut bytes.as_slice()))
+                    }
+                    _ => return Err(ParseError::CorruptRecording { offset: entry.offset }),
+                }
+            }
+            Source::Messages { ref parser, ref mut reader } => {
+                try!(reader.seek(SeekFrom::Start(entry.offset)));
+                let mut bytes = vec![0; 4];
+                try!(read_exact(reader, &mut bytes));
+                let len = 4 + LittleEndian::read_u16(&bytes[2..]) as usize;
+                bytes.resize(len, 0);
+                try!(read_exact(reader, &mut bytes[4..]));
+                try!(parser.unpack(&mut bytes.as_slice()))
+            }
+        };
+        match msg {
+            NatNetResponse::FrameOfData(frame) => Ok(Some(frame)),
+            _ => Err(ParseError::CorruptRecording { offset: entry.offset }),
+        }
+    }
+
+    /// Read the frames with frame numbers in `frames`
+    ///
+    /// Reading continues after the range afterwards.
+    pub fn read_frames(&mut self, frames: Range<i32>) -> Result<Vec<FrameOfData>> {
+        self.seek_frame(frames.start);
+        let mut read = Vec::new();
+        while self.index.entries.get(self.next).map_or(false, |e| e.frame_number < frames.end) {
+            if let Some(frame) = try!(self.next_frame()) {
+                read.push(frame);
+            }
+        }
+        Ok(read)
+    }
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    match reader.read_exact(buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(ParseError::NotEnoughBytes),
+        res => Ok(try!(res)),
+    }
+}
@@ -12,8 +12,16 @@
 //! The raw byte interface in `NatNet` has been through many revisions. Many
 //! data fields are optional going backwards. Because of this it is assumed
 //! that users have at least version `>=2.5.0`, fields added after this is
-//! marked optional so that this crate can support several versions. It is
-//! currently up to the user to decide which version to use.
+//! marked optional so that this crate can support several versions. The
+//! version can either be given by the user, detected from a frame of data
+//! with `NatNet::detect` or picked up from a ping response with `AutoNatNet`.
+//!
+//! # Untrusted data
+//! Decoding never panics on malformed data. The amount of data a single
+//! message can make the decoder read and allocate is bounded by
+//! `DecodeLimits`, which can be configured with `NatNet::with_limits`.
+//! Decoding never reads past the end of a message as given by its header,
+//! how bytes left over are treated is configured with `SizeCheck`.
 //!
 //! # Example
 //! ```rust,ignore
@@ -40,51 +48,115 @@ extern crate byteorder;
 extern crate log;
 extern crate nalgebra;
 extern crate semver;
+#[cfg(feature = "tokio")]
+extern crate futures_util;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
+mod asset;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod client;
+mod command;
+mod device;
+mod dispatch;
 mod force_plate;
 mod frame;
+mod header;
+pub mod index;
+mod limits;
 mod marker;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod model;
+pub mod recording;
+pub mod replay;
 mod rigid_body;
 mod sender;
+pub mod server;
+mod server_info;
 mod skeleton;
+mod stream;
 mod messages;
+pub mod view;
 
 // External imports
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use semver::Version;
 
 // Imports from standard library
+use std::cmp;
+use std::convert::TryFrom;
 use std::error::Error as StdError;
 use std::fmt;
-use std::io::BufRead;
+use std::io::{self, BufRead, Read, Write};
 use std::result;
+use std::str;
+use std::sync::{Mutex, MutexGuard};
 
 // Local imports
+pub use asset::Asset;
+pub use command::{Command, CommandResponse, DataType};
+pub use device::Device;
+pub use dispatch::Dispatcher;
 pub use force_plate::ForcePlate;
 pub use frame::FrameOfData;
+pub use header::{MessageHeader, peek_header};
+pub use limits::DecodeLimits;
 pub use marker::{Marker, LabeledMarker};
-pub use messages::{NatNetResponse, NatNetRequest};
+pub use messages::{ConnectOptions, NatNetResponse, NatNetRequest};
 pub use rigid_body::RigidBody;
 pub use sender::Sender;
+use server_info::SERVER_DESCRIPTION_SIZE;
+pub use server_info::ServerDescription;
 pub use skeleton::Skeleton;
+pub use stream::Messages;
+pub use view::FrameView;
 
 /// A result type for errors
 pub type Result<T> = result::Result<T, ParseError>;
 
+/// `NatNet` versions with distinct data layouts supported by this crate
+///
+/// These are the versions tried by `NatNet::detect`.
+pub const SUPPORTED_VERSIONS: &'static [&'static str] = &["2.5.0", "2.6.0", "2.7.0", "2.9.0",
+                                                          "2.11.0", "3.0.0", "4.0.0", "4.1.0"];
+
 /// Errors caused during message parsing
 #[derive(Debug)]
 pub enum ParseError {
-    /// Something unexpected happened
+    /// The End-Of-Data marker of a frame is not `0`
+    ///
+    /// This most likely means that all data parsed is garbage and that
+    /// there is a version mismatch.
+    EndOfDataMismatch {
+        /// End-Of-Data marker found in the message
+        found: i32,
+    },
+    /// A count or size in the message is negative
     ///
-    /// This error is returned when End-Of-Data marker is not
-    /// as expected, this most likely mean that all data parsed
-    /// is garbage and that there is a version mismatch.
-    UnknownError,
+    /// The number included is the value found in the message.
+    InvalidCount(i32),
+    /// The type of a dataset in a model definition is negative
+    ///
+    /// Unknown, but positive, dataset types are decoded as
+    /// `model::DataSet::Unknown`. The number included is the type found.
+    InvalidDataSetType(i32),
+    /// The items of a data block do not fill the size given for the block
+    ///
+    /// Data blocks are prefixed by their size since `NatNet` 4.1.
+    BlockSizeMismatch {
+        /// Size of the block given in the message
+        size: usize,
+        /// Size used by the items in the block
+        used: usize,
+    },
     /// Unknown message received from Motive
     ///
     /// The number included is the message ID given by `NatNet`.
     UnknownResponse(u16),
+    /// The response to a `Command` does not match the command
+    UnexpectedResponse,
     /// Problem reading bytes from input source
     ///
     /// This error is caused by an IO error on the given input source
@@ -94,19 +166,182 @@ pub enum ParseError {
     /// Problem converting C-String in Motive
     ///
     /// There was a problem converting the bytes that Motive considers
-    /// a string into what Rust considers a String.
-    StringError,
+    /// a string into what Rust considers a String, or the other way around.
+    StringError {
+        /// The offending bytes, without null byte
+        bytes: Vec<u8>,
+        /// Reason the bytes are not valid UTF-8, if that was the problem
+        utf8_error: Option<str::Utf8Error>,
+    },
     /// There were not enough bytes in the source to parse a complete message
     ///
     /// This is most likely caused by a mismatch in versions.
     NotEnoughBytes,
+    /// Message is too large to be packed into a single `NatNet` message
+    ///
+    /// The `NatNet` header stores the size of a message in a `u16`, the
+    /// number included is the size of the message that was attempted.
+    MessageTooLarge(usize),
+    /// The `NatNet` version is not known yet
+    ///
+    /// This error is returned by `AutoNatNet` when a message requiring a
+    /// version is received before the version could be detected.
+    UnknownVersion,
+    /// A limit in `DecodeLimits` was exceeded
+    ///
+    /// The name of the limit, the value found in the message and the
+    /// configured maximum are included.
+    LimitExceeded {
+        /// Name of the limit in `DecodeLimits`
+        limit: &'static str,
+        /// Value found in the message
+        value: u64,
+        /// Configured maximum
+        max: usize,
+    },
+    /// The payload of a message does not match the size in its header
+    ///
+    /// Returned for bytes left over in `SizeCheck::Strict` mode, and in both
+    /// modes when the layout needs more bytes than the header declares. In
+    /// the latter case `consumed` is a lower bound.
+    SizeMismatch {
+        /// Size of the payload given in the header
+        declared: usize,
+        /// Size of the payload consumed, or needed, by the layout
+        consumed: usize,
+    },
+    /// An error with the position in a message at which it occurred
+    ///
+    /// Errors while decoding a message are wrapped in this variant, use
+    /// `ParseError::kind` to get the underlying error.
+    Context {
+        /// The underlying error
+        error: Box<ParseError>,
+        /// ID of the message being decoded, if known
+        msg_id: Option<u16>,
+        /// Offset in bytes from the start of the message, including the
+        /// header, at which decoding stopped, if known
+        offset: Option<usize>,
+        /// Path of the field being decoded, e.g.
+        /// `frame.rigid_bodies[3].marker_sizes[2]`
+        path: String,
+    },
+    /// A recording, or the index of a recording, is corrupt
+    ///
+    /// The number included is the offset in bytes of the corrupt part of the
+    /// file.
+    CorruptRecording {
+        /// Offset of the corrupt header or record
+        offset: u64,
+    },
+}
+
+impl ParseError {
+    /// The underlying error, without context
+    pub fn kind(&self) -> &ParseError {
+        match *self {
+            ParseError::Context { ref error, .. } => error.kind(),
+            _ => self,
+        }
+    }
+
+    /// ID of the message in which the error occurred, if known
+    pub fn msg_id(&self) -> Option<u16> {
+        match *self {
+            ParseError::Context { msg_id, .. } => msg_id,
+            _ => None,
+        }
+    }
+
+    /// Offset in bytes from the start of the message at which the error
+    /// occurred, if known
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            ParseError::Context { offset, .. } => offset,
+            _ => None,
+        }
+    }
+
+    /// Path of the field in which the error occurred, if known
+    pub fn path(&self) -> Option<&str> {
+        match *self {
+            ParseError::Context { ref path, .. } if !path.is_empty() => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Prepend a segment to the path of this error
+    fn prepend(self, segment: String) -> ParseError {
+        match self {
+            ParseError::Context { error, msg_id, offset, path } => {
+                let path = if path.is_empty() || path.starts_with('[') {
+                    segment + &path
+                } else {
+                    segment + "." + &path
+                };
+                ParseError::Context {
+                    error: error,
+                    msg_id: msg_id,
+                    offset: offset,
+                    path: path,
+                }
+            }
+            err => {
+                ParseError::Context {
+                    error: Box::new(err),
+                    msg_id: None,
+                    offset: None,
+                    path: segment,
+                }
+            }
+        }
+    }
+
+    /// Create a new error with the same context as this error
+    fn clone_context(&self, error: ParseError) -> ParseError {
+        match *self {
+            ParseError::Context { msg_id, offset, ref path, .. } => {
+                ParseError::Context {
+                    error: Box::new(error),
+                    msg_id: msg_id,
+                    offset: offset,
+                    path: path.clone(),
+                }
+            }
+            _ => error,
+        }
+    }
+
+    /// Set the message and offset of this error, unless already known
+    fn locate(self, id: u16, pos: usize) -> ParseError {
+        match self {
+            ParseError::Context { error, msg_id, offset, path } => {
+                ParseError::Context {
+                    error: error,
+                    msg_id: msg_id.or(Some(id)),
+                    offset: offset.or(Some(pos)),
+                    path: path,
+                }
+            }
+            err => {
+                ParseError::Context {
+                    error: Box::new(err),
+                    msg_id: Some(id),
+                    offset: Some(pos),
+                    path: String::new(),
+                }
+            }
+        }
+    }
 }
 
 /// C-like Enum representing the different possible messages coming from `NatNet`
-/// Updated for `2.10.0`
-#[derive(Clone, PartialEq, Debug, PartialOrd)]
+/// Updated for `4.1.0`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, PartialOrd)]
 pub enum NatNetMsgType {
+    /// Ping, or connect request (`NAT_CONNECT`) since `NatNet` 3.0
     Ping = 0,
+    /// Ping response, or server info (`NAT_SERVERINFO`) since `NatNet` 3.0
     PingResponse = 1,
     Request = 2,
     Response = 3,
@@ -115,15 +350,68 @@ pub enum NatNetMsgType {
     RequestFrameOfData = 6,
     FrameOfData = 7,
     MessageString = 8,
+    Disconnect = 9,
+    KeepAlive = 10,
+    DisconnectByTimeout = 11,
     UnrecognizedRequest = 100,
 }
 
+impl TryFrom<u16> for NatNetMsgType {
+    type Error = ParseError;
+
+    fn try_from(msg_id: u16) -> Result<NatNetMsgType> {
+        match msg_id {
+            0 => Ok(NatNetMsgType::Ping),
+            1 => Ok(NatNetMsgType::PingResponse),
+            2 => Ok(NatNetMsgType::Request),
+            3 => Ok(NatNetMsgType::Response),
+            4 => Ok(NatNetMsgType::RequestModelDef),
+            5 => Ok(NatNetMsgType::ModelDef),
+            6 => Ok(NatNetMsgType::RequestFrameOfData),
+            7 => Ok(NatNetMsgType::FrameOfData),
+            8 => Ok(NatNetMsgType::MessageString),
+            9 => Ok(NatNetMsgType::Disconnect),
+            10 => Ok(NatNetMsgType::KeepAlive),
+            11 => Ok(NatNetMsgType::DisconnectByTimeout),
+            100 => Ok(NatNetMsgType::UnrecognizedRequest),
+            _ => Err(ParseError::UnknownResponse(msg_id)),
+        }
+    }
+}
+
+/// Validation of the size given in the header of a message
+///
+/// Decoding never reads past the end of a message as given by its header,
+/// and the source is always advanced to that end, also when decoding fails.
+/// A layout which needs more bytes results in `ParseError::SizeMismatch` in
+/// both modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeCheck {
+    /// Fail with `ParseError::SizeMismatch` if bytes are left over
+    ///
+    /// The source is still advanced to the end of the message.
+    Strict,
+    /// Skip any bytes left over
+    ///
+    /// Unknown trailing data, e.g. from a newer version of `NatNet`, is
+    /// ignored so that a stream of messages stays in sync.
+    Lenient,
+}
+
+impl Default for SizeCheck {
+    fn default() -> SizeCheck {
+        SizeCheck::Lenient
+    }
+}
+
 /// Parser for `NatNet` data
 ///
 /// This is the main entry point to unpack/parse `NatNet` data.
 #[derive(Clone, Debug)]
 pub struct NatNet {
     ver: Version,
+    limits: DecodeLimits,
+    check: SizeCheck,
 }
 
 impl NatNet {
@@ -132,46 +420,220 @@ impl NatNet {
     /// This will create a new parser that utilizes the given version
     /// for subsequent `unpack` calls
     pub fn new<V: Into<Version>>(ver: V) -> NatNet {
-        NatNet { ver: ver.into() }
+        NatNet {
+            ver: ver.into(),
+            limits: DecodeLimits::default(),
+            check: SizeCheck::default(),
+        }
+    }
+
+    /// Use the given limits for subsequent `unpack` calls
+    pub fn with_limits(mut self, limits: DecodeLimits) -> NatNet {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the given size validation for subsequent `unpack` calls
+    pub fn with_size_check(mut self, check: SizeCheck) -> NatNet {
+        self.check = check;
+        self
+    }
+
+    /// The version used by this parser
+    pub fn version(&self) -> &Version {
+        &self.ver
+    }
+
+    /// The limits used by this parser
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// The size validation used by this parser
+    pub fn size_check(&self) -> SizeCheck {
+        self.check
+    }
+
+    /// Detect which `NatNet` versions can decode a frame of data
+    ///
+    /// This tries every layout in `SUPPORTED_VERSIONS` on the given message
+    /// and returns the versions which consume exactly the number of bytes
+    /// given in the header and end with a valid end-of-data tag. Versions
+    /// which share a layout are all returned. If the message is not a frame
+    /// of data no version is returned.
+    pub fn detect(bytes: &[u8]) -> Vec<Version> {
+        let mut header = bytes;
+        let msg_id = header.read_u16::<LittleEndian>();
+        let num_bytes = header.read_u16::<LittleEndian>();
+        let num_bytes = match (msg_id, num_bytes) {
+            (Ok(msg_id), Ok(num_bytes)) if msg_id == NatNetMsgType::FrameOfData as u16 => {
+                num_bytes as usize
+            }
+            _ => return Vec::new(),
+        };
+        SUPPORTED_VERSIONS.iter()
+            .map(|v| Version::parse(v).unwrap())
+            .filter(|ver| {
+                let mut rest = header;
+                match FrameOfData::unpack(ver, &DecodeLimits::default(), &mut rest) {
+                    Ok(_) => header.len() - rest.len() == num_bytes,
+                    Err(_) => false,
+                }
+            })
+            .collect()
     }
 
     /// Unpack a message from `NatNet` using a specified version
     ///
     /// This will try to unpack a message coming from a NatNet application
-    /// assuming the message uses the given version
+    /// assuming the message uses the given version, the default
+    /// `DecodeLimits` and `SizeCheck::Lenient`
     pub fn unpack_with<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<NatNetResponse> {
+        NatNet::unpack_with_limits(ver, &DecodeLimits::default(), bytes)
+    }
+
+    /// Unpack a message from `NatNet` using a specified version and limits
+    ///
+    /// A message breaking any of the given limits results in
+    /// `ParseError::LimitExceeded`. The size of the message is validated
+    /// with `SizeCheck::Lenient`.
+    pub fn unpack_with_limits<B: BufRead>(ver: &Version,
+                                          limits: &DecodeLimits,
+                                          bytes: &mut B)
+                                          -> Result<NatNetResponse> {
+        NatNet::unpack_checked(ver, limits, SizeCheck::Lenient, bytes)
+    }
+
+    fn unpack_checked<B: BufRead>(ver: &Version,
+                                  limits: &DecodeLimits,
+                                  check: SizeCheck,
+                                  bytes: &mut B)
+                                  -> Result<NatNetResponse> {
         // First 4 bytes contains `msg_id` and number of bytes in message
         // according to `PacketClient.cpp` line 609:615
         let msg_id = try!(bytes.read_u16::<LittleEndian>());
         let num_bytes = try!(bytes.read_u16::<LittleEndian>());
-        NatNet::unpack_rest(msg_id, num_bytes, ver, bytes)
+        NatNet::unpack_rest(msg_id, num_bytes, ver, limits, check, bytes)
     }
 
     fn unpack_rest<B: BufRead>(msg_id: u16,
                                num_bytes: u16,
                                ver: &Version,
+                               limits: &DecodeLimits,
+                               check: SizeCheck,
+                               bytes: &mut B)
+                               -> Result<NatNetResponse> {
+        // Limit decoding to the payload given in the header and count
+        // consumed bytes so that errors can report their offset, the header
+        // has already been read
+        let mut payload = Payload {
+            inner: bytes,
+            pos: 4,
+            end: 4 + num_bytes as usize,
+            wanted: 0,
+        };
+        let res = NatNet::unpack_body(msg_id, num_bytes, ver, limits, &mut payload);
+        let consumed = payload.pos - 4;
+        let res = match res {
+            Err(ref err) if payload.pos == payload.end && payload.wanted > 0 => {
+                match *err.kind() {
+                    ParseError::NotEnoughBytes => {
+                        // The layout needs at least the bytes which were
+                        // requested past the end of the payload
+                        let mismatch = ParseError::SizeMismatch {
+                            declared: num_bytes as usize,
+                            consumed: consumed + payload.wanted,
+                        };
+                        Err(err.clone_context(mismatch))
+                    }
+                    _ => res,
+                }
+            }
+            Err(err) => {
+                // Always advance to the end of the message so that a stream
+                // of messages stays in sync, the error is located where
+                // decoding stopped
+                let err = err.locate(msg_id, payload.pos);
+                if let Err(e) = payload.skip_rest() {
+                    debug!("Failed to skip the rest of message with type: {}: {}", msg_id, e);
+                }
+                return Err(err);
+            }
+            Ok(_) if consumed < num_bytes as usize => {
+                // Always advance to the end of the message so that a stream
+                // of messages stays in sync
+                let leftover = num_bytes as u64 - consumed as u64;
+                let skipped = try!(payload.skip_rest());
+                if skipped < leftover {
+                    Err(ParseError::NotEnoughBytes)
+                } else if check == SizeCheck::Strict {
+                    Err(ParseError::SizeMismatch {
+                        declared: num_bytes as usize,
+                        consumed: consumed,
+                    })
+                } else {
+                    warn!("Message with type: {} has {} bytes left over",
+                          msg_id,
+                          leftover);
+                    res
+                }
+            }
+            _ => res,
+        };
+        res.map_err(|err| err.locate(msg_id, payload.pos))
+    }
+
+    fn unpack_body<B: BufRead>(msg_id: u16,
+                               num_bytes: u16,
+                               ver: &Version,
+                               limits: &DecodeLimits,
                                bytes: &mut B)
                                -> Result<NatNetResponse> {
         debug!("Unpacking `NatNet` message with type: {}, size: {}",
                msg_id,
                num_bytes);
+        try!(check_limit("max_message_size", num_bytes as i64, limits.max_message_size));
         match msg_id {
             _ if msg_id == NatNetMsgType::FrameOfData as u16 => {
-                Ok(NatNetResponse::FrameOfData(try!(FrameOfData::unpack(ver, bytes))))
+                let frame = try!(FrameOfData::unpack(ver, limits, bytes).field("frame"));
+                Ok(NatNetResponse::FrameOfData(frame))
             }
             _ if msg_id == NatNetMsgType::ModelDef as u16 => {
                 let num_models = try!(bytes.read_i32::<LittleEndian>());
-                let mut models = Vec::with_capacity(num_models as usize);
-                for _ in 0..num_models {
-                    models.push(try!(model::DataSet::unpack(ver, bytes)));
+                if num_models < 0 {
+                    return Err(ParseError::InvalidCount(num_models)).field("models");
+                }
+                let mut models = Vec::with_capacity(capacity(num_models));
+                // NOTE: The source is limited to this message so that unknown
+                // datasets can be skipped without reading into the next message
+                for i in 0..num_models {
+                    let model = try!(model::DataSet::unpack(ver, limits, bytes)
+                        .index(i as usize)
+                        .field("models"));
+                    let unknown = match model {
+                        model::DataSet::Unknown { .. } => true,
+                        _ => false,
+                    };
+                    models.push(model);
+                    // Unknown datasets consume the rest of the message
+                    // before version 4.1
+                    if unknown && *ver < Version::parse("4.1.0").unwrap() {
+                        break;
+                    }
                 }
                 Ok(NatNetResponse::ModelDef(models))
             }
+            _ if msg_id == NatNetMsgType::PingResponse as u16 &&
+                 num_bytes as usize >= SERVER_DESCRIPTION_SIZE => {
+                let desc = try!(ServerDescription::unpack(ver, limits, bytes).field("server"));
+                Ok(NatNetResponse::ServerInfo(desc))
+            }
             _ if msg_id == NatNetMsgType::PingResponse as u16 => {
-                Ok(NatNetResponse::Ping(try!(Sender::unpack(ver, bytes))))
+                Ok(NatNetResponse::Ping(try!(Sender::unpack(ver, limits, bytes).field("sender"))))
             }
             _ if msg_id == NatNetMsgType::MessageString as u16 => {
-                Ok(NatNetResponse::MessageString(try!(read_cstring(bytes))))
+                let msg = try!(read_cstring(bytes, limits.max_string_length).field("message"));
+                Ok(NatNetResponse::MessageString(msg))
             }
             // If the message is a command response it can either be a
             // i32 response code or a response string, line: 147
@@ -179,62 +641,349 @@ impl NatNet {
                 Ok(NatNetResponse::Response(try!(bytes.read_i32::<LittleEndian>())))
             }
             _ if msg_id == NatNetMsgType::Response as u16 => {
-                Ok(NatNetResponse::ResponseString(try!(read_cstring(bytes))))
+                let msg = try!(read_cstring(bytes, limits.max_string_length).field("response"));
+                Ok(NatNetResponse::ResponseString(msg))
             }
             _ if msg_id == NatNetMsgType::UnrecognizedRequest as u16 => {
                 Ok(NatNetResponse::UnrecognizedRequest)
             }
-            _ => Err(ParseError::UnknownResponse(msg_id)),
+            _ if msg_id == NatNetMsgType::Disconnect as u16 => Ok(NatNetResponse::Disconnect),
+            _ if msg_id == NatNetMsgType::DisconnectByTimeout as u16 => {
+                Ok(NatNetResponse::DisconnectByTimeout)
+            }
+            _ => {
+                debug!("Passing through unknown message with type: {}", msg_id);
+                let mut raw = Vec::new();
+                try!(bytes.take(num_bytes as u64).read_to_end(&mut raw));
+                if raw.len() < num_bytes as usize {
+                    return Err(ParseError::NotEnoughBytes);
+                }
+                Ok(NatNetResponse::Unknown {
+                    msg_id: msg_id,
+                    raw: raw,
+                })
+            }
         }
     }
 
     /// Unpack only `NatNetMsgType` messages
     ///
-    /// This method unpacks only messages of the requested type. The function
-    /// will consume the header of any `NatNet` message to check if it is the
-    /// correct message and unpack only if it is. This method can be useful when
-    /// needing to unpack only sender messages if `NatNet` version is unknown.
+    /// This method unpacks only messages of the requested type. The header of
+    /// the next message is peeked at with `peek_header` and nothing is
+    /// consumed unless the message has the requested type, so other messages
+    /// can still be read from `bytes`. This method can be useful when needing
+    /// to unpack only sender messages if `NatNet` version is unknown.
+    ///
+    /// # Note
+    /// The header is peeked at through `BufRead::fill_buf`, a reader which
+    /// buffers fewer than 4 bytes of the message results in `None`. Use a
+    /// `Dispatcher` to skip messages of other types.
     pub fn unpack_type_with<B: BufRead>(t: NatNetMsgType,
                                         ver: &Version,
                                         bytes: &mut B)
                                         -> Option<Result<NatNetResponse>> {
-        let msg_id = bytes.read_u16::<LittleEndian>();
-        let num_bytes = bytes.read_u16::<LittleEndian>();
         trace!("Trying to unpack {:?}", t);
-        if let Ok(msg_id) = msg_id {
-            if let Ok(num_bytes) = num_bytes {
-                if msg_id == t as u16 {
-                    trace!("Correct message found");
-                    return Some(NatNet::unpack_rest(msg_id, num_bytes, ver, bytes));
-                }
+        let header = match bytes.fill_buf() {
+            Ok(buf) => peek_header(buf),
+            Err(err) => return Some(Err(err.into())),
+        };
+        match header {
+            Ok(header) if header.msg_type == t => {
+                trace!("Correct message found");
+                bytes.consume(4);
+                Some(NatNet::unpack_rest(t as u16,
+                                         header.num_bytes,
+                                         ver,
+                                         &DecodeLimits::default(),
+                                         SizeCheck::Lenient,
+                                         bytes))
             }
+            _ => None,
         }
-        None
     }
 
     /// Unpack a message from `NatNet`
     pub fn unpack<B: BufRead>(&self, bytes: &mut B) -> Result<NatNetResponse> {
-        NatNet::unpack_with(&self.ver, bytes)
+        NatNet::unpack_checked(&self.ver, &self.limits, self.check, bytes)
+    }
+
+    /// Create a borrowed view of a frame of data
+    ///
+    /// See `FrameView::new`
+    pub fn view<'a>(&'a self, bytes: &'a [u8]) -> Result<FrameView<'a>> {
+        FrameView::new(&self.ver, bytes)
+    }
+
+    /// Iterate over messages stored back to back in `reader`
+    ///
+    /// See `Messages`
+    pub fn messages<'a, R: Read>(&'a self, reader: R) -> Messages<'a, R> {
+        Messages::new(self, reader)
     }
 
     /// Unpack only `NatNetMsgType` messages
     ///
-    /// This method unpacks only messages of the requested type. The function
-    /// will consume the header of any `NatNet` message to check if it is the
-    /// correct message and unpack only if it is. This method can be useful when
-    /// needing to unpack only sender messages if `NatNet` version is unknown.
+    /// See `NatNet::unpack_type_with`
     pub fn unpack_type<B: BufRead>(&self,
                                    t: NatNetMsgType,
                                    bytes: &mut B)
                                    -> Option<Result<NatNetResponse>> {
         NatNet::unpack_type_with(t, &self.ver, bytes)
     }
+
+    /// Pack a message into `NatNet` bytes using a specified version
+    ///
+    /// This writes the complete message, including header, in exactly the
+    /// layout `unpack_with` expects for the given version. Optional fields
+    /// which are missing, but required by the version, are written as their
+    /// zero value.
+    ///
+    /// # Note
+    /// A `NatNetResponse::ResponseString` of exactly three characters has
+    /// the same size as a `NatNetResponse::Response` and will be unpacked as
+    /// such, this is a limitation in `NatNet` itself.
+    pub fn pack_with<W: Write>(ver: &Version, msg: &NatNetResponse, bytes: &mut W) -> Result<()> {
+        // Pack message body first so that we know the size for the header
+        let mut body = Vec::new();
+        let msg_id = match *msg {
+            NatNetResponse::Ping(ref sender) => {
+                try!(sender.pack(ver, &mut body));
+                NatNetMsgType::PingResponse
+            }
+            NatNetResponse::ServerInfo(ref desc) => {
+                try!(desc.pack(ver, &mut body));
+                NatNetMsgType::PingResponse
+            }
+            NatNetResponse::Response(code) => {
+                try!(body.write_i32::<LittleEndian>(code));
+                NatNetMsgType::Response
+            }
+            NatNetResponse::ResponseString(ref s) => {
+                try!(write_cstring(s, &mut body));
+                NatNetMsgType::Response
+            }
+            NatNetResponse::ModelDef(ref models) => {
+                try!(body.write_i32::<LittleEndian>(models.len() as i32));
+                for model in models {
+                    try!(model.pack(ver, &mut body));
+                }
+                NatNetMsgType::ModelDef
+            }
+            NatNetResponse::FrameOfData(ref frame) => {
+                try!(frame.pack(ver, &mut body));
+                NatNetMsgType::FrameOfData
+            }
+            NatNetResponse::MessageString(ref s) => {
+                try!(write_cstring(s, &mut body));
+                NatNetMsgType::MessageString
+            }
+            NatNetResponse::UnrecognizedRequest => NatNetMsgType::UnrecognizedRequest,
+            NatNetResponse::Disconnect => NatNetMsgType::Disconnect,
+            NatNetResponse::DisconnectByTimeout => NatNetMsgType::DisconnectByTimeout,
+            NatNetResponse::Unknown { msg_id, ref raw } => {
+                if raw.len() > u16::max_value() as usize {
+                    return Err(ParseError::MessageTooLarge(raw.len()));
+                }
+                try!(bytes.write_u16::<LittleEndian>(msg_id));
+                try!(bytes.write_u16::<LittleEndian>(raw.len() as u16));
+                try!(bytes.write_all(raw));
+                return Ok(());
+            }
+        };
+        if body.len() > u16::max_value() as usize {
+            return Err(ParseError::MessageTooLarge(body.len()));
+        }
+        debug!("Packing `NatNet` message with type: {:?}, size: {}",
+               msg_id,
+               body.len());
+        let header = MessageHeader {
+            msg_type: msg_id,
+            num_bytes: body.len() as u16,
+        };
+        try!(header.pack(ver, bytes));
+        try!(bytes.write_all(&body));
+        Ok(())
+    }
+
+    /// Pack a message into `NatNet` bytes
+    pub fn pack<W: Write>(&self, msg: &NatNetResponse, bytes: &mut W) -> Result<()> {
+        NatNet::pack_with(&self.ver, msg, bytes)
+    }
+}
+
+/// Auto-configuring parser for `NatNet` data
+///
+/// This parser starts out without a version and locks onto the `NatNet`
+/// version of the first `Sender` received in a ping response. Until then only
+/// messages which do not depend on the version can be unpacked.
+#[derive(Clone, Debug, Default)]
+pub struct AutoNatNet {
+    ver: Option<Version>,
+    limits: DecodeLimits,
+    check: SizeCheck,
+}
+
+impl AutoNatNet {
+    /// Create a new parser without a version
+    pub fn new() -> AutoNatNet {
+        AutoNatNet {
+            ver: None,
+            limits: DecodeLimits::default(),
+            check: SizeCheck::default(),
+        }
+    }
+
+    /// Use the given limits for subsequent `unpack` calls
+    pub fn with_limits(mut self, limits: DecodeLimits) -> AutoNatNet {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the given size validation for subsequent `unpack` calls
+    pub fn with_size_check(mut self, check: SizeCheck) -> AutoNatNet {
+        self.check = check;
+        self
+    }
+
+    /// The detected version, if any
+    pub fn version(&self) -> Option<&Version> {
+        self.ver.as_ref()
+    }
+
+    /// The configured `NatNet` parser, if a version has been detected
+    pub fn parser(&self) -> Option<NatNet> {
+        self.ver.as_ref().map(|ver| {
+            NatNet::new(ver.clone())
+                .with_limits(self.limits.clone())
+                .with_size_check(self.check)
+        })
+    }
+
+    /// Unpack a message from `NatNet`
+    ///
+    /// Frames of data and model definitions received before the version is
+    /// known are skipped and `ParseError::UnknownVersion` is returned.
+    pub fn unpack<B: BufRead>(&mut self, bytes: &mut B) -> Result<NatNetResponse> {
+        let msg_id = try!(bytes.read_u16::<LittleEndian>());
+        let num_bytes = try!(bytes.read_u16::<LittleEndian>());
+        if let Some(ref ver) = self.ver {
+            return NatNet::unpack_rest(msg_id, num_bytes, ver, &self.limits, self.check, bytes);
+        }
+        if msg_id == NatNetMsgType::FrameOfData as u16 ||
+           msg_id == NatNetMsgType::ModelDef as u16 {
+            debug!("Skipping message with type: {} until version is known", msg_id);
+            try!(io::copy(&mut bytes.take(num_bytes as u64), &mut io::sink()));
+            return Err(ParseError::UnknownVersion);
+        }
+        // The remaining messages are the same for all versions
+        let msg = try!(NatNet::unpack_rest(msg_id,
+                                           num_bytes,
+                                           &Version::parse("0.0.0").unwrap(),
+                                           &self.limits,
+                                           self.check,
+                                           bytes));
+        match msg {
+            NatNetResponse::Ping(ref sender) |
+            NatNetResponse::ServerInfo(ServerDescription { ref sender, .. }) => {
+                debug!("Detected NatNet version {}", sender.natnet_version);
+                self.ver = Some(sender.natnet_version.clone());
+            }
+            _ => {}
+        }
+        Ok(msg)
+    }
 }
 
 // Private trait used to unpack underlying data
 trait Unpack<T> {
     /// Unpack the type `T` from the `BufRead` source
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<T>;
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<T>;
+}
+
+// Private trait used to pack underlying data, counterpart to `Unpack`
+trait Pack {
+    /// Pack `self` into the `Write` destination
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()>;
+}
+
+// Private trait used to add the path of a field to errors
+trait ErrorPath<T> {
+    /// Prepend the name of a field to the path of an error
+    fn field(self, name: &str) -> Result<T>;
+    /// Prepend the index of an item to the path of an error
+    fn index(self, i: usize) -> Result<T>;
+}
+
+impl<T> ErrorPath<T> for Result<T> {
+    fn field(self, name: &str) -> Result<T> {
+        self.map_err(|err| err.prepend(name.to_string()))
+    }
+
+    fn index(self, i: usize) -> Result<T> {
+        self.map_err(|err| err.prepend(format!("[{}]", i)))
+    }
+}
+
+impl<T> ErrorPath<T> for io::Result<T> {
+    fn field(self, name: &str) -> Result<T> {
+        self.map_err(ParseError::from).field(name)
+    }
+
+    fn index(self, i: usize) -> Result<T> {
+        self.map_err(ParseError::from).index(i)
+    }
+}
+
+/// Wrapper limiting a `BufRead` source to the payload of a single message
+///
+/// The wrapper counts the bytes consumed and the largest number of bytes
+/// requested past the end of the payload.
+struct Payload<'a, B: 'a> {
+    inner: &'a mut B,
+    pos: usize,
+    end: usize,
+    wanted: usize,
+}
+
+impl<'a, B: BufRead> Payload<'a, B> {
+    /// Skip the rest of the payload, returning the number of bytes skipped
+    fn skip_rest(&mut self) -> io::Result<u64> {
+        let left = (self.end - self.pos) as u64;
+        let skipped = try!(io::copy(&mut (&mut *self.inner).take(left), &mut io::sink()));
+        self.pos += skipped as usize;
+        Ok(skipped)
+    }
+}
+
+impl<'a, B: BufRead> Read for Payload<'a, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.end - self.pos;
+        if left == 0 {
+            self.wanted = cmp::max(self.wanted, buf.len());
+            return Ok(0);
+        }
+        let len = cmp::min(left, buf.len());
+        let num = try!(self.inner.read(&mut buf[..len]));
+        self.pos += num;
+        Ok(num)
+    }
+}
+
+impl<'a, B: BufRead> BufRead for Payload<'a, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let left = self.end - self.pos;
+        if left == 0 {
+            self.wanted = cmp::max(self.wanted, 1);
+        }
+        let buf = try!(self.inner.fill_buf());
+        Ok(&buf[..cmp::min(left, buf.len())])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = cmp::min(amt, self.end - self.pos);
+        self.inner.consume(amt);
+        self.pos += amt;
+    }
 }
 
 // From io error for ParseError
@@ -250,9 +999,11 @@ impl From<std::io::Error> for ParseError {
 
 impl From<std::ffi::NulError> for ParseError {
     /// Convert a `std::ffi::NulError` into a `ParseError::StringError`
-    fn from(_: std::ffi::NulError) -> ParseError {
-        // FIXME: `StringError` should contain the cause
-        ParseError::StringError
+    fn from(err: std::ffi::NulError) -> ParseError {
+        ParseError::StringError {
+            bytes: err.into_vec(),
+            utf8_error: None,
+        }
     }
 }
 
@@ -260,18 +1011,66 @@ impl fmt::Display for ParseError {
     /// Format `ParseError` in human readable fashion
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ParseError::UnknownError => {
+            ParseError::EndOfDataMismatch { found } => {
                 write!(f,
-                       "An unknown error occurred (most likely caused by version mismatch)")
+                       "End-Of-Data marker was {}, not 0 (most likely caused by version \
+                        mismatch)",
+                       found)
+            }
+            ParseError::InvalidCount(num) => write!(f, "Invalid count or size: {}", num),
+            ParseError::InvalidDataSetType(t) => write!(f, "Invalid dataset type: {}", t),
+            ParseError::BlockSizeMismatch { size, used } => {
+                write!(f, "Data block of {} bytes has {} bytes of items", size, used)
             }
             ParseError::UnknownResponse(ref resp) => {
                 write!(f, "Got an unknown message from NatNet with ID: {}", resp)
             }
+            ParseError::UnexpectedResponse => write!(f, "Unexpected response to command"),
             ParseError::IO(ref err) => write!(f, "IO error: {}", err),
-            ParseError::StringError => write!(f, "Error parsing C-String from NatNet"),
+            ParseError::StringError { ref bytes, utf8_error: Some(ref err) } => {
+                write!(f, "Error parsing C-String {:?} from NatNet: {}", bytes, err)
+            }
+            ParseError::StringError { ref bytes, .. } => {
+                write!(f, "Error converting C-String {:?}", bytes)
+            }
             ParseError::NotEnoughBytes => {
                 write!(f, "Not enough bytes in source to parse complete message")
             }
+            ParseError::MessageTooLarge(size) => {
+                write!(f, "Message of {} bytes is too large for NatNet", size)
+            }
+            ParseError::UnknownVersion => write!(f, "NatNet version is not known yet"),
+            ParseError::LimitExceeded { limit, value, max } => {
+                write!(f, "Decode limit `{}` exceeded: {} > {}", limit, value, max)
+            }
+            ParseError::SizeMismatch { declared, consumed } if consumed < declared => {
+                write!(f,
+                       "Message of {} bytes has {} bytes left over",
+                       declared,
+                       declared - consumed)
+            }
+            ParseError::SizeMismatch { declared, consumed } => {
+                write!(f,
+                       "Message of {} bytes is missing at least {} bytes",
+                       declared,
+                       consumed - declared)
+            }
+            ParseError::Context { ref error, msg_id, offset, ref path } => {
+                try!(write!(f, "{}", error));
+                if let Some(id) = msg_id {
+                    try!(write!(f, " in message {}", id));
+                }
+                if let Some(pos) = offset {
+                    try!(write!(f, " at byte {}", pos));
+                }
+                if !path.is_empty() {
+                    try!(write!(f, " ({})", path));
+                }
+                Ok(())
+            }
+            ParseError::CorruptRecording { offset } => {
+                write!(f, "Recording is corrupt at byte {}", offset)
+            }
         }
     }
 }
@@ -279,38 +1078,147 @@ impl fmt::Display for ParseError {
 impl StdError for ParseError {
     fn description(&self) -> &str {
         match *self {
-            ParseError::UnknownError => "Unknown error occurred",
+            ParseError::EndOfDataMismatch { .. } => "End-Of-Data marker not 0",
+            ParseError::InvalidCount(_) => "Invalid count or size",
+            ParseError::InvalidDataSetType(_) => "Invalid dataset type",
+            ParseError::BlockSizeMismatch { .. } => "Data block size mismatch",
             ParseError::UnknownResponse(_) => "Unknown message ID",
+            ParseError::UnexpectedResponse => "Unexpected response to command",
             ParseError::IO(ref err) => err.description(),
-            ParseError::StringError => "Problem parsing C-String from NatNet",
+            ParseError::StringError { .. } => "Problem parsing C-String from NatNet",
             ParseError::NotEnoughBytes => "Not enough bytes in source",
+            ParseError::MessageTooLarge(_) => "Message too large for NatNet",
+            ParseError::UnknownVersion => "NatNet version not known",
+            ParseError::LimitExceeded { .. } => "Decode limit exceeded",
+            ParseError::SizeMismatch { .. } => "Message size does not match header",
+            ParseError::Context { ref error, .. } => error.description(),
+            ParseError::CorruptRecording { .. } => "Corrupt recording",
         }
     }
 
     fn cause(&self) -> Option<&StdError> {
         match *self {
             ParseError::IO(ref err) => Some(err),
+            ParseError::StringError { utf8_error: Some(ref err), .. } => Some(err),
+            ParseError::Context { ref error, .. } => Some(&**error),
             _ => None,
         }
     }
 }
 
+/// Helper function to bound the pre-allocation for a count read from `NatNet`
+///
+/// Counts read from the source can not be trusted, so only a bounded number
+/// of items is allocated up front. Vectors still grow as items are read.
+fn capacity(num: i32) -> usize {
+    cmp::min(cmp::max(num, 0) as usize, 1024)
+}
+
+/// Helper function to check a value read from `NatNet` against a limit
+///
+/// Negative values are not checked, see `read_count`.
+fn check_limit(name: &'static str, value: i64, max: usize) -> Result<()> {
+    if value > 0 && value as u64 > max as u64 {
+        Err(ParseError::LimitExceeded {
+            limit: name,
+            value: value as u64,
+            max: max,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Helper function to read a count from raw bytes and check it against a limit
+fn read_count<B: BufRead>(bytes: &mut B, name: &'static str, max: usize) -> Result<i32> {
+    let num = try!(bytes.read_i32::<LittleEndian>());
+    if num < 0 {
+        return Err(ParseError::InvalidCount(num));
+    }
+    try!(check_limit(name, num as i64, max));
+    Ok(num)
+}
+
+/// Helper function to unpack the channels of a force plate or device
+///
+/// Both the number of channels and the number of samples, in total over all
+/// channels, are checked against the limit `max` named `name`.
+fn unpack_channels<B: BufRead>(bytes: &mut B,
+                               name: &'static str,
+                               max: usize)
+                               -> Result<Vec<Vec<f32>>> {
+    let num_channels = try!(read_count(bytes, name, max));
+    let mut chans = Vec::with_capacity(capacity(num_channels));
+    let mut num_samples = 0i64;
+    for i in 0..num_channels as usize {
+        let num_frames = try!(read_count(bytes, name, max).index(i));
+        num_samples += num_frames as i64;
+        try!(check_limit(name, num_samples, max).index(i));
+        let mut frame = Vec::with_capacity(capacity(num_frames));
+        for j in 0..num_frames as usize {
+            let sample = bytes.read_f32::<LittleEndian>();
+            frame.push(try!(sample.index(j).index(i)));
+        }
+        chans.push(frame);
+    }
+    Ok(chans)
+}
+
+/// Helper function to pack the channels of a force plate or device
+fn pack_channels<W: Write>(chans: &[Vec<f32>], bytes: &mut W) -> Result<()> {
+    try!(bytes.write_i32::<LittleEndian>(chans.len() as i32));
+    for chan in chans {
+        try!(bytes.write_i32::<LittleEndian>(chan.len() as i32));
+        for val in chan {
+            try!(bytes.write_f32::<LittleEndian>(*val));
+        }
+    }
+    Ok(())
+}
+
 /// Helper function to read a C-String from raw bytes
-fn read_cstring<B: BufRead>(bytes: &mut B) -> Result<String> {
+///
+/// At most `max_len` bytes, excluding the null byte, are read from `bytes`.
+fn read_cstring<B: BufRead>(bytes: &mut B, max_len: usize) -> Result<String> {
     // Maximum size for a String is 256, ref: line 631
-    let mut str_buf = Vec::with_capacity(256);
-    try!(bytes.read_until(b'\0', &mut str_buf));
+    let mut str_buf = Vec::with_capacity(cmp::min(256, max_len.saturating_add(1)));
+    let limit = (max_len as u64).saturating_add(1);
+    let read = try!((&mut *bytes).take(limit).read_until(b'\0', &mut str_buf));
     // Remove null byte from end of `str_buf`
-    str_buf.pop();
-    match try!(std::ffi::CString::new(str_buf)).into_string() {
-        Ok(s) => Ok(s),
-        Err(err) => {
-            let reason = err.utf8_error();
-            error!("Could not convert C-String '{:?}' into String, reason: {:?}",
-                   err.into_cstring(),
-                   reason);
-            // FIXME: Return more descriptive error here
-            Err(ParseError::StringError)
-        }
+    if str_buf.pop() != Some(b'\0') {
+        return if read as u64 == limit {
+            Err(ParseError::LimitExceeded {
+                limit: "max_string_length",
+                value: limit,
+                max: max_len,
+            })
+        } else {
+            Err(ParseError::NotEnoughBytes)
+        };
     }
+    // `str_buf` can not contain a null byte since `read_until` stops there
+    String::from_utf8(str_buf).map_err(|err| {
+        let reason = err.utf8_error();
+        debug!("Could not convert C-String '{:?}' into String, reason: {:?}",
+               err.as_bytes(),
+               reason);
+        ParseError::StringError {
+            bytes: err.into_bytes(),
+            utf8_error: Some(reason),
+        }
+    })
+}
+
+/// Lock state shared with a background thread
+///
+/// The state is valid even if a thread panicked while holding the lock.
+fn lock<'a, T>(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Helper function to write a C-String as raw bytes
+fn write_cstring<W: Write>(s: &str, bytes: &mut W) -> Result<()> {
+    let c_str = try!(std::ffi::CString::new(s));
+    try!(bytes.write_all(c_str.as_bytes_with_nul()));
+    Ok(())
 }
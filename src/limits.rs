@@ -0,0 +1,68 @@
+/// Limits applied while decoding data from `NatNet`
+///
+/// Everything read from the network is untrusted, these limits bound the
+/// size of what a single message can make the decoder read and allocate.
+/// A message breaking a limit results in `ParseError::LimitExceeded`.
+///
+/// The default limits are chosen so that no message which fits in a single
+/// `NatNet` packet is rejected.
+///
+/// # Example
+/// ```rust,ignore
+/// use natnet_decode::{DecodeLimits, NatNet};
+///
+/// let limits = DecodeLimits { max_markers: 1000, ..DecodeLimits::default() };
+/// let parser = NatNet::new(ver).with_limits(limits);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum number of marker sets in a frame of data
+    pub max_marker_sets: usize,
+    /// Maximum number of markers in a single list of markers
+    pub max_markers: usize,
+    /// Maximum number of rigid bodies, skeletons, bones, assets, force plates
+    /// or devices in a single list
+    pub max_bodies: usize,
+    /// Maximum number of channels and samples, in total over all channels,
+    /// of a single force plate
+    pub max_force_plate_samples: usize,
+    /// Maximum number of channels and samples, in total over all channels,
+    /// of a single device
+    pub max_device_samples: usize,
+    /// Maximum length of a string, excluding the null terminator
+    pub max_string_length: usize,
+    /// Maximum size in bytes of a message, or a sized block inside a message
+    pub max_message_size: usize,
+}
+
+impl DecodeLimits {
+    /// Limits which never reject a message
+    pub fn unlimited() -> DecodeLimits {
+        DecodeLimits {
+            max_marker_sets: usize::max_value(),
+            max_markers: usize::max_value(),
+            max_bodies: usize::max_value(),
+            max_force_plate_samples: usize::max_value(),
+            max_device_samples: usize::max_value(),
+            max_string_length: usize::max_value(),
+            max_message_size: usize::max_value(),
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        // The size of a `NatNet` message is stored in a `u16`, so no list or
+        // string can be longer than this
+        let max = u16::max_value() as usize;
+        DecodeLimits {
+            max_marker_sets: max,
+            max_markers: max,
+            max_bodies: max,
+            max_force_plate_samples: max,
+            max_device_samples: max,
+            max_string_length: max,
+            max_message_size: max,
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use nalgebra::Point3;
 use semver::Version;
-use std::io::BufRead;
-use super::{Result, Unpack};
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, Result, Pack, Unpack};
 
 /// Visible marker as a point
 pub type Marker = Point3<f32>;
@@ -22,10 +22,36 @@ pub struct LabeledMarker {
     pub point_cloud_solved: Option<bool>,
     /// Was the position solved using a model solver? (NatNet >= 2.6)
     pub model_solved: Option<bool>,
+    /// Is the marker associated with a model? (NatNet >= 2.6)
+    pub has_model: Option<bool>,
+    /// Is the marker unlabeled? (NatNet >= 3.0)
+    pub unlabeled: Option<bool>,
+    /// Is the marker an active marker? (NatNet >= 3.0)
+    pub active_marker: Option<bool>,
+    /// Marker error residual (NatNet >= 3.0)
+    pub residual: Option<f32>,
+}
+
+impl LabeledMarker {
+    /// ID of the model this marker belongs to, `0` if unassigned
+    ///
+    /// Since `NatNet` 3.0 the upper 16 bits of `id` contain the ID of the
+    /// model, e.g. rigid body, the marker is associated with.
+    pub fn model_id(&self) -> i32 {
+        self.id >> 16
+    }
+
+    /// ID of this marker within its model
+    ///
+    /// For rigid bodies this is the 1-based index into the markers of the
+    /// rigid body description (NatNet >= 3.0).
+    pub fn member_id(&self) -> i32 {
+        self.id & 0xffff
+    }
 }
 
 impl Unpack<Marker> for Marker {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<Marker> {
+    fn unpack<B: BufRead>(_: &Version, _: &DecodeLimits, bytes: &mut B) -> Result<Marker> {
         // From `PacketClient.cpp` line 643:645
         let x = try!(bytes.read_f32::<LittleEndian>());
         let y = try!(bytes.read_f32::<LittleEndian>());
@@ -35,14 +61,23 @@ impl Unpack<Marker> for Marker {
 }
 
 impl Unpack<LabeledMarker> for LabeledMarker {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<LabeledMarker> {
+    fn unpack<B: BufRead>(ver: &Version,
+                          limits: &DecodeLimits,
+                          bytes: &mut B)
+                          -> Result<LabeledMarker> {
         // From `PacketClient.cpp` line 825:857
         let id = try!(bytes.read_i32::<LittleEndian>());
-        let pos = try!(Marker::unpack(ver, bytes));
+        let pos = try!(Marker::unpack(ver, limits, bytes));
         let size = try!(bytes.read_f32::<LittleEndian>());
-        let (oc, pcs, ms) = if *ver >= Version::parse("2.6.0").unwrap() {
-            let params = try!(bytes.read_i16::<LittleEndian>());
-            (Some(params & 0x01 > 0), Some(params & 0x02 > 0), Some(params & 0x04 > 0))
+        let params = if *ver >= Version::parse("2.6.0").unwrap() {
+            Some(try!(bytes.read_i16::<LittleEndian>()))
+        } else {
+            None
+        };
+        let flag = |mask: i16| params.map(|p| p & mask > 0);
+        // Marker residual and additional flags added in version 3.0
+        let (unl, act, res) = if *ver >= Version::parse("3.0.0").unwrap() {
+            (flag(0x10), flag(0x20), Some(try!(bytes.read_f32::<LittleEndian>())))
         } else {
             (None, None, None)
         };
@@ -50,9 +85,56 @@ impl Unpack<LabeledMarker> for LabeledMarker {
             id: id,
             position: pos,
             size: size,
-            occluded: oc,
-            point_cloud_solved: pcs,
-            model_solved: ms,
+            occluded: flag(0x01),
+            point_cloud_solved: flag(0x02),
+            model_solved: flag(0x04),
+            has_model: flag(0x08),
+            unlabeled: unl,
+            active_marker: act,
+            residual: res,
         })
     }
 }
+
+impl Pack for Marker {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_f32::<LittleEndian>(self.x));
+        try!(bytes.write_f32::<LittleEndian>(self.y));
+        try!(bytes.write_f32::<LittleEndian>(self.z));
+        Ok(())
+    }
+}
+
+impl Pack for LabeledMarker {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(self.position.pack(ver, bytes));
+        try!(bytes.write_f32::<LittleEndian>(self.size));
+        if *ver >= Version::parse("2.6.0").unwrap() {
+            let mut params = 0;
+            if self.occluded.unwrap_or(false) {
+                params |= 0x01;
+            }
+            if self.point_cloud_solved.unwrap_or(false) {
+                params |= 0x02;
+            }
+            if self.model_solved.unwrap_or(false) {
+                params |= 0x04;
+            }
+            if self.has_model.unwrap_or(false) {
+                params |= 0x08;
+            }
+            if self.unlabeled.unwrap_or(false) {
+                params |= 0x10;
+            }
+            if self.active_marker.unwrap_or(false) {
+                params |= 0x20;
+            }
+            try!(bytes.write_i16::<LittleEndian>(params));
+        }
+        if *ver >= Version::parse("3.0.0").unwrap() {
+            try!(bytes.write_f32::<LittleEndian>(self.residual.unwrap_or(0.0)));
+        }
+        Ok(())
+    }
+}
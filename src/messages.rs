@@ -1,7 +1,10 @@
 use byteorder::{WriteBytesExt, LittleEndian};
+use command::Command;
 use frame::FrameOfData;
 use model;
-use sender::Sender;
+use semver::Version;
+use sender::{self, Sender};
+use server_info::ServerDescription;
 use std::ffi::CString;
 use super::NatNetMsgType;
 
@@ -12,6 +15,9 @@ pub enum NatNetResponse {
     ///
     /// The ping response contains data about the sender application
     Ping(Sender),
+    /// Response to connect request, or ping, from servers using `NatNet` 3.0
+    /// and later
+    ServerInfo(ServerDescription),
     /// Response to command
     Response(i32),
     /// Response to command in String form
@@ -27,6 +33,17 @@ pub enum NatNetResponse {
     MessageString(String),
     /// The sender application did not understand the request
     UnrecognizedRequest,
+    /// The server disconnected the client
+    Disconnect,
+    /// The server disconnected the client after not hearing from it
+    DisconnectByTimeout,
+    /// Message of a type not known to this crate
+    Unknown {
+        /// Message ID as given by `NatNet`
+        msg_id: u16,
+        /// Raw bytes of the message, excluding header
+        raw: Vec<u8>,
+    },
 }
 
 /// Enumeration of possible requests sent to `NatNet`
@@ -40,6 +57,20 @@ pub enum NatNetRequest {
     ModelDefinitions,
     /// Request a frame of data
     FrameOfData,
+    /// Connect to a server
+    ///
+    /// This should result in a `NatNetResponse::ServerInfo`, or a
+    /// `NatNetResponse::Ping` from servers older than `NatNet` 3.0
+    Connect(ConnectOptions),
+    /// Keep a unicast connection alive
+    KeepAlive,
+    /// Disconnect from a server
+    Disconnect,
+    /// Send a command to Motive
+    ///
+    /// This should result in a `NatNetResponse::Response` or
+    /// `NatNetResponse::ResponseString`, see `Command::decode_response`
+    Command(Command),
 }
 
 impl Into<Vec<u8>> for NatNetRequest {
@@ -56,6 +87,32 @@ impl Into<Vec<u8>> for NatNetRequest {
                 bytes.write_u16::<LittleEndian>(NatNetMsgType::RequestFrameOfData as u16).unwrap();
                 bytes.write_u16::<LittleEndian>(0).unwrap();
             }
+            NatNetRequest::KeepAlive => {
+                bytes.write_u16::<LittleEndian>(NatNetMsgType::KeepAlive as u16).unwrap();
+                bytes.write_u16::<LittleEndian>(0).unwrap();
+            }
+            NatNetRequest::Disconnect => {
+                bytes.write_u16::<LittleEndian>(NatNetMsgType::Disconnect as u16).unwrap();
+                bytes.write_u16::<LittleEndian>(0).unwrap();
+            }
+            NatNetRequest::Connect(options) => {
+                bytes.write_u16::<LittleEndian>(NatNetMsgType::Ping as u16).unwrap();
+                bytes.write_u16::<LittleEndian>(CONNECT_SIZE as u16).unwrap();
+                // The client is described like a `Sender`, with the name cut
+                // to fit 256 bytes including `'\0'`
+                let name = options.name.as_bytes();
+                let len = ::std::cmp::min(name.len(), 255);
+                bytes.extend_from_slice(&name[..len]);
+                bytes.extend_from_slice(&[0u8; 256][..256 - len]);
+                bytes.extend_from_slice(&[0u8; 8]);
+                // Followed by the connection options
+                bytes.push(options.subscribed_data_only as u8);
+                match options.bitstream_version {
+                    Some(ref ver) => sender::pack_version(ver, &mut bytes).unwrap(),
+                    None => bytes.extend_from_slice(&[0u8; 4]),
+                }
+            }
+            NatNetRequest::Command(cmd) => return cmd.encode(),
             NatNetRequest::Ping(data) => {
                 let str_data = data.to_bytes_with_nul();
                 bytes.write_u16::<LittleEndian>(NatNetMsgType::Ping as u16).unwrap();
@@ -78,3 +135,31 @@ impl Into<Vec<u8>> for NatNetRequest {
         bytes
     }
 }
+
+/// Size of the payload of a connect request
+const CONNECT_SIZE: usize = 256 + 8 + 1 + 4;
+
+/// Options sent with `NatNetRequest::Connect`
+///
+/// The bitstream version is only understood by servers using `NatNet` 4.1
+/// and later, older servers ignore the options.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectOptions {
+    /// Name of the connecting client
+    pub name: CString,
+    /// Only send the data the client subscribed to
+    pub subscribed_data_only: bool,
+    /// `NatNet` version the server should use for frames of data, `None` to
+    /// use the version of the server
+    pub bitstream_version: Option<Version>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> ConnectOptions {
+        ConnectOptions {
+            name: CString::new("NatNetLib").unwrap(),
+            subscribed_data_only: false,
+            bitstream_version: None,
+        }
+    }
+}
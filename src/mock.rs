@@ -0,0 +1,392 @@
+//! Scripted `NatNet` server for testing clients without Motive
+//!
+//! The mock server answers requests on a loopback UDP socket. Pings are
+//! answered with a configurable `Sender`, which can be changed in the middle
+//! of a session to simulate a change of `NatNet` version, and commands with
+//! scripted responses. Frames of data, synthetic or replayed from fixture
+//! files, are only sent when the test asks for them.
+//!
+//! Every message sent, replies included, goes through a fault injector which
+//! can drop, duplicate, reorder or truncate it. Faults are either given one
+//! by one with `MockServer::inject`, or drawn from a seeded random number
+//! generator with `MockServer::set_random_faults`, so tests stay
+//! deterministic.
+//!
+//! The module is only built with the `mock` feature.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::mock::{Fault, MockServer};
+//!
+//! let mock = try!(MockServer::start(sender));
+//! mock.respond(&Command::StartRecording, NatNetResponse::Response(0));
+//! let mut client = try!(Client::connect(ClientConfig {
+//!     command_port: mock.command_addr().port(),
+//!     ..config
+//! }));
+//! mock.set_data_addr(Some(try!(client.data_addr())));
+//! mock.inject(Fault::Truncate(10));
+//! try!(mock.replay("tests/data/frame-motive-1.9.0-001.bin"));
+//! assert!(client.recv_frame().is_err());
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+use model::DataSet;
+use server::{ping_reply, serve};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use super::{Command, NatNet, NatNetMsgType, NatNetResponse, Result, Sender, lock};
+
+/// Fault injected into a message sent by a `MockServer`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Do not send the message
+    Drop,
+    /// Send the message twice
+    Duplicate,
+    /// Hold the message back and send it after the next message
+    Reorder,
+    /// Only send the first bytes of the message
+    Truncate(usize),
+}
+
+/// Probabilities of faults drawn for each message sent by a `MockServer`
+///
+/// At most one fault is injected into each message. Truncated messages keep
+/// a random number of their bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RandomFaults {
+    /// Seed of the random number generator, the same seed gives the same
+    /// faults
+    pub seed: u64,
+    /// Probability of dropping a message
+    pub drop: f64,
+    /// Probability of duplicating a message
+    pub duplicate: f64,
+    /// Probability of reordering a message
+    pub reorder: f64,
+    /// Probability of truncating a message
+    pub truncate: f64,
+}
+
+/// State shared between the `MockServer` and the thread answering requests
+#[derive(Debug)]
+struct Shared {
+    sender: Sender,
+    models: Vec<DataSet>,
+    responses: HashMap<String, VecDeque<NatNetResponse>>,
+    data_addr: Option<SocketAddr>,
+    clients: Vec<SocketAddr>,
+    requests: Vec<Vec<u8>>,
+    faults: VecDeque<Fault>,
+    random: Option<(RandomFaults, u64)>,
+    held: Option<(Vec<SocketAddr>, Vec<u8>)>,
+}
+
+/// Scripted `NatNet` server on the loopback interface
+///
+/// Requests are answered from a background thread which runs until the mock
+/// server is dropped:
+///
+/// * Pings and connect requests with the current sender, as a
+///   `ServerDescription` for `NatNet` 3.0 and later
+/// * Model definition requests with the current model definitions
+/// * Commands with the responses scripted by `respond`, in order, and with
+///   `NatNetResponse::UnrecognizedRequest` when there are none left
+/// * Keep-alive and disconnect messages are not answered
+///
+/// Messages are sent to the data address if one is set, otherwise to every
+/// client which has sent a request. All messages are packed using the
+/// `NatNet` version of the current sender.
+#[derive(Debug)]
+pub struct MockServer {
+    shared: Arc<Mutex<Shared>>,
+    socket: UdpSocket,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a mock server answering pings with `sender`
+    pub fn start(sender: Sender) -> Result<MockServer> {
+        let socket = try!(UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), 0)));
+        try!(socket.set_read_timeout(Some(Duration::from_millis(20))));
+        let shared = Arc::new(Mutex::new(Shared {
+            sender: sender,
+            models: Vec::new(),
+            responses: HashMap::new(),
+            data_addr: None,
+            clients: Vec::new(),
+            requests: Vec::new(),
+            faults: VecDeque::new(),
+            random: None,
+            held: None,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let socket = try!(socket.try_clone());
+            let shared = shared.clone();
+            let stop = stop.clone();
+            thread::spawn(move || answer(&socket, &shared, &stop))
+        };
+        Ok(MockServer {
+            shared: shared,
+            socket: socket,
+            stop: stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address of the command socket of the mock server
+    pub fn command_addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("Bound socket has a local address")
+    }
+
+    /// Answer pings with `sender` from now on
+    ///
+    /// Messages are packed using the `NatNet` version of the new sender.
+    pub fn set_sender(&self, sender: Sender) {
+        lock(&self.shared).sender = sender;
+    }
+
+    /// Answer model definition requests with `models` from now on
+    pub fn set_models(&self, models: Vec<DataSet>) {
+        lock(&self.shared).models = models;
+    }
+
+    /// Answer the next `cmd` not yet answered with `reply`
+    pub fn respond(&self, cmd: &Command, reply: NatNetResponse) {
+        let mut shared = lock(&self.shared);
+        shared.responses.entry(cmd.to_string()).or_insert_with(VecDeque::new).push_back(reply);
+    }
+
+    /// Send messages to `addr`, or to clients if `None`
+    pub fn set_data_addr(&self, addr: Option<SocketAddr>) {
+        lock(&self.shared).data_addr = addr;
+    }
+
+    /// Inject `fault` into a coming message
+    ///
+    /// Faults are injected in the order given, one per message, before any
+    /// random faults.
+    pub fn inject(&self, fault: Fault) {
+        lock(&self.shared).faults.push_back(fault);
+    }
+
+    /// Inject random faults into messages, `None` to stop
+    pub fn set_random_faults(&self, faults: Option<RandomFaults>) {
+        // Xorshift gets stuck at zero
+        lock(&self.shared).random = faults.map(|f| {
+            let state = if f.seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { f.seed };
+            (f, state)
+        });
+    }
+
+    /// Raw bytes of every request received so far, keep-alives included
+    pub fn requests(&self) -> Vec<Vec<u8>> {
+        lock(&self.shared).requests.clone()
+    }
+
+    /// Send `msg` packed with the current `NatNet` version
+    pub fn send(&self, msg: &NatNetResponse) -> Result<()> {
+        let mut shared = lock(&self.shared);
+        let mut bytes = Vec::new();
+        try!(NatNet::pack_with(&shared.sender.natnet_version, msg, &mut bytes));
+        let targets = shared.targets();
+        Ok(try!(shared.transmit(&self.socket, targets, bytes)))
+    }
+
+    /// Send raw bytes as a single message
+    pub fn send_raw(&self, bytes: &[u8]) -> Result<()> {
+        let mut shared = lock(&self.shared);
+        let targets = shared.targets();
+        Ok(try!(shared.transmit(&self.socket, targets, bytes.to_vec())))
+    }
+
+    /// Send the messages stored back to back in the file at `path`
+    ///
+    /// Each message is sent as it is, without being decoded, so fixture files
+    /// of any `NatNet` version can be replayed. A message cut short by the
+    /// end of the file is sent truncated. Returns the number of messages
+    /// sent.
+    pub fn replay<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let mut bytes = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut bytes));
+        let mut rest = bytes.as_slice();
+        let mut count = 0;
+        while !rest.is_empty() {
+            let len = if rest.len() >= 4 {
+                4 + LittleEndian::read_u16(&rest[2..]) as usize
+            } else {
+                rest.len()
+            };
+            let (msg, next) = rest.split_at(cmp::min(len, rest.len()));
+            try!(self.send_raw(msg));
+            rest = next;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Send the message held back by `Fault::Reorder`, if any
+    pub fn flush(&self) -> Result<()> {
+        let held = lock(&self.shared).held.take();
+        if let Some((targets, bytes)) = held {
+            try!(send_to(&self.socket, &targets, &bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MockServer {
+    /// Stop and wait for the thread answering requests
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Addresses messages which are not replies are sent to
+    fn targets(&self) -> Vec<SocketAddr> {
+        match self.data_addr {
+            Some(addr) => vec![addr],
+            None => self.clients.clone(),
+        }
+    }
+
+    /// The next fault to inject, if any
+    fn next_fault(&mut self, len: usize) -> Option<Fault> {
+        if let Some(fault) = self.faults.pop_front() {
+            return Some(fault);
+        }
+        let (faults, state) = match self.random {
+            Some((ref faults, ref mut state)) => (faults, state),
+            None => return None,
+        };
+        let draw = next_random(state) as f64 / u64::max_value() as f64;
+        let mut limit = 0.0;
+        for &(p, fault) in [(faults.drop, Fault::Drop),
+                            (faults.duplicate, Fault::Duplicate),
+                            (faults.reorder, Fault::Reorder),
+                            (faults.truncate, Fault::Truncate(0))]
+            .iter() {
+            limit += p;
+            if draw < limit {
+                return match fault {
+                    Fault::Truncate(_) => {
+                        let keep = next_random(state) % cmp::max(len, 1) as u64;
+                        Some(Fault::Truncate(keep as usize))
+                    }
+                    fault => Some(fault),
+                };
+            }
+        }
+        None
+    }
+
+    /// Send `bytes` to `targets` after injecting faults
+    fn transmit(&mut self,
+                socket: &UdpSocket,
+                targets: Vec<SocketAddr>,
+                mut bytes: Vec<u8>)
+                -> io::Result<()> {
+        let fault = self.next_fault(bytes.len());
+        if fault.is_some() {
+            debug!("Injecting {:?} into message of {} bytes", fault, bytes.len());
+        }
+        match fault {
+            Some(Fault::Drop) => return Ok(()),
+            Some(Fault::Reorder) => {
+                // Only one message is held back, an earlier one is released
+                if let Some((targets, bytes)) = self.held.take() {
+                    try!(send_to(socket, &targets, &bytes));
+                }
+                self.held = Some((targets, bytes));
+                return Ok(());
+            }
+            Some(Fault::Duplicate) => try!(send_to(socket, &targets, &bytes)),
+            Some(Fault::Truncate(len)) => bytes.truncate(len),
+            None => {}
+        }
+        try!(send_to(socket, &targets, &bytes));
+        if let Some((targets, bytes)) = self.held.take() {
+            try!(send_to(socket, &targets, &bytes));
+        }
+        Ok(())
+    }
+}
+
+fn send_to(socket: &UdpSocket, targets: &[SocketAddr], bytes: &[u8]) -> io::Result<()> {
+    for addr in targets {
+        try!(socket.send_to(bytes, addr));
+    }
+    Ok(())
+}
+
+/// Xorshift random number generator, good enough for injecting faults
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Answer requests on `socket` until `stop` is set
+///
+/// Replies go through the fault injector, so none are left for `serve` to
+/// send.
+fn answer(socket: &UdpSocket, shared: &Mutex<Shared>, stop: &AtomicBool) {
+    serve(socket, || stop.load(Ordering::SeqCst), |client, msg_id, request| {
+        let mut shared = lock(shared);
+        shared.requests.push(request.to_vec());
+        if !shared.clients.contains(&client) {
+            shared.clients.push(client);
+        }
+        let reply = match reply(&mut shared, msg_id, &request[4..]) {
+            Some(reply) => reply,
+            None => return None,
+        };
+        let mut bytes = Vec::new();
+        if let Err(e) = NatNet::pack_with(&shared.sender.natnet_version, &reply, &mut bytes) {
+            warn!("Mock server failed to pack {:?}: {}", reply, e);
+            return None;
+        }
+        if let Err(e) = shared.transmit(socket, vec![client], bytes) {
+            warn!("Mock server failed to answer {}: {}", client, e);
+        }
+        None
+    })
+}
+
+/// The reply to a request of type `msg_id` with `payload`
+fn reply(shared: &mut Shared, msg_id: u16, payload: &[u8]) -> Option<NatNetResponse> {
+    if msg_id == NatNetMsgType::Ping as u16 {
+        Some(ping_reply(&shared.sender, shared.data_addr))
+    } else if msg_id == NatNetMsgType::RequestModelDef as u16 {
+        Some(NatNetResponse::ModelDef(shared.models.clone()))
+    } else if msg_id == NatNetMsgType::Request as u16 {
+        let len = payload.iter().position(|b| *b == 0).unwrap_or(payload.len());
+        let cmd = String::from_utf8_lossy(&payload[..len]).into_owned();
+        let reply = shared.responses.get_mut(&cmd).and_then(|replies| replies.pop_front());
+        if reply.is_none() {
+            debug!("No scripted response to command {:?}", cmd);
+        }
+        Some(reply.unwrap_or(NatNetResponse::UnrecognizedRequest))
+    } else if msg_id == NatNetMsgType::KeepAlive as u16 ||
+              msg_id == NatNetMsgType::Disconnect as u16 {
+        None
+    } else {
+        Some(NatNetResponse::UnrecognizedRequest)
+    }
+}
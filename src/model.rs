@@ -1,8 +1,9 @@
-use byteorder::{ReadBytesExt, LittleEndian};
-use nalgebra::Vector3;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use nalgebra::{Point3, Quaternion, Vector3};
 use semver::Version;
-use std::io::BufRead;
-use super::{Result, Unpack, read_cstring};
+use std::io::{self, BufRead, Read, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, ParseError, Unpack, capacity, check_limit,
+            read_count, read_cstring, write_cstring};
 
 /// Description of `MarkerSet`
 #[derive(Clone, Debug, PartialEq)]
@@ -24,6 +25,12 @@ pub struct RigidBody {
     pub parent_id: i32,
     /// Offset from parent
     pub offset: Vector3<f32>,
+    /// Position of markers relative to body (NatNet >= 3.0)
+    pub marker_positions: Option<Vec<Vector3<f32>>>,
+    /// Required active label of markers, `0` if passive (NatNet >= 3.0)
+    pub marker_labels: Option<Vec<i32>>,
+    /// Name of markers (NatNet >= 4.0)
+    pub marker_names: Option<Vec<String>>,
 }
 
 /// Description of `Skeleton`
@@ -37,6 +44,101 @@ pub struct Skeleton {
     pub bones: Vec<RigidBody>,
 }
 
+/// Description of `ForcePlate`
+///
+/// The `id` of the description matches the `id` of `ForcePlate` data in a
+/// `FrameOfData`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForcePlate {
+    /// ID of plate
+    pub id: i32,
+    /// Serial number of plate
+    pub serial_number: String,
+    /// Width of plate
+    pub width: f32,
+    /// Length of plate
+    pub length: f32,
+    /// Electrical center offset of plate
+    pub origin: Vector3<f32>,
+    /// Calibration matrix of plate
+    pub calibration_matrix: [[f32; 12]; 12],
+    /// Position of plate corners
+    pub corners: [Point3<f32>; 4],
+    /// Type of plate
+    pub plate_type: i32,
+    /// Data type of channels
+    pub channel_data_type: i32,
+    /// Names of channels
+    pub channel_names: Vec<String>,
+}
+
+/// Description of `Device`
+///
+/// The `id` of the description matches the `id` of `Device` data in a
+/// `FrameOfData`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    /// ID of device
+    pub id: i32,
+    /// Name of device
+    pub name: String,
+    /// Serial number of device
+    pub serial_number: String,
+    /// Type of device
+    pub device_type: i32,
+    /// Data type of channels
+    pub channel_data_type: i32,
+    /// Names of channels
+    pub channel_names: Vec<String>,
+}
+
+/// Description of `Camera`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    /// Name of camera
+    pub name: String,
+    /// Position in 3D
+    pub position: Point3<f32>,
+    /// Orientation represented as a quaternion
+    pub orientation: Quaternion<f32>,
+}
+
+/// Description of `Marker`
+///
+/// # `NatNet` version
+/// This structure is new in 4.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    /// Name of marker
+    pub name: String,
+    /// ID of marker
+    pub id: i32,
+    /// Initial position
+    pub position: Point3<f32>,
+    /// Size of marker
+    pub size: f32,
+    /// Marker parameters
+    pub params: i16,
+}
+
+/// Description of `Asset`
+///
+/// # `NatNet` version
+/// This structure is new in 4.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// Name of asset
+    pub name: String,
+    /// Type of asset
+    pub asset_type: i32,
+    /// ID of asset
+    pub id: i32,
+    /// List of `RigidBody` descriptions
+    pub rigid_bodies: Vec<RigidBody>,
+    /// List of `Marker` descriptions
+    pub markers: Vec<Marker>,
+}
+
 /// Description of dataset
 #[derive(Clone, Debug, PartialEq)]
 pub enum DataSet {
@@ -46,6 +148,25 @@ pub enum DataSet {
     RigidBody(RigidBody),
     /// Description of a `Skeleton`
     Skeleton(Skeleton),
+    /// Description of a `ForcePlate`
+    ForcePlate(ForcePlate),
+    /// Description of a `Device`
+    Device(Device),
+    /// Description of a `Camera`
+    Camera(Camera),
+    /// Description of an `Asset` (NatNet >= 4.1)
+    Asset(Asset),
+    /// Dataset of a type not known to this crate
+    ///
+    /// Before `NatNet` 4.1 datasets do not carry their size, `raw` will
+    /// then contain the rest of the message including any datasets following
+    /// this one.
+    Unknown {
+        /// Dataset type as given by `NatNet`
+        type_id: i32,
+        /// Raw bytes of the dataset
+        raw: Vec<u8>,
+    },
 }
 
 /// Private type to match against
@@ -53,33 +174,130 @@ enum DataSetType {
     MarkerSet = 0,
     RigidBody = 1,
     Skeleton = 2,
+    ForcePlate = 3,
+    Device = 4,
+    Camera = 5,
+    Asset = 6,
+}
+
+/// Helper function to read a `Vector3` from raw bytes
+fn read_vector<B: BufRead>(bytes: &mut B) -> Result<Vector3<f32>> {
+    let x = try!(bytes.read_f32::<LittleEndian>());
+    let y = try!(bytes.read_f32::<LittleEndian>());
+    let z = try!(bytes.read_f32::<LittleEndian>());
+    Ok(Vector3::new(x, y, z))
 }
 
 impl Unpack<DataSet> for DataSet {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<DataSet> {
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<DataSet> {
         let d_type = try!(bytes.read_i32::<LittleEndian>());
-        match d_type {
-            _ if d_type == DataSetType::MarkerSet as i32 => {
-                Ok(DataSet::MarkerSet(try!(MarkerSet::unpack(ver, bytes))))
+        if d_type < 0 {
+            return Err(ParseError::InvalidDataSetType(d_type));
+        }
+        // Size of dataset in bytes added in version 4.1
+        let size = if *ver >= Version::parse("4.1.0").unwrap() {
+            let size = try!(bytes.read_i32::<LittleEndian>());
+            trace!("Dataset of type {} with {} bytes", d_type, size);
+            if size < 0 {
+                return Err(ParseError::InvalidCount(size));
             }
-            _ if d_type == DataSetType::RigidBody as i32 => {
-                Ok(DataSet::RigidBody(try!(RigidBody::unpack(ver, bytes))))
+            try!(check_limit("max_message_size", size as i64, limits.max_message_size));
+            Some(size as u64)
+        } else {
+            None
+        };
+        match size {
+            Some(size) => {
+                // Known datasets are limited to their size, so fields added
+                // by a newer version of `NatNet` are skipped
+                let mut dataset = bytes.take(size);
+                let model = match try!(unpack_known(d_type, ver, limits, &mut dataset)) {
+                    Some(model) => model,
+                    None => {
+                        debug!("Skipping unknown dataset of type {}", d_type);
+                        let mut raw = Vec::new();
+                        try!(dataset.read_to_end(&mut raw));
+                        DataSet::Unknown {
+                            type_id: d_type,
+                            raw: raw,
+                        }
+                    }
+                };
+                let left = dataset.limit();
+                if left > 0 {
+                    debug!("Skipping {} bytes left over in dataset of type {}", left, d_type);
+                    if try!(io::copy(&mut dataset, &mut io::sink())) < left {
+                        return Err(ParseError::NotEnoughBytes);
+                    }
+                }
+                Ok(model)
             }
-            _ if d_type == DataSetType::Skeleton as i32 => {
-                Ok(DataSet::Skeleton(try!(Skeleton::unpack(ver, bytes))))
+            None => {
+                match try!(unpack_known(d_type, ver, limits, bytes)) {
+                    Some(model) => Ok(model),
+                    None => {
+                        // Without a size the only option is to consume the
+                        // rest of the source, which the caller limits to the
+                        // current message
+                        debug!("Skipping unknown dataset of type {}", d_type);
+                        let mut raw = Vec::new();
+                        try!(bytes.read_to_end(&mut raw));
+                        Ok(DataSet::Unknown {
+                            type_id: d_type,
+                            raw: raw,
+                        })
+                    }
+                }
             }
-            _ => unreachable!(),
         }
     }
 }
 
+/// Helper function to unpack a dataset of type `d_type`
+///
+/// Returns `None` for types not known to this crate, without reading
+/// anything.
+fn unpack_known<B: BufRead>(d_type: i32,
+                            ver: &Version,
+                            limits: &DecodeLimits,
+                            bytes: &mut B)
+                            -> Result<Option<DataSet>> {
+    let model = match d_type {
+        _ if d_type == DataSetType::MarkerSet as i32 => {
+            DataSet::MarkerSet(try!(MarkerSet::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::RigidBody as i32 => {
+            DataSet::RigidBody(try!(RigidBody::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::Skeleton as i32 => {
+            DataSet::Skeleton(try!(Skeleton::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::ForcePlate as i32 => {
+            DataSet::ForcePlate(try!(ForcePlate::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::Device as i32 => {
+            DataSet::Device(try!(Device::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::Camera as i32 => {
+            DataSet::Camera(try!(Camera::unpack(ver, limits, bytes)))
+        }
+        _ if d_type == DataSetType::Asset as i32 => {
+            DataSet::Asset(try!(Asset::unpack(ver, limits, bytes)))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(model))
+}
+
 impl Unpack<MarkerSet> for MarkerSet {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<MarkerSet> {
-        let name = try!(read_cstring(bytes));
-        let num_markers = try!(bytes.read_i32::<LittleEndian>());
-        let mut markers = Vec::with_capacity(num_markers as usize);
-        for _ in 0..num_markers {
-            markers.push(try!(read_cstring(bytes)));
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<MarkerSet> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+        let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers)
+            .field("markers"));
+        let mut markers = Vec::with_capacity(capacity(num_markers));
+        for i in 0..num_markers as usize {
+            let marker = read_cstring(bytes, limits.max_string_length);
+            markers.push(try!(marker.index(i).field("markers")));
         }
         Ok(MarkerSet {
             name: name,
@@ -89,30 +307,65 @@ impl Unpack<MarkerSet> for MarkerSet {
 }
 
 impl Unpack<RigidBody> for RigidBody {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<RigidBody> {
-        let name = try!(read_cstring(bytes));
+    fn unpack<B: BufRead>(ver: &Version,
+                          limits: &DecodeLimits,
+                          bytes: &mut B)
+                          -> Result<RigidBody> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
         let id = try!(bytes.read_i32::<LittleEndian>());
         let p_id = try!(bytes.read_i32::<LittleEndian>());
         let x = try!(bytes.read_f32::<LittleEndian>());
         let y = try!(bytes.read_f32::<LittleEndian>());
         let z = try!(bytes.read_f32::<LittleEndian>());
+        // Marker data was added in version 3.0 and marker names in 4.0
+        let (positions, labels, names) = if *ver >= Version::parse("3.0.0").unwrap() {
+            let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers)
+                .field("marker_positions"));
+            // NOTE: All positions are consecutively, then labels, then names
+            let mut positions = Vec::with_capacity(capacity(num_markers));
+            for i in 0..num_markers as usize {
+                let pos = read_vector(bytes);
+                positions.push(try!(pos.index(i).field("marker_positions")));
+            }
+            let mut labels = Vec::with_capacity(capacity(num_markers));
+            for i in 0..num_markers as usize {
+                let label = bytes.read_i32::<LittleEndian>();
+                labels.push(try!(label.index(i).field("marker_labels")));
+            }
+            let names = if *ver >= Version::parse("4.0.0").unwrap() {
+                let mut names = Vec::with_capacity(capacity(num_markers));
+                for i in 0..num_markers as usize {
+                    let name = read_cstring(bytes, limits.max_string_length);
+                    names.push(try!(name.index(i).field("marker_names")));
+                }
+                Some(names)
+            } else {
+                None
+            };
+            (Some(positions), Some(labels), names)
+        } else {
+            (None, None, None)
+        };
         Ok(RigidBody {
             name: name,
             id: id,
             parent_id: p_id,
             offset: Vector3::new(x, y, z),
+            marker_positions: positions,
+            marker_labels: labels,
+            marker_names: names,
         })
     }
 }
 
 impl Unpack<Skeleton> for Skeleton {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<Skeleton> {
-        let name = try!(read_cstring(bytes));
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Skeleton> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
         let id = try!(bytes.read_i32::<LittleEndian>());
-        let num_rb = try!(bytes.read_i32::<LittleEndian>());
-        let mut bodies = Vec::with_capacity(num_rb as usize);
-        for _ in 0..num_rb {
-            bodies.push(try!(RigidBody::unpack(ver, bytes)));
+        let num_rb = try!(read_count(bytes, "max_bodies", limits.max_bodies).field("bones"));
+        let mut bodies = Vec::with_capacity(capacity(num_rb));
+        for i in 0..num_rb as usize {
+            bodies.push(try!(RigidBody::unpack(ver, limits, bytes).index(i).field("bones")));
         }
         Ok(Skeleton {
             name: name,
@@ -121,3 +374,328 @@ impl Unpack<Skeleton> for Skeleton {
         })
     }
 }
+
+impl Pack for DataSet {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        // Pack dataset first so that the size is known for version 4.1
+        let mut data = Vec::new();
+        let d_type = match *self {
+            DataSet::Unknown { type_id, ref raw } => {
+                try!(bytes.write_i32::<LittleEndian>(type_id));
+                if *ver >= Version::parse("4.1.0").unwrap() {
+                    try!(bytes.write_i32::<LittleEndian>(raw.len() as i32));
+                }
+                try!(bytes.write_all(raw));
+                return Ok(());
+            }
+            DataSet::MarkerSet(ref set) => {
+                try!(set.pack(ver, &mut data));
+                DataSetType::MarkerSet
+            }
+            DataSet::RigidBody(ref body) => {
+                try!(body.pack(ver, &mut data));
+                DataSetType::RigidBody
+            }
+            DataSet::Skeleton(ref skel) => {
+                try!(skel.pack(ver, &mut data));
+                DataSetType::Skeleton
+            }
+            DataSet::ForcePlate(ref plate) => {
+                try!(plate.pack(ver, &mut data));
+                DataSetType::ForcePlate
+            }
+            DataSet::Device(ref device) => {
+                try!(device.pack(ver, &mut data));
+                DataSetType::Device
+            }
+            DataSet::Camera(ref camera) => {
+                try!(camera.pack(ver, &mut data));
+                DataSetType::Camera
+            }
+            DataSet::Asset(ref asset) => {
+                try!(asset.pack(ver, &mut data));
+                DataSetType::Asset
+            }
+        };
+        try!(bytes.write_i32::<LittleEndian>(d_type as i32));
+        if *ver >= Version::parse("4.1.0").unwrap() {
+            try!(bytes.write_i32::<LittleEndian>(data.len() as i32));
+        }
+        try!(bytes.write_all(&data));
+        Ok(())
+    }
+}
+
+impl Pack for MarkerSet {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.markers.len() as i32));
+        for marker in &self.markers {
+            try!(write_cstring(marker, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Pack for RigidBody {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_i32::<LittleEndian>(self.parent_id));
+        try!(bytes.write_f32::<LittleEndian>(self.offset.x));
+        try!(bytes.write_f32::<LittleEndian>(self.offset.y));
+        try!(bytes.write_f32::<LittleEndian>(self.offset.z));
+        if *ver >= Version::parse("3.0.0").unwrap() {
+            // The number of positions decides how many labels and names are
+            // read, so missing labels and names are padded
+            let positions = self.marker_positions.as_ref().map_or(&[][..], |p| &p[..]);
+            try!(bytes.write_i32::<LittleEndian>(positions.len() as i32));
+            for pos in positions {
+                try!(bytes.write_f32::<LittleEndian>(pos.x));
+                try!(bytes.write_f32::<LittleEndian>(pos.y));
+                try!(bytes.write_f32::<LittleEndian>(pos.z));
+            }
+            for i in 0..positions.len() {
+                let label = self.marker_labels.as_ref().and_then(|l| l.get(i).cloned());
+                try!(bytes.write_i32::<LittleEndian>(label.unwrap_or(0)));
+            }
+            if *ver >= Version::parse("4.0.0").unwrap() {
+                for i in 0..positions.len() {
+                    let name = self.marker_names.as_ref().and_then(|n| n.get(i));
+                    try!(write_cstring(name.map_or("", |n| &n[..]), bytes));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Pack for Skeleton {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_i32::<LittleEndian>(self.bones.len() as i32));
+        for bone in &self.bones {
+            try!(bone.pack(ver, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Unpack<Marker> for Marker {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Marker> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let x = try!(bytes.read_f32::<LittleEndian>());
+        let y = try!(bytes.read_f32::<LittleEndian>());
+        let z = try!(bytes.read_f32::<LittleEndian>());
+        let size = try!(bytes.read_f32::<LittleEndian>());
+        let params = try!(bytes.read_i16::<LittleEndian>());
+        Ok(Marker {
+            name: name,
+            id: id,
+            position: Point3::new(x, y, z),
+            size: size,
+            params: params,
+        })
+    }
+}
+
+impl Pack for Marker {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_f32::<LittleEndian>(self.position.x));
+        try!(bytes.write_f32::<LittleEndian>(self.position.y));
+        try!(bytes.write_f32::<LittleEndian>(self.position.z));
+        try!(bytes.write_f32::<LittleEndian>(self.size));
+        try!(bytes.write_i16::<LittleEndian>(self.params));
+        Ok(())
+    }
+}
+
+impl Unpack<Asset> for Asset {
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Asset> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+        let a_type = try!(bytes.read_i32::<LittleEndian>());
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num_rb = try!(read_count(bytes, "max_bodies", limits.max_bodies).field("rigid_bodies"));
+        let mut bodies = Vec::with_capacity(capacity(num_rb));
+        for i in 0..num_rb as usize {
+            bodies.push(try!(RigidBody::unpack(ver, limits, bytes).index(i).field("rigid_bodies")));
+        }
+        let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers)
+            .field("markers"));
+        let mut markers = Vec::with_capacity(capacity(num_markers));
+        for i in 0..num_markers as usize {
+            markers.push(try!(Marker::unpack(ver, limits, bytes).index(i).field("markers")));
+        }
+        Ok(Asset {
+            name: name,
+            asset_type: a_type,
+            id: id,
+            rigid_bodies: bodies,
+            markers: markers,
+        })
+    }
+}
+
+impl Pack for Asset {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.asset_type));
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_i32::<LittleEndian>(self.rigid_bodies.len() as i32));
+        for body in &self.rigid_bodies {
+            try!(body.pack(ver, bytes));
+        }
+        try!(bytes.write_i32::<LittleEndian>(self.markers.len() as i32));
+        for marker in &self.markers {
+            try!(marker.pack(ver, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Unpack<ForcePlate> for ForcePlate {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<ForcePlate> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let serial = try!(read_cstring(bytes, limits.max_string_length).field("serial_number"));
+        let width = try!(bytes.read_f32::<LittleEndian>());
+        let length = try!(bytes.read_f32::<LittleEndian>());
+        let x = try!(bytes.read_f32::<LittleEndian>());
+        let y = try!(bytes.read_f32::<LittleEndian>());
+        let z = try!(bytes.read_f32::<LittleEndian>());
+        let mut cal_mat = [[0.0; 12]; 12];
+        for row in cal_mat.iter_mut() {
+            for val in row.iter_mut() {
+                *val = try!(bytes.read_f32::<LittleEndian>());
+            }
+        }
+        let mut corners = [Point3::new(0.0, 0.0, 0.0); 4];
+        for corner in corners.iter_mut() {
+            let x = try!(bytes.read_f32::<LittleEndian>());
+            let y = try!(bytes.read_f32::<LittleEndian>());
+            let z = try!(bytes.read_f32::<LittleEndian>());
+            *corner = Point3::new(x, y, z);
+        }
+        let p_type = try!(bytes.read_i32::<LittleEndian>());
+        let c_type = try!(bytes.read_i32::<LittleEndian>());
+        let num_channels = try!(read_count(bytes,
+                                           "max_force_plate_samples",
+                                           limits.max_force_plate_samples)
+            .field("channel_names"));
+        let mut names = Vec::with_capacity(capacity(num_channels));
+        for i in 0..num_channels as usize {
+            let name = read_cstring(bytes, limits.max_string_length);
+            names.push(try!(name.index(i).field("channel_names")));
+        }
+        Ok(ForcePlate {
+            id: id,
+            serial_number: serial,
+            width: width,
+            length: length,
+            origin: Vector3::new(x, y, z),
+            calibration_matrix: cal_mat,
+            corners: corners,
+            plate_type: p_type,
+            channel_data_type: c_type,
+            channel_names: names,
+        })
+    }
+}
+
+impl Pack for ForcePlate {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(write_cstring(&self.serial_number, bytes));
+        try!(bytes.write_f32::<LittleEndian>(self.width));
+        try!(bytes.write_f32::<LittleEndian>(self.length));
+        try!(bytes.write_f32::<LittleEndian>(self.origin.x));
+        try!(bytes.write_f32::<LittleEndian>(self.origin.y));
+        try!(bytes.write_f32::<LittleEndian>(self.origin.z));
+        for row in &self.calibration_matrix {
+            for val in row {
+                try!(bytes.write_f32::<LittleEndian>(*val));
+            }
+        }
+        for corner in &self.corners {
+            try!(bytes.write_f32::<LittleEndian>(corner.x));
+            try!(bytes.write_f32::<LittleEndian>(corner.y));
+            try!(bytes.write_f32::<LittleEndian>(corner.z));
+        }
+        try!(bytes.write_i32::<LittleEndian>(self.plate_type));
+        try!(bytes.write_i32::<LittleEndian>(self.channel_data_type));
+        try!(bytes.write_i32::<LittleEndian>(self.channel_names.len() as i32));
+        for name in &self.channel_names {
+            try!(write_cstring(name, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Unpack<Device> for Device {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Device> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+        let serial = try!(read_cstring(bytes, limits.max_string_length).field("serial_number"));
+        let d_type = try!(bytes.read_i32::<LittleEndian>());
+        let c_type = try!(bytes.read_i32::<LittleEndian>());
+        let num_channels = try!(read_count(bytes, "max_device_samples", limits.max_device_samples)
+            .field("channel_names"));
+        let mut names = Vec::with_capacity(capacity(num_channels));
+        for i in 0..num_channels as usize {
+            let name = read_cstring(bytes, limits.max_string_length);
+            names.push(try!(name.index(i).field("channel_names")));
+        }
+        Ok(Device {
+            id: id,
+            name: name,
+            serial_number: serial,
+            device_type: d_type,
+            channel_data_type: c_type,
+            channel_names: names,
+        })
+    }
+}
+
+impl Pack for Device {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(write_cstring(&self.name, bytes));
+        try!(write_cstring(&self.serial_number, bytes));
+        try!(bytes.write_i32::<LittleEndian>(self.device_type));
+        try!(bytes.write_i32::<LittleEndian>(self.channel_data_type));
+        try!(bytes.write_i32::<LittleEndian>(self.channel_names.len() as i32));
+        for name in &self.channel_names {
+            try!(write_cstring(name, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl Unpack<Camera> for Camera {
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Camera> {
+        let name = try!(read_cstring(bytes, limits.max_string_length).field("name"));
+        let x = try!(bytes.read_f32::<LittleEndian>());
+        let y = try!(bytes.read_f32::<LittleEndian>());
+        let z = try!(bytes.read_f32::<LittleEndian>());
+        let orient = try!(Quaternion::unpack(ver, limits, bytes));
+        Ok(Camera {
+            name: name,
+            position: Point3::new(x, y, z),
+            orientation: orient,
+        })
+    }
+}
+
+impl Pack for Camera {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_f32::<LittleEndian>(self.position.x));
+        try!(bytes.write_f32::<LittleEndian>(self.position.y));
+        try!(bytes.write_f32::<LittleEndian>(self.position.z));
+        try!(self.orientation.pack(ver, bytes));
+        Ok(())
+    }
+}
@@ -0,0 +1,498 @@
+//! Recording file format for `NatNet` sessions
+//!
+//! A recording stores the packets received from a `NatNet` server as they
+//! arrived, together with the host time at which each was received, so that
+//! a session can be decoded or replayed exactly later on.
+//!
+//! # Format
+//! A recording starts with the magic bytes `NNREC` and a format version byte,
+//! followed by records. All integers are little endian. Each record is:
+//!
+//! * Kind of record, `u8`
+//! * Receive timestamp in nanoseconds since the UNIX epoch, `u64`
+//! * Size of the payload, `u32`
+//! * Payload
+//! * CRC-32 of all of the above, `u32`
+//!
+//! The first record holds the `Sender` of the server, which gives the
+//! `NatNet` version of the records following it, and the second the model
+//! definitions as a `NatNetResponse::ModelDef` message. Later sender and model
+//! definition records replace these, all other records hold a raw packet.
+//!
+//! Records are only ever appended, each with a single write, so a crash can
+//! at worst leave an incomplete record at the end, either cut short or with
+//! bytes which never reached the disk and fail the CRC. Readers ignore such a
+//! record and `RecordingWriter::append` cuts it off before appending.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::recording::{RecordingReader, RecordingWriter};
+//!
+//! let mut writer = try!(RecordingWriter::from_client(try!(File::create("take.nnrec")),
+//!                                                    &client));
+//! loop {
+//!     let frame = try!(writer.record(&mut client));
+//! }
+//!
+//! for msg in try!(RecordingReader::new(BufReader::new(try!(File::open("take.nnrec"))))) {
+//!     let (timestamp, msg) = try!(msg);
+//! }
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+use client::Client;
+use model::DataSet;
+use semver::Version;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use super::{DecodeLimits, FrameOfData, NatNet, NatNetResponse, Pack, ParseError, Result, Sender,
+            Unpack};
+
+const MAGIC: &'static [u8] = b"NNREC";
+const FORMAT_VERSION: u8 = 1;
+// Magic bytes and format version at the start of a recording
+pub(crate) const FILE_HEADER_SIZE: usize = 6;
+// Kind, timestamp and payload size
+const RECORD_HEADER_SIZE: usize = 1 + 8 + 4;
+// Guards against allocating huge buffers for a corrupt payload size
+const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+const KIND_SENDER: u8 = 1;
+const KIND_MODELS: u8 = 2;
+const KIND_PACKET: u8 = 3;
+
+/// Record in a recording
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    /// The server, and `NatNet` version, of the following records
+    Sender(Sender),
+    /// Model definitions valid from this record on
+    Models(Vec<DataSet>),
+    /// Raw packet as received from the server
+    Packet(Vec<u8>),
+}
+
+/// Outcome of reading the raw bytes of a record
+enum Raw {
+    Record(u8, SystemTime, Vec<u8>),
+    // The recording ends in the middle of a record, or with a record which
+    // was not fully written
+    Incomplete,
+    End,
+}
+
+/// Write a recording
+///
+/// Each record is written with a single call to `write_all`. Wrapping a file
+/// in a `BufWriter` saves system calls, at the cost of losing the buffered
+/// records in a crash.
+#[derive(Debug)]
+pub struct RecordingWriter<W: Write> {
+    inner: W,
+    sender: Sender,
+}
+
+impl<W: Write> RecordingWriter<W> {
+    /// Start a new recording of a session with `sender` and `models`
+    ///
+    /// The header and the first two records are written together, so a
+    /// recording is never left without its sender or model definitions.
+    pub fn new(mut inner: W, sender: &Sender, models: &[DataSet]) -> Result<RecordingWriter<W>> {
+        let now = SystemTime::now();
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        try!(encode_record(KIND_SENDER, now, &try!(pack_sender(sender)), &mut bytes));
+        try!(encode_record(KIND_MODELS,
+                           now,
+                           &try!(pack_models(&sender.natnet_version, models)),
+                           &mut bytes));
+        try!(inner.write_all(&bytes));
+        Ok(RecordingWriter {
+            inner: inner,
+            sender: sender.clone(),
+        })
+    }
+
+    /// Start a new recording of the session of `client`
+    pub fn from_client(inner: W, client: &Client) -> Result<RecordingWriter<W>> {
+        RecordingWriter::new(inner, client.sender(), client.models())
+    }
+
+    /// The server of the records written from now on
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Record a change of server, or `NatNet` version
+    pub fn write_sender(&mut self, timestamp: SystemTime, sender: &Sender) -> Result<()> {
+        let payload = try!(pack_sender(sender));
+        try!(self.write_record(KIND_SENDER, timestamp, &payload));
+        self.sender = sender.clone();
+        Ok(())
+    }
+
+    /// Record new model definitions
+    pub fn write_models(&mut self, timestamp: SystemTime, models: &[DataSet]) -> Result<()> {
+        let payload = try!(pack_models(&self.sender.natnet_version, models));
+        self.write_record(KIND_MODELS, timestamp, &payload)
+    }
+
+    /// Record a raw packet received at `timestamp`
+    pub fn write_packet(&mut self, timestamp: SystemTime, bytes: &[u8]) -> Result<()> {
+        self.write_record(KIND_PACKET, timestamp, bytes)
+    }
+
+    /// Receive the next frame of data from `client` and record it
+    ///
+    /// New model definitions are requested and recorded when the frame says
+    /// the tracked models changed, and the server is recorded again when the
+    /// client connected to a different server or version.
+    pub fn record(&mut self, client: &mut Client) -> Result<FrameOfData> {
+        let frame = try!(client.recv_frame());
+        let now = SystemTime::now();
+        if client.sender() != &self.sender {
+            let sender = client.sender().clone();
+            try!(self.write_sender(now, &sender));
+            try!(self.write_models(now, client.models()));
+        }
+        try!(self.write_packet(now, client.last_packet()));
+        if frame.tracked_models_changed == Some(true) {
+            debug!("Tracked models changed, recording new model definitions");
+            let models = try!(client.request_models()).to_vec();
+            try!(self.write_models(now, &models));
+        }
+        Ok(frame)
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(try!(self.inner.flush()))
+    }
+
+    /// The underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Consume the recording writer returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_record(&mut self, kind: u8, timestamp: SystemTime, payload: &[u8]) -> Result<()> {
+        let mut bytes = Vec::with_capacity(record_size(payload) as usize);
+        try!(encode_record(kind, timestamp, payload, &mut bytes));
+        Ok(try!(self.inner.write_all(&bytes)))
+    }
+}
+
+impl RecordingWriter<File> {
+    /// Continue a recording in `file`
+    ///
+    /// An incomplete record at the end, left by a crash, is removed first.
+    /// This includes a last record failing the CRC, since its bytes may not
+    /// have reached the disk.
+    /// Recordings which are corrupt elsewhere result in
+    /// `ParseError::CorruptRecording`.
+    pub fn append(mut file: File) -> Result<RecordingWriter<File>> {
+        try!(file.seek(SeekFrom::Start(0)));
+        let (end, sender) = {
+            let mut reader = try!(RecordingReader::new(BufReader::new(&mut file)));
+            while try!(reader.next_record()).is_some() {}
+            (reader.offset(), reader.sender().clone())
+        };
+        try!(file.set_len(end));
+        try!(file.seek(SeekFrom::Start(end)));
+        Ok(RecordingWriter {
+            inner: file,
+            sender: sender,
+        })
+    }
+}
+
+/// Read a recording
+///
+/// Iterating over the reader gives each record with its receive timestamp as
+/// a `NatNetResponse`. Packets are decoded using the `NatNet` version of the
+/// latest sender record, sender records become a `NatNetResponse::Ping` and
+/// model definitions a `NatNetResponse::ModelDef`. A packet which fails to
+/// decode results in an error, but iteration can continue with the next
+/// record. A corrupt record ends the iteration after the error.
+#[derive(Debug)]
+pub struct RecordingReader<R> {
+    reader: R,
+    parser: NatNet,
+    sender: Sender,
+    models: Vec<DataSet>,
+    offset: u64,
+    incomplete: bool,
+    done: bool,
+}
+
+impl<R: Read> RecordingReader<R> {
+    /// Read the header of the recording in `reader`
+    pub fn new(mut reader: R) -> Result<RecordingReader<R>> {
+        let mut magic = [0u8; FILE_HEADER_SIZE];
+        if try!(read_full(&mut reader, &mut magic)) < magic.len() ||
+           &magic[..MAGIC.len()] != MAGIC || magic[MAGIC.len()] != FORMAT_VERSION {
+            return Err(ParseError::CorruptRecording { offset: 0 });
+        }
+        let mut offset = FILE_HEADER_SIZE as u64;
+        let sender = match try!(read_raw(&mut reader, offset)) {
+            Raw::Record(KIND_SENDER, _, payload) => {
+                offset += record_size(&payload);
+                try!(unpack_sender(&payload))
+            }
+            Raw::Record(..) => return Err(ParseError::CorruptRecording { offset: offset }),
+            _ => return Err(ParseError::NotEnoughBytes),
+        };
+        let parser = NatNet::new(sender.natnet_version.clone());
+        let models = match try!(read_raw(&mut reader, offset)) {
+            Raw::Record(KIND_MODELS, _, payload) => {
+                let models = try!(unpack_models(&parser, &payload, offset));
+                offset += record_size(&payload);
+                models
+            }
+            Raw::Record(..) => return Err(ParseError::CorruptRecording { offset: offset }),
+            _ => return Err(ParseError::NotEnoughBytes),
+        };
+        Ok(RecordingReader {
+            reader: reader,
+            parser: parser,
+            sender: sender,
+            models: models,
+            offset: offset,
+            incomplete: false,
+            done: false,
+        })
+    }
+
+    /// The server of the records read so far
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// The model definitions valid for the records read so far
+    pub fn models(&self) -> &[DataSet] {
+        &self.models
+    }
+
+    /// The parser used for packets read so far
+    pub fn parser(&self) -> &NatNet {
+        &self.parser
+    }
+
+    /// Byte offset of the end of the last complete record read
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Did the recording end with an incomplete record?
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// Read the next record without decoding packets
+    ///
+    /// Returns `Ok(None)` at the end of the recording.
+    pub fn next_record(&mut self) -> Result<Option<(SystemTime, Record)>> {
+        if self.done {
+            return Ok(None);
+        }
+        let start = self.offset;
+        let raw = read_raw(&mut self.reader, start);
+        let (kind, timestamp, payload) = match raw {
+            Ok(Raw::Record(kind, timestamp, payload)) => (kind, timestamp, payload),
+            Ok(Raw::Incomplete) => {
+                warn!("Ignoring incomplete record at byte {} of recording", start);
+                self.incomplete = true;
+                self.done = true;
+                return Ok(None);
+            }
+            Ok(Raw::End) => {
+                self.done = true;
+                return Ok(None);
+            }
+            Err(err) => {
+                self.done = true;
+                return Err(err);
+            }
+        };
+        self.offset += record_size(&payload);
+        let record = match kind {
+            KIND_SENDER => {
+                let sender = try!(unpack_sender(&payload));
+                self.parser = NatNet::new(sender.natnet_version.clone());
+                self.sender = sender.clone();
+                Record::Sender(sender)
+            }
+            KIND_MODELS => {
+                let models = try!(unpack_models(&self.parser, &payload, start));
+                self.models = models.clone();
+                Record::Models(models)
+            }
+            KIND_PACKET => Record::Packet(payload),
+            _ => return Err(ParseError::CorruptRecording { offset: start }),
+        };
+        Ok(Some((timestamp, record)))
+    }
+
+    /// Consume the recording reader returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> RecordingReader<R> {
+    /// Continue reading at the record starting at byte `offset`
+    ///
+    /// The offset must be the start of a record, such as one returned by
+    /// `offset`. The current sender and model definitions are kept until
+    /// records replacing them are read.
+    pub fn seek(&mut self, offset: u64) -> Result<()> {
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        self.offset = offset;
+        self.incomplete = false;
+        self.done = false;
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = Result<(SystemTime, NatNetResponse)>;
+
+    fn next(&mut self) -> Option<Result<(SystemTime, NatNetResponse)>> {
+        let (timestamp, record) = match self.next_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => return None,
+            Err(err) => return Some(Err(err)),
+        };
+        let msg = match record {
+            Record::Sender(sender) => Ok(NatNetResponse::Ping(sender)),
+            Record::Models(models) => Ok(NatNetResponse::ModelDef(models)),
+            Record::Packet(bytes) => self.parser.unpack(&mut bytes.as_slice()),
+        };
+        Some(msg.map(|msg| (timestamp, msg)))
+    }
+}
+
+/// Size of a record with `payload` in a recording
+fn record_size(payload: &[u8]) -> u64 {
+    (RECORD_HEADER_SIZE + payload.len() + 4) as u64
+}
+
+/// Append the record of `kind` with `payload` to `bytes`
+fn encode_record(kind: u8,
+                 timestamp: SystemTime,
+                 payload: &[u8],
+                 bytes: &mut Vec<u8>)
+                 -> Result<()> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(ParseError::MessageTooLarge(payload.len()));
+    }
+    let start = bytes.len();
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    header[0] = kind;
+    LittleEndian::write_u64(&mut header[1..], to_nanos(timestamp));
+    LittleEndian::write_u32(&mut header[9..], payload.len() as u32);
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(payload);
+    let mut crc = [0u8; 4];
+    LittleEndian::write_u32(&mut crc, crc32(&bytes[start..]));
+    bytes.extend_from_slice(&crc);
+    Ok(())
+}
+
+fn pack_sender(sender: &Sender) -> Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(264);
+    try!(sender.pack(&sender.natnet_version, &mut payload));
+    Ok(payload)
+}
+
+fn pack_models(ver: &Version, models: &[DataSet]) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    try!(NatNet::pack_with(ver, &NatNetResponse::ModelDef(models.to_vec()), &mut payload));
+    Ok(payload)
+}
+
+fn unpack_sender(payload: &[u8]) -> Result<Sender> {
+    // The sender is the same for all versions
+    Sender::unpack(&Version::parse("0.0.0").unwrap(),
+                   &DecodeLimits::default(),
+                   &mut &payload[..])
+}
+
+fn unpack_models(parser: &NatNet, payload: &[u8], offset: u64) -> Result<Vec<DataSet>> {
+    match try!(parser.unpack(&mut &payload[..])) {
+        NatNetResponse::ModelDef(models) => Ok(models),
+        _ => Err(ParseError::CorruptRecording { offset: offset }),
+    }
+}
+
+/// Read the record starting at `offset` in the recording
+fn read_raw<R: Read>(reader: &mut R, offset: u64) -> Result<Raw> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    match try!(read_full(reader, &mut header)) {
+        0 => return Ok(Raw::End),
+        n if n < header.len() => return Ok(Raw::Incomplete),
+        _ => {}
+    }
+    let len = LittleEndian::read_u32(&header[9..]) as usize;
+    if len > MAX_PAYLOAD {
+        return Err(ParseError::CorruptRecording { offset: offset });
+    }
+    let mut payload = vec![0; len + 4];
+    if try!(read_full(reader, &mut payload)) < payload.len() {
+        return Ok(Raw::Incomplete);
+    }
+    let crc = LittleEndian::read_u32(&payload[len..]);
+    payload.truncate(len);
+    let mut record = header.to_vec();
+    record.extend_from_slice(&payload);
+    if crc32(&record) != crc {
+        // Nothing following the record means the crash happened before all
+        // of it was written to disk
+        if try!(read_full(reader, &mut [0u8; 1])) == 0 {
+            return Ok(Raw::Incomplete);
+        }
+        return Err(ParseError::CorruptRecording { offset: offset });
+    }
+    let nanos = LittleEndian::read_u64(&header[1..]);
+    let timestamp = UNIX_EPOCH +
+                    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+    Ok(Raw::Record(header[0], timestamp, payload))
+}
+
+/// Fill `buf` from `reader`, returning less than its size only at the end
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Nanoseconds since the UNIX epoch, zero for earlier times
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// CRC-32 as used by zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
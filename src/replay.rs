@@ -0,0 +1,389 @@
+//! Real-time replay of recordings
+//!
+//! A `Replayer` plays a recording back as if the server it was recorded from
+//! were live: packets are sent with the time between them as they were
+//! received, scaled by a speed factor, to a UDP address or to a channel in
+//! the same process. Optionally pings and model definition requests are
+//! answered with the sender and model definitions of the recording at the
+//! current point of the replay, so unmodified clients can connect.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::replay::{Destination, ReplayConfig, Replayer};
+//!
+//! let replayer = try!(Replayer::open("take.nnrec",
+//!                                    Destination::Udp("239.255.42.99:1511".parse().unwrap()),
+//!                                    ReplayConfig::default()));
+//! replayer.set_speed(0.5);
+//! replayer.wait();
+//! ```
+
+use client::COMMAND_PORT;
+use model::DataSet;
+use recording::{FILE_HEADER_SIZE, Record, RecordingReader};
+use server::{ping_reply, serve};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use super::{NatNet, NatNetMsgType, NatNetResponse, Result, Sender, lock};
+
+/// Where a `Replayer` sends packets
+#[derive(Clone, Debug)]
+pub enum Destination {
+    /// Send packets to a UDP address, usually a multicast group
+    Udp(SocketAddr),
+    /// Send raw packets to a channel
+    ///
+    /// Playback stops when the receiver is dropped.
+    Channel(mpsc::Sender<Vec<u8>>),
+}
+
+/// Configuration of a `Replayer`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayConfig {
+    /// Playback speed, `2.0` plays twice as fast as recorded
+    pub speed: f64,
+    /// Start again from the beginning at the end of the recording
+    pub looping: bool,
+    /// Start paused, see `Replayer::step`
+    pub paused: bool,
+    /// Local address to answer requests on, `None` to not answer requests
+    pub command_addr: Option<SocketAddr>,
+    /// Local interface to send packets from
+    pub interface: Ipv4Addr,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> ReplayConfig {
+        ReplayConfig {
+            speed: 1.0,
+            looping: false,
+            paused: false,
+            command_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                                               COMMAND_PORT)),
+            interface: Ipv4Addr::new(0, 0, 0, 0),
+        }
+    }
+}
+
+/// State shared between the `Replayer` and its threads
+#[derive(Debug)]
+struct State {
+    speed: f64,
+    paused: bool,
+    // Packets to send while paused
+    steps: usize,
+    // Should the threads stop?
+    stop: bool,
+    finished: bool,
+    sent: u64,
+    sender: Sender,
+    models: Vec<DataSet>,
+    // Last packet sent, the reply to a frame of data request
+    last: Option<Vec<u8>>,
+}
+
+/// Replay a recording in real time
+///
+/// Packets are sent from a background thread, and requests answered from
+/// another, until the end of the recording or until the replayer is dropped.
+/// Sender and model definition records only change the replies to requests.
+///
+/// After a pause, and after looping, playback continues with the next packet
+/// right away. Looping playback of a recording without packets finishes after
+/// one pass. A record which can not be read ends playback, also when looping.
+#[derive(Debug)]
+pub struct Replayer {
+    state: Arc<(Mutex<State>, Condvar)>,
+    command_addr: Option<SocketAddr>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Replayer {
+    /// Replay the recording at `path`
+    pub fn open<P: AsRef<Path>>(path: P,
+                                destination: Destination,
+                                config: ReplayConfig)
+                                -> Result<Replayer> {
+        let reader = try!(RecordingReader::new(BufReader::new(try!(File::open(path)))));
+        Replayer::start(reader, destination, config)
+    }
+
+    /// Replay the recording read by `reader`
+    ///
+    /// Playback starts at the current position of the reader.
+    pub fn start<R>(reader: RecordingReader<R>,
+                    destination: Destination,
+                    config: ReplayConfig)
+                    -> Result<Replayer>
+        where R: Read + Seek + Send + 'static
+    {
+        let socket = match destination {
+            Destination::Udp(_) => Some(try!(UdpSocket::bind((config.interface, 0)))),
+            Destination::Channel(_) => None,
+        };
+        let command = match config.command_addr {
+            Some(addr) => {
+                let command = try!(UdpSocket::bind(addr));
+                try!(command.set_read_timeout(Some(Duration::from_millis(50))));
+                Some(command)
+            }
+            None => None,
+        };
+        let command_addr = match command {
+            Some(ref command) => Some(try!(command.local_addr())),
+            None => None,
+        };
+        let state = Arc::new((Mutex::new(State {
+                                  speed: config.speed,
+                                  paused: config.paused,
+                                  steps: 0,
+                                  stop: false,
+                                  finished: false,
+                                  sent: 0,
+                                  sender: reader.sender().clone(),
+                                  models: reader.models().to_vec(),
+                                  last: None,
+                              }),
+                              Condvar::new()));
+        let mut threads = Vec::with_capacity(2);
+        if let Some(command) = command {
+            let state = state.clone();
+            let data_addr = match destination {
+                Destination::Udp(addr) => Some(addr),
+                Destination::Channel(_) => None,
+            };
+            threads.push(thread::spawn(move || answer(&command, data_addr, &state)));
+        }
+        {
+            let state = state.clone();
+            let looping = config.looping;
+            threads.push(thread::spawn(move || {
+                play(reader, &destination, socket.as_ref(), looping, &state);
+                let (ref mutex, ref cvar) = *state;
+                lock(mutex).finished = true;
+                cvar.notify_all();
+            }));
+        }
+        Ok(Replayer {
+            state: state,
+            command_addr: command_addr,
+            threads: threads,
+        })
+    }
+
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        let (ref mutex, ref cvar) = *self.state;
+        f(&mut lock(mutex));
+        cvar.notify_all();
+    }
+
+    /// Pause playback
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    /// Continue playback after a pause
+    pub fn resume(&self) {
+        self.update(|state| {
+            state.paused = false;
+            state.steps = 0;
+        });
+    }
+
+    /// Send the next packet while paused
+    pub fn step(&self) {
+        self.update(|state| state.steps += 1);
+    }
+
+    /// Change the playback speed
+    ///
+    /// The new speed applies from the next packet on. Speeds which are not
+    /// positive are ignored.
+    pub fn set_speed(&self, speed: f64) {
+        if speed > 0.0 {
+            self.update(|state| state.speed = speed);
+        } else {
+            warn!("Ignoring replay speed of {}", speed);
+        }
+    }
+
+    /// Is playback paused?
+    pub fn is_paused(&self) -> bool {
+        lock(&self.state.0).paused
+    }
+
+    /// Has playback reached the end of the recording?
+    pub fn is_finished(&self) -> bool {
+        lock(&self.state.0).finished
+    }
+
+    /// Number of packets sent so far
+    pub fn sent(&self) -> u64 {
+        lock(&self.state.0).sent
+    }
+
+    /// Local address requests are answered on
+    pub fn command_addr(&self) -> Option<SocketAddr> {
+        self.command_addr
+    }
+
+    /// Block until playback reaches the end of the recording
+    ///
+    /// Never returns when looping over packets, unless a record can not be
+    /// read.
+    pub fn wait(&self) {
+        let (ref mutex, ref cvar) = *self.state;
+        let mut state = lock(mutex);
+        while !state.finished {
+            state = cvar.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Drop for Replayer {
+    /// Stop and wait for the background threads
+    fn drop(&mut self) {
+        self.update(|state| state.stop = true);
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Send the packets read by `reader` to `destination` in real time
+fn play<R: Read + Seek>(mut reader: RecordingReader<R>,
+                        destination: &Destination,
+                        socket: Option<&UdpSocket>,
+                        looping: bool,
+                        state: &(Mutex<State>, Condvar)) {
+    let (ref mutex, ref cvar) = *state;
+    // Time at which the last packet was due, and when it was recorded
+    let mut anchor: Option<(Instant, SystemTime)> = None;
+    // Has a packet been sent since rewinding, `None` before the first rewind
+    let mut sent_pass: Option<bool> = None;
+    loop {
+        if lock(mutex).stop {
+            return;
+        }
+        let (timestamp, bytes) = match reader.next_record() {
+            Ok(Some((timestamp, Record::Packet(bytes)))) => (timestamp, bytes),
+            Ok(Some((_, Record::Sender(sender)))) => {
+                lock(mutex).sender = sender;
+                continue;
+            }
+            Ok(Some((_, Record::Models(models)))) => {
+                lock(mutex).models = models;
+                continue;
+            }
+            Ok(None) if looping => {
+                if sent_pass == Some(false) {
+                    warn!("No packets in recording, stopping replay");
+                    return;
+                }
+                debug!("End of recording, replaying from the start");
+                if let Err(err) = reader.seek(FILE_HEADER_SIZE as u64) {
+                    warn!("Failed to rewind recording: {}", err);
+                    return;
+                }
+                anchor = None;
+                sent_pass = Some(false);
+                continue;
+            }
+            Ok(None) => return,
+            Err(err) => {
+                // The reader can not continue after a corrupt record, and
+                // looping would only replay the records before it
+                warn!("Stopping replay at record which can not be read: {}", err);
+                return;
+            }
+        };
+        {
+            let mut state = lock(mutex);
+            loop {
+                if state.stop {
+                    return;
+                }
+                if state.paused {
+                    anchor = None;
+                    if state.steps > 0 {
+                        state.steps -= 1;
+                        break;
+                    }
+                    state = cvar.wait(state).unwrap_or_else(|e| e.into_inner());
+                    continue;
+                }
+                let now = Instant::now();
+                let due = match anchor {
+                    Some((due, recorded)) => {
+                        let gap = timestamp.duration_since(recorded).unwrap_or(Duration::new(0, 0));
+                        due + scale(gap, state.speed)
+                    }
+                    None => now,
+                };
+                if now >= due {
+                    anchor = Some((due, timestamp));
+                    break;
+                }
+                state = cvar.wait_timeout(state, due - now).unwrap_or_else(|e| e.into_inner()).0;
+            }
+            state.sent += 1;
+            state.last = Some(bytes.clone());
+        }
+        sent_pass = sent_pass.map(|_| true);
+        match *destination {
+            Destination::Udp(addr) => {
+                if let Some(socket) = socket {
+                    if let Err(err) = socket.send_to(&bytes, addr) {
+                        warn!("Failed to send packet to {}: {}", addr, err);
+                    }
+                }
+            }
+            Destination::Channel(ref tx) => {
+                if tx.send(bytes).is_err() {
+                    debug!("Replay channel closed, stopping");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Time between packets played at `speed`
+fn scale(gap: Duration, speed: f64) -> Duration {
+    let nanos = gap.as_secs() as f64 * 1e9 + gap.subsec_nanos() as f64;
+    let nanos = (nanos / speed) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Answer requests on `command` until the replayer stops
+///
+/// Pings are answered with the recorded sender, model definition requests
+/// with the recorded model definitions and frame of data requests with the
+/// last packet sent.
+fn answer(command: &UdpSocket, data_addr: Option<SocketAddr>, state: &(Mutex<State>, Condvar)) {
+    let mutex = &state.0;
+    serve(command, || lock(mutex).stop, |_, msg_id, _| {
+        let state = lock(mutex);
+        let reply = match NatNetMsgType::try_from(msg_id) {
+            Ok(NatNetMsgType::Ping) => ping_reply(&state.sender, data_addr),
+            Ok(NatNetMsgType::RequestModelDef) => NatNetResponse::ModelDef(state.models.clone()),
+            Ok(NatNetMsgType::RequestFrameOfData) => return state.last.clone(),
+            Ok(NatNetMsgType::KeepAlive) |
+            Ok(NatNetMsgType::Disconnect) => return None,
+            _ => NatNetResponse::UnrecognizedRequest,
+        };
+        let mut bytes = Vec::new();
+        if let Err(e) = NatNet::pack_with(&state.sender.natnet_version, &reply, &mut bytes) {
+            warn!("Failed to pack reply {:?}: {}", reply, e);
+            return None;
+        }
+        Some(bytes)
+    })
+}
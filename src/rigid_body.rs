@@ -1,9 +1,9 @@
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use marker::Marker;
 use nalgebra::Quaternion;
 use semver::Version;
-use std::io::BufRead;
-use super::{Result, Unpack};
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, capacity, read_count};
 
 /// A set of `Marker`s creating a rigid body
 #[derive(Clone, Debug, PartialEq)]
@@ -14,11 +14,11 @@ pub struct RigidBody {
     pub position: Marker,
     /// Orientation represented as a quaternion
     pub orientation: Quaternion<f32>,
-    /// List of markers comprising this body
+    /// List of markers comprising this body (NatNet < 3.0)
     pub markers: Vec<Marker>,
-    /// List of marker IDs
+    /// List of marker IDs (NatNet < 3.0)
     pub marker_ids: Vec<i32>,
-    /// List of marker sizes
+    /// List of marker sizes (NatNet < 3.0)
     pub marker_sizes: Vec<f32>,
     /// Mean error for markers
     pub mean_error: f32,
@@ -27,28 +27,40 @@ pub struct RigidBody {
 }
 
 impl Unpack<RigidBody> for RigidBody {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<RigidBody> {
+    fn unpack<B: BufRead>(ver: &Version,
+                          limits: &DecodeLimits,
+                          bytes: &mut B)
+                          -> Result<RigidBody> {
         // Unpack Rigid body according to `PacketClient.cpp` lines 667:738
-        let id = try!(bytes.read_i32::<LittleEndian>());
-        let pos = try!(Marker::unpack(ver, bytes));
-        let orient = try!(Quaternion::unpack(ver, bytes));
-        let num_markers = try!(bytes.read_i32::<LittleEndian>());
-        let mut markers = Vec::with_capacity(num_markers as usize);
-        let mut ids = Vec::with_capacity(num_markers as usize);
-        let mut sizes = Vec::with_capacity(num_markers as usize);
-        // NOTE: All markers are consecutively, then IDs, then sizes
-        // See: lines 684:710
-        // FIXME: Should data be presented differently to users?
-        for _ in 0..num_markers {
-            markers.push(try!(Marker::unpack(ver, bytes)));
+        let id = try!(bytes.read_i32::<LittleEndian>().field("id"));
+        let pos = try!(Marker::unpack(ver, limits, bytes).field("position"));
+        let orient = try!(Quaternion::unpack(ver, limits, bytes).field("orientation"));
+        let mut markers = Vec::new();
+        let mut ids = Vec::new();
+        let mut sizes = Vec::new();
+        // Marker data was moved to the rigid body description in version 3.0
+        if *ver < Version::parse("3.0.0").unwrap() {
+            let num_markers = try!(read_count(bytes, "max_markers", limits.max_markers)
+                .field("markers"));
+            markers.reserve(capacity(num_markers));
+            ids.reserve(capacity(num_markers));
+            sizes.reserve(capacity(num_markers));
+            // NOTE: All markers are consecutively, then IDs, then sizes
+            // See: lines 684:710
+            // FIXME: Should data be presented differently to users?
+            for i in 0..num_markers as usize {
+                markers.push(try!(Marker::unpack(ver, limits, bytes).index(i).field("markers")));
+            }
+            for i in 0..num_markers as usize {
+                let id = bytes.read_i32::<LittleEndian>();
+                ids.push(try!(id.index(i).field("marker_ids")));
+            }
+            for i in 0..num_markers as usize {
+                let size = bytes.read_f32::<LittleEndian>();
+                sizes.push(try!(size.index(i).field("marker_sizes")));
+            }
         }
-        for _ in 0..num_markers {
-            ids.push(try!(bytes.read_i32::<LittleEndian>()));
-        }
-        for _ in 0..num_markers {
-            sizes.push(try!(bytes.read_f32::<LittleEndian>()));
-        }
-        let err = try!(bytes.read_f32::<LittleEndian>());
+        let err = try!(bytes.read_f32::<LittleEndian>().field("mean_error"));
         let track = if *ver >= Version::parse("2.6.0").unwrap() {
             let params = try!(bytes.read_i16::<LittleEndian>());
             Some(params & 0x01 > 0)
@@ -69,7 +81,7 @@ impl Unpack<RigidBody> for RigidBody {
 }
 
 impl Unpack<Quaternion<f32>> for Quaternion<f32> {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<Quaternion<f32>> {
+    fn unpack<B: BufRead>(_: &Version, _: &DecodeLimits, bytes: &mut B) -> Result<Quaternion<f32>> {
         let x = try!(bytes.read_f32::<LittleEndian>());
         let y = try!(bytes.read_f32::<LittleEndian>());
         let z = try!(bytes.read_f32::<LittleEndian>());
@@ -77,3 +89,53 @@ impl Unpack<Quaternion<f32>> for Quaternion<f32> {
         Ok(Quaternion::new(x, y, z, w))
     }
 }
+
+impl Pack for RigidBody {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(self.position.pack(ver, bytes));
+        try!(self.orientation.pack(ver, bytes));
+        if *ver < Version::parse("3.0.0").unwrap() {
+            // The number of markers decides how many IDs and sizes are read,
+            // so missing IDs and sizes are padded to keep the layout intact
+            try!(bytes.write_i32::<LittleEndian>(self.markers.len() as i32));
+            for marker in &self.markers {
+                try!(marker.pack(ver, bytes));
+            }
+            for i in 0..self.markers.len() {
+                try!(bytes.write_i32::<LittleEndian>(self.marker_ids
+                    .get(i)
+                    .cloned()
+                    .unwrap_or(0)));
+            }
+            for i in 0..self.markers.len() {
+                try!(bytes.write_f32::<LittleEndian>(self.marker_sizes
+                    .get(i)
+                    .cloned()
+                    .unwrap_or(0.0)));
+            }
+        }
+        try!(bytes.write_f32::<LittleEndian>(self.mean_error));
+        if *ver >= Version::parse("2.6.0").unwrap() {
+            let params = if self.valid_track.unwrap_or(false) {
+                0x01
+            } else {
+                0x00
+            };
+            try!(bytes.write_i16::<LittleEndian>(params));
+        }
+        Ok(())
+    }
+}
+
+impl Pack for Quaternion<f32> {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        // Mirror of `unpack` which passes the raw values straight to
+        // `Quaternion::new`
+        try!(bytes.write_f32::<LittleEndian>(self.w));
+        try!(bytes.write_f32::<LittleEndian>(self.i));
+        try!(bytes.write_f32::<LittleEndian>(self.j));
+        try!(bytes.write_f32::<LittleEndian>(self.k));
+        Ok(())
+    }
+}
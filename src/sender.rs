@@ -1,7 +1,9 @@
-use byteorder::ReadBytesExt;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use semver::{Version, Identifier};
-use std::io::BufRead;
-use super::{Result, Unpack, read_cstring};
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, ParseError, Unpack, read_cstring,
+            write_cstring};
 
 /// `NatNet` application identifier
 ///
@@ -22,7 +24,7 @@ pub struct Sender {
 }
 
 /// Helper function to unpack `NatNet` version into a `semver::Version`
-fn unpack_version<B: BufRead>(bytes: &mut B) -> Result<Version> {
+pub fn unpack_version<B: BufRead>(bytes: &mut B) -> Result<Version> {
     let v1 = try!(bytes.read_u8()) as u64;
     let v2 = try!(bytes.read_u8()) as u64;
     let v3 = try!(bytes.read_u8()) as u64;
@@ -36,13 +38,30 @@ fn unpack_version<B: BufRead>(bytes: &mut B) -> Result<Version> {
     })
 }
 
+/// Helper function to pack a `semver::Version` into `NatNet` version bytes
+pub fn pack_version<W: Write>(ver: &Version, bytes: &mut W) -> Result<()> {
+    let v4 = match ver.build.first() {
+        Some(&Identifier::Numeric(v)) => v,
+        _ => 0,
+    };
+    try!(bytes.write_u8(ver.major as u8));
+    try!(bytes.write_u8(ver.minor as u8));
+    try!(bytes.write_u8(ver.patch as u8));
+    try!(bytes.write_u8(v4 as u8));
+    Ok(())
+}
+
 impl Unpack<Sender> for Sender {
-    fn unpack<B: BufRead>(_: &Version, bytes: &mut B) -> Result<Sender> {
+    fn unpack<B: BufRead>(_: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Sender> {
         debug!("Unpacking application identifier");
-        let name = try!(read_cstring(bytes));
+        // The application name can be at most 255 bytes plus the `'\0'` byte
+        let name = try!(read_cstring(bytes, cmp::min(limits.max_string_length, 255)).field("name"));
         // NOTE: The application name always contains 256 bytes, so we need to
         // throw away the rest, the `-1` at the end is for the `'\0'` byte
-        bytes.consume(256 - name.as_bytes().len() - 1);
+        let padding = (256 - name.as_bytes().len() - 1) as u64;
+        if try!(io::copy(&mut (&mut *bytes).take(padding), &mut io::sink())) < padding {
+            return Err(ParseError::NotEnoughBytes);
+        }
         let ver = try!(unpack_version(bytes));
         let nat = try!(unpack_version(bytes));
         trace!("Found application name {:?}, version {} using NatNet version {}",
@@ -56,3 +75,20 @@ impl Unpack<Sender> for Sender {
         })
     }
 }
+
+impl Pack for Sender {
+    fn pack<W: Write>(&self, _: &Version, bytes: &mut W) -> Result<()> {
+        // The application name must fit in 256 bytes including `'\0'`
+        if self.name.as_bytes().len() > 255 {
+            return Err(ParseError::StringError {
+                bytes: self.name.clone().into_bytes(),
+                utf8_error: None,
+            });
+        }
+        try!(write_cstring(&self.name, bytes));
+        try!(bytes.write_all(&[0u8; 256][..256 - self.name.as_bytes().len() - 1]));
+        try!(pack_version(&self.version, bytes));
+        try!(pack_version(&self.natnet_version, bytes));
+        Ok(())
+    }
+}
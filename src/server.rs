@@ -0,0 +1,355 @@
+//! Blocking UDP server publishing frames of data over `NatNet`
+//!
+//! The server answers requests on its command port like Motive does, so
+//! trackers and simulators can feed `NatNet` clients. Frames of data given to
+//! `Server::publish` are sent at a fixed rate to a data address, usually a
+//! multicast group, and in unicast mode to every client which has connected
+//! and keeps sending keep-alive messages.
+//!
+//! # Example
+//! ```rust,ignore
+//! use natnet_decode::server::{Server, ServerConfig};
+//!
+//! let server = try!(Server::start(ServerConfig {
+//!     models: models,
+//!     ..ServerConfig::default()
+//! }));
+//! loop {
+//!     server.publish(tracker.next_frame());
+//! }
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+use client::{COMMAND_PORT, DATA_PORT, MAX_MESSAGE, MULTICAST_GROUP};
+use model::DataSet;
+use semver::Version;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use super::{FrameOfData, NatNet, NatNetMsgType, NatNetResponse, Result, Sender,
+            ServerDescription, lock};
+
+// How often the command thread checks if the server is stopping
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Configuration of a `Server`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    /// Local address to answer requests on
+    pub command_addr: SocketAddr,
+    /// Address frames of data are sent to, usually a multicast group, `None`
+    /// to only send frames of data to unicast clients
+    pub data_addr: Option<SocketAddr>,
+    /// Local interface to send frames of data from
+    pub interface: Ipv4Addr,
+    /// Application identifier the server answers pings with
+    ///
+    /// All messages are packed using the `NatNet` version of the sender.
+    pub sender: Sender,
+    /// Model definitions sent to clients
+    pub models: Vec<DataSet>,
+    /// Interval between frames of data
+    pub frame_interval: Duration,
+    /// Send frames of data to clients connecting in unicast mode
+    pub unicast: bool,
+    /// Stop sending frames of data to a unicast client when nothing has been
+    /// received from it for this long
+    pub client_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            command_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), COMMAND_PORT),
+            data_addr: Some(SocketAddr::new(IpAddr::V4(MULTICAST_GROUP), DATA_PORT)),
+            interface: Ipv4Addr::new(0, 0, 0, 0),
+            sender: Sender {
+                name: "NatNetDecode".to_string(),
+                version: Version::parse("0.1.0").unwrap(),
+                natnet_version: Version::parse("2.9.0").unwrap(),
+            },
+            models: Vec::new(),
+            frame_interval: Duration::from_millis(10),
+            unicast: false,
+            client_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// State shared between the `Server` and its threads
+#[derive(Debug)]
+struct State {
+    models: Vec<DataSet>,
+    frame: Option<FrameOfData>,
+    // Has `frame` been published since it was last sent?
+    fresh: bool,
+    // Unicast clients and when they were last heard from
+    clients: Vec<(SocketAddr, Instant)>,
+}
+
+/// Blocking `NatNet` server
+///
+/// Requests are answered, and frames of data sent, from two background
+/// threads which run until the server is dropped.
+///
+/// Each interval the most recently published frame of data is sent, if it has
+/// not been sent already. Frames published faster than the interval are
+/// therefore dropped, except for the last one. Clients requesting a frame of
+/// data get the most recent frame, even if it was already sent.
+#[derive(Debug)]
+pub struct Server {
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    command_addr: SocketAddr,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Start a server with the given configuration
+    pub fn start(config: ServerConfig) -> Result<Server> {
+        let command = try!(UdpSocket::bind(config.command_addr));
+        try!(command.set_read_timeout(Some(POLL_INTERVAL)));
+        let data = try!(UdpSocket::bind((config.interface, 0)));
+        let command_addr = try!(command.local_addr());
+        let state = Arc::new(Mutex::new(State {
+            models: config.models.clone(),
+            frame: None,
+            fresh: false,
+            clients: Vec::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::with_capacity(2);
+        {
+            let config = config.clone();
+            let command = try!(command.try_clone());
+            let state = state.clone();
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || {
+                serve(&command,
+                      || stop.load(Ordering::SeqCst),
+                      |client, msg_id, _| match reply(&config, &state, client, msg_id) {
+                          Ok(bytes) => bytes,
+                          Err(e) => {
+                              warn!("Failed to answer {}: {}", client, e);
+                              None
+                          }
+                      })
+            }));
+        }
+        {
+            let state = state.clone();
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || publish(&config, &command, &data, &state, &stop)));
+        }
+        debug!("NatNet server listening on {}", command_addr);
+        Ok(Server {
+            state: state,
+            stop: stop,
+            command_addr: command_addr,
+            threads: threads,
+        })
+    }
+
+    /// Publish a frame of data, replacing any frame not yet sent
+    pub fn publish(&self, frame: FrameOfData) {
+        let mut state = lock(&self.state);
+        state.frame = Some(frame);
+        state.fresh = true;
+    }
+
+    /// Replace the model definitions sent to clients
+    ///
+    /// Clients are not notified, they get the new definitions the next time
+    /// they request them.
+    pub fn set_models(&self, models: Vec<DataSet>) {
+        lock(&self.state).models = models;
+    }
+
+    /// Local address requests are answered on
+    pub fn command_addr(&self) -> SocketAddr {
+        self.command_addr
+    }
+
+    /// Addresses of the unicast clients frames of data are sent to
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        lock(&self.state).clients.iter().map(|&(addr, _)| addr).collect()
+    }
+}
+
+impl Drop for Server {
+    /// Stop and wait for the background threads
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Answer requests on `socket` until `stop` returns true
+///
+/// `reply` is given the client, the type and the raw bytes of each request,
+/// and returns the bytes to answer with, if any. The socket needs a read
+/// timeout for `stop` to be checked while no requests arrive.
+pub(crate) fn serve<S, F>(socket: &UdpSocket, stop: S, mut reply: F)
+    where S: Fn() -> bool,
+          F: FnMut(SocketAddr, u16, &[u8]) -> Option<Vec<u8>>
+{
+    let mut buf = vec![0; MAX_MESSAGE];
+    while !stop() {
+        let (len, client) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => {
+                warn!("Failed to receive request: {}", e);
+                continue;
+            }
+        };
+        if len < 4 {
+            debug!("Dropping request of {} bytes from {}", len, client);
+            continue;
+        }
+        if let Some(bytes) = reply(client, LittleEndian::read_u16(&buf), &buf[..len]) {
+            if let Err(e) = socket.send_to(&bytes, client) {
+                warn!("Failed to answer {}: {}", client, e);
+            }
+        }
+    }
+}
+
+/// Handle a request of type `msg_id` from `client`, returning the reply
+///
+/// In unicast mode clients are added by a connect request, kept by any later
+/// message and removed by a disconnect request. Like Motive, every ping is
+/// taken as a connect request, whatever options the client sent with it.
+fn reply(config: &ServerConfig,
+         state: &Mutex<State>,
+         client: SocketAddr,
+         msg_id: u16)
+         -> Result<Option<Vec<u8>>> {
+    let ver = &config.sender.natnet_version;
+    let mut state = lock(state);
+    if config.unicast {
+        let known = state.clients.iter().any(|&(addr, _)| addr == client);
+        state.clients.retain(|&(addr, _)| addr != client);
+        let connect = msg_id == NatNetMsgType::Ping as u16;
+        if connect && !known {
+            debug!("Unicast client {} connected", client);
+        }
+        if connect || (known && msg_id != NatNetMsgType::Disconnect as u16) {
+            state.clients.push((client, Instant::now()));
+        }
+    }
+    let msg = match NatNetMsgType::try_from(msg_id) {
+        // A connect request is a ping with options
+        Ok(NatNetMsgType::Ping) => {
+            debug!("Answering ping from {}", client);
+            ping_reply(&config.sender, config.data_addr)
+        }
+        Ok(NatNetMsgType::RequestModelDef) => NatNetResponse::ModelDef(state.models.clone()),
+        Ok(NatNetMsgType::RequestFrameOfData) => {
+            match state.frame {
+                Some(ref frame) => NatNetResponse::FrameOfData(frame.clone()),
+                None => {
+                    debug!("No frame of data to answer {} with", client);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(NatNetMsgType::KeepAlive) |
+        Ok(NatNetMsgType::Disconnect) => return Ok(None),
+        _ => {
+            debug!("Unrecognized request of type {} from {}", msg_id, client);
+            NatNetResponse::UnrecognizedRequest
+        }
+    };
+    let mut bytes = Vec::new();
+    try!(NatNet::pack_with(ver, &msg, &mut bytes));
+    Ok(Some(bytes))
+}
+
+/// Reply to a ping from a server identified by `sender`, sending frames of
+/// data to `data_addr`
+///
+/// Servers using `NatNet` 3.0 and later reply with a description of
+/// themselves.
+pub(crate) fn ping_reply(sender: &Sender, data_addr: Option<SocketAddr>) -> NatNetResponse {
+    if sender.natnet_version.major < 3 {
+        return NatNetResponse::Ping(sender.clone());
+    }
+    let (data_port, group) = match data_addr {
+        Some(SocketAddr::V4(addr)) => (addr.port(), *addr.ip()),
+        Some(SocketAddr::V6(addr)) => (addr.port(), Ipv4Addr::new(0, 0, 0, 0)),
+        None => (0, Ipv4Addr::new(0, 0, 0, 0)),
+    };
+    NatNetResponse::ServerInfo(ServerDescription {
+        sender: sender.clone(),
+        // Timestamps are not generated by the server
+        high_resolution_clock_frequency: 0,
+        data_port: data_port,
+        is_multicast: group.is_multicast(),
+        multicast_group: group,
+    })
+}
+
+/// Send published frames of data every interval until `stop` is set
+///
+/// Unicast clients are sent frames from the `command` socket, since that is
+/// where they listen.
+fn publish(config: &ServerConfig,
+           command: &UdpSocket,
+           data: &UdpSocket,
+           state: &Mutex<State>,
+           stop: &AtomicBool) {
+    let mut next = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        next += config.frame_interval;
+        let (frame, clients) = {
+            let mut state = lock(state);
+            let now = Instant::now();
+            let timeout = config.client_timeout;
+            state.clients.retain(|&(addr, seen)| {
+                let alive = now < seen + timeout;
+                if !alive {
+                    debug!("Unicast client {} timed out", addr);
+                }
+                alive
+            });
+            let frame = if state.fresh { state.frame.clone() } else { None };
+            state.fresh = false;
+            (frame, state.clients.iter().map(|&(addr, _)| addr).collect::<Vec<_>>())
+        };
+        if let Some(frame) = frame {
+            let mut bytes = Vec::new();
+            match NatNet::pack_with(&config.sender.natnet_version,
+                                    &NatNetResponse::FrameOfData(frame),
+                                    &mut bytes) {
+                Ok(()) => {
+                    if let Some(addr) = config.data_addr {
+                        if let Err(e) = data.send_to(&bytes, addr) {
+                            warn!("Failed to send frame of data to {}: {}", addr, e);
+                        }
+                    }
+                    for addr in clients {
+                        if let Err(e) = command.send_to(&bytes, addr) {
+                            warn!("Failed to send frame of data to {}: {}", addr, e);
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to pack frame of data: {}", e),
+            }
+        }
+        let now = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        } else {
+            // Do not try to catch up after falling behind
+            next = now;
+        }
+    }
+}
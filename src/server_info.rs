@@ -0,0 +1,64 @@
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use semver::Version;
+use sender::Sender;
+use std::io::{BufRead, Write};
+use std::net::Ipv4Addr;
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack};
+
+/// Size of a packed `ServerDescription`
+///
+/// Ping responses of at least this size are server descriptions.
+pub const SERVER_DESCRIPTION_SIZE: usize = 264 + 8 + 2 + 1 + 4;
+
+/// Description of a `NatNet` server
+///
+/// Servers using `NatNet` 3.0 and later answer a connect request, or ping,
+/// with this description. It extends the `Sender` with what a client needs
+/// to receive data from the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerDescription {
+    /// Application identifier of the server
+    pub sender: Sender,
+    /// Frequency of the clock used for high resolution timestamps, in ticks
+    /// per second
+    pub high_resolution_clock_frequency: u64,
+    /// Port the server sends frames of data to
+    pub data_port: u16,
+    /// Does the server multicast frames of data?
+    pub is_multicast: bool,
+    /// Multicast group the server sends frames of data to
+    pub multicast_group: Ipv4Addr,
+}
+
+impl Unpack<ServerDescription> for ServerDescription {
+    fn unpack<B: BufRead>(ver: &Version,
+                          limits: &DecodeLimits,
+                          bytes: &mut B)
+                          -> Result<ServerDescription> {
+        debug!("Unpacking server description");
+        let sender = try!(Sender::unpack(ver, limits, bytes));
+        let freq = try!(bytes.read_u64::<LittleEndian>().field("high_resolution_clock_frequency"));
+        let port = try!(bytes.read_u16::<LittleEndian>().field("data_port"));
+        let multicast = try!(bytes.read_u8().field("is_multicast"));
+        let mut group = [0u8; 4];
+        try!(bytes.read_exact(&mut group).field("multicast_group"));
+        Ok(ServerDescription {
+            sender: sender,
+            high_resolution_clock_frequency: freq,
+            data_port: port,
+            is_multicast: multicast != 0,
+            multicast_group: Ipv4Addr::from(group),
+        })
+    }
+}
+
+impl Pack for ServerDescription {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(self.sender.pack(ver, bytes));
+        try!(bytes.write_u64::<LittleEndian>(self.high_resolution_clock_frequency));
+        try!(bytes.write_u16::<LittleEndian>(self.data_port));
+        try!(bytes.write_u8(self.is_multicast as u8));
+        try!(bytes.write_all(&self.multicast_group.octets()));
+        Ok(())
+    }
+}
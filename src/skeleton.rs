@@ -1,8 +1,8 @@
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use rigid_body::RigidBody;
 use semver::Version;
-use std::io::BufRead;
-use super::{Result, Unpack};
+use std::io::{BufRead, Write};
+use super::{DecodeLimits, ErrorPath, Result, Pack, Unpack, capacity, read_count};
 
 /// A `Skeleton` is a collection of `RigidBody`
 #[derive(Clone, Debug, PartialEq)]
@@ -14,12 +14,12 @@ pub struct Skeleton {
 }
 
 impl Unpack<Skeleton> for Skeleton {
-    fn unpack<B: BufRead>(ver: &Version, bytes: &mut B) -> Result<Skeleton> {
+    fn unpack<B: BufRead>(ver: &Version, limits: &DecodeLimits, bytes: &mut B) -> Result<Skeleton> {
         let id = try!(bytes.read_i32::<LittleEndian>());
-        let num_bodies = try!(bytes.read_i32::<LittleEndian>());
-        let mut bodies = Vec::with_capacity(num_bodies as usize);
-        for _ in 0..num_bodies {
-            bodies.push(try!(RigidBody::unpack(ver, bytes)));
+        let num_bodies = try!(read_count(bytes, "max_bodies", limits.max_bodies).field("bones"));
+        let mut bodies = Vec::with_capacity(capacity(num_bodies));
+        for i in 0..num_bodies as usize {
+            bodies.push(try!(RigidBody::unpack(ver, limits, bytes).index(i).field("bones")));
         }
         Ok(Skeleton {
             id: id,
@@ -27,3 +27,14 @@ impl Unpack<Skeleton> for Skeleton {
         })
     }
 }
+
+impl Pack for Skeleton {
+    fn pack<W: Write>(&self, ver: &Version, bytes: &mut W) -> Result<()> {
+        try!(bytes.write_i32::<LittleEndian>(self.id));
+        try!(bytes.write_i32::<LittleEndian>(self.bones.len() as i32));
+        for bone in &self.bones {
+            try!(bone.pack(ver, bytes));
+        }
+        Ok(())
+    }
+}
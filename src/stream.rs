@@ -0,0 +1,248 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use super::{NatNet, NatNetMsgType, NatNetResponse, ParseError, Result, SizeCheck};
+
+/// Iterator over `NatNet` messages stored back to back in a reader
+///
+/// Created with `NatNet::messages`. Messages are decoded with the limits and
+/// size check of the parser.
+///
+/// The iterator ends at a clean end of the reader, that is when the last
+/// message is complete. If the reader ends in the middle of a message the
+/// iterator instead returns `ParseError::NotEnoughBytes`.
+///
+/// After a message fails to decode the iterator resynchronises on the next
+/// plausible message, a known message type which decodes using exactly the
+/// size in its header. The bytes between the corrupt message and this message
+/// are skipped.
+///
+/// # Example
+/// ```rust,ignore
+/// use natnet_decode::NatNet;
+///
+/// let parser = NatNet::new(ver);
+/// let mut messages = parser.messages(File::open("recording.bin").unwrap());
+/// while let Some(msg) = messages.next() {
+///     println!("Message at byte {}: {:?}", messages.offset(), msg);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Messages<'a, R> {
+    parser: &'a NatNet,
+    reader: R,
+    // Bytes read from `reader`, consumed up to `start`
+    buf: Vec<u8>,
+    start: usize,
+    // Offset in the stream of `buf[start]`
+    pos: usize,
+    // Offset in the stream of the last message returned
+    last: usize,
+    // Where to first look for the next message after an error
+    resync: Option<usize>,
+    done: bool,
+}
+
+/// Outcome of trying to decode a message at some point in the buffer
+enum Found {
+    Message(usize, Result<NatNetResponse>),
+    Implausible,
+    Truncated(usize),
+    End,
+}
+
+impl<'a, R: Read> Messages<'a, R> {
+    /// Create a new iterator over messages in `reader`
+    pub fn new(parser: &'a NatNet, reader: R) -> Messages<'a, R> {
+        Messages {
+            parser: parser,
+            reader: reader,
+            buf: Vec::new(),
+            start: 0,
+            pos: 0,
+            last: 0,
+            resync: None,
+            done: false,
+        }
+    }
+
+    /// Byte offset in the reader of the message last returned
+    ///
+    /// For errors this is the offset of the message which could not be
+    /// decoded.
+    pub fn offset(&self) -> usize {
+        self.last
+    }
+
+    /// Consume the iterator returning the underlying reader
+    ///
+    /// Bytes already read from the reader, but not yet returned as a message,
+    /// are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Number of buffered bytes not yet consumed
+    fn available(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    fn buffered(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+
+    /// Make sure that at least `len` bytes are buffered
+    ///
+    /// Returns `false` if the reader ended before that.
+    fn fill(&mut self, len: usize) -> io::Result<bool> {
+        if self.available() < len && self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        while self.buf.len() < len {
+            let have = self.buf.len();
+            self.buf.resize(len, 0);
+            let read = self.reader.read(&mut self.buf[have..]);
+            match read {
+                Ok(0) => {
+                    self.buf.truncate(have);
+                    return Ok(false);
+                }
+                Ok(n) => self.buf.truncate(have + n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(have),
+                Err(e) => {
+                    self.buf.truncate(have);
+                    self.done = true;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        self.pos += len;
+    }
+
+    /// Try to decode a message starting at `at` in the buffer
+    ///
+    /// If `resync` is set only known message types with a size within limits
+    /// are considered, and their payload must be used exactly.
+    fn decode_at(&mut self, at: usize, resync: bool) -> io::Result<Found> {
+        if !try!(self.fill(at + 4)) {
+            if self.available() <= at {
+                return Ok(Found::End);
+            }
+            return Ok(Found::Truncated(self.available() - at));
+        }
+        let msg_id = LittleEndian::read_u16(&self.buffered()[at..]);
+        let num_bytes = LittleEndian::read_u16(&self.buffered()[at + 2..]) as usize;
+        if resync &&
+           (NatNetMsgType::try_from(msg_id).is_err() ||
+            num_bytes > self.parser.limits.max_message_size) {
+            return Ok(Found::Implausible);
+        }
+        let len = 4 + num_bytes;
+        if !try!(self.fill(at + len)) {
+            return Ok(Found::Truncated(self.available() - at));
+        }
+        let parser = self.parser;
+        let mut bytes = &self.buffered()[at..at + len];
+        if resync {
+            let msg = NatNet::unpack_checked(&parser.ver,
+                                             &parser.limits,
+                                             SizeCheck::Strict,
+                                             &mut bytes);
+            match msg {
+                // Requests, unknown and empty messages are too easily found
+                // in arbitrary data
+                Ok(NatNetResponse::Unknown { .. }) |
+                Ok(NatNetResponse::Disconnect) |
+                Ok(NatNetResponse::DisconnectByTimeout) => Ok(Found::Implausible),
+                msg => Ok(Found::Message(len, msg)),
+            }
+        } else {
+            Ok(Found::Message(len, parser.unpack(&mut bytes)))
+        }
+    }
+
+    fn read_next(&mut self) -> Result<Option<NatNetResponse>> {
+        if let Some(end) = self.resync.take() {
+            // The corrupt message is still buffered, the next message most
+            // likely starts where its header said it would end
+            match try!(self.decode_at(end, true)) {
+                Found::Message(len, Ok(msg)) => {
+                    self.last = self.pos + end;
+                    self.consume(end + len);
+                    return Ok(Some(msg));
+                }
+                Found::End => {
+                    self.consume(end);
+                    return Ok(None);
+                }
+                _ => self.consume(1),
+            }
+            // Otherwise search for the next plausible header byte by byte
+            while try!(self.fill(4)) {
+                if let Found::Message(len, Ok(msg)) = try!(self.decode_at(0, true)) {
+                    self.last = self.pos;
+                    self.consume(len);
+                    return Ok(Some(msg));
+                }
+                self.consume(1);
+            }
+            let skipped = self.available();
+            debug!("Skipped {} bytes at end of stream", skipped);
+            self.consume(skipped);
+            return Ok(None);
+        }
+        self.last = self.pos;
+        match try!(self.decode_at(0, false)) {
+            Found::Message(len, Ok(msg)) => {
+                self.consume(len);
+                Ok(Some(msg))
+            }
+            Found::Message(len, Err(err)) => {
+                debug!("Corrupt message at byte {}, resynchronising", self.pos);
+                self.resync = Some(len);
+                Err(err)
+            }
+            Found::Truncated(available) => {
+                let msg_id = if available >= 2 {
+                    Some(LittleEndian::read_u16(self.buffered()))
+                } else {
+                    None
+                };
+                // The size in the header could be corrupt, search the rest of
+                // the buffer for more messages
+                self.resync = Some(0);
+                Err(ParseError::Context {
+                    error: Box::new(ParseError::NotEnoughBytes),
+                    msg_id: msg_id,
+                    offset: Some(available),
+                    path: String::new(),
+                })
+            }
+            Found::End | Found::Implausible => Ok(None),
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for Messages<'a, R> {
+    type Item = Result<NatNetResponse>;
+
+    fn next(&mut self) -> Option<Result<NatNetResponse>> {
+        if self.done {
+            return None;
+        }
+        match self.read_next() {
+            Ok(Some(msg)) => Some(Ok(msg)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
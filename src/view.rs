@@ -0,0 +1,770 @@
+//! Borrowed views of `NatNet` data
+//!
+//! A `FrameView` validates the layout of a raw frame of data once and then
+//! decodes its content on demand, without copying the underlying bytes.
+use asset::Asset;
+use byteorder::{ReadBytesExt, LittleEndian};
+use device::Device;
+use force_plate::ForcePlate;
+use frame::FrameOfData;
+use marker::{Marker, LabeledMarker};
+use nalgebra::Quaternion;
+use rigid_body::RigidBody;
+use semver::Version;
+use skeleton::Skeleton;
+use std::fmt;
+use std::str;
+use super::{DecodeLimits, ErrorPath, NatNetMsgType, ParseError, Result, Unpack};
+
+/// Function used to read a single item from bytes
+type ReadFn<'a, T> = fn(Layout<'a>, &mut &'a [u8]) -> Result<T>;
+
+/// Function used to skip a single item in bytes
+type SkipFn<'a> = fn(Layout<'a>, &mut &'a [u8]) -> Result<()>;
+
+/// Layout of a frame of data for a `NatNet` version
+///
+/// Computed once per frame so that items can be read without comparing
+/// versions.
+#[derive(Clone, Copy, Debug)]
+struct Layout<'a> {
+    ver: &'a Version,
+    // Parameters of rigid bodies, labeled markers and frames (NatNet >= 2.6)
+    has_params: bool,
+    // Timestamp stored as `f64` instead of `f32` (NatNet >= 2.7)
+    has_f64_timestamp: bool,
+    has_force_plates: bool,
+    has_devices: bool,
+    // Markers in rigid bodies and software latency (NatNet < 3.0)
+    has_body_markers: bool,
+    // Marker residuals and high resolution timestamps (NatNet >= 3.0)
+    has_residual: bool,
+    // Size of data blocks, assets and precision timestamp (NatNet >= 4.1)
+    has_block_sizes: bool,
+}
+
+impl<'a> Layout<'a> {
+    fn new(ver: &'a Version) -> Layout<'a> {
+        Layout {
+            ver: ver,
+            has_params: *ver >= Version::parse("2.6.0").unwrap(),
+            has_f64_timestamp: *ver >= Version::parse("2.7.0").unwrap(),
+            has_force_plates: *ver >= Version::parse("2.9.0").unwrap(),
+            has_devices: *ver >= Version::parse("2.11.0").unwrap(),
+            has_body_markers: *ver < Version::parse("3.0.0").unwrap(),
+            has_residual: *ver >= Version::parse("3.0.0").unwrap(),
+            has_block_sizes: *ver >= Version::parse("4.1.0").unwrap(),
+        }
+    }
+}
+
+/// Lazy iterator over items in a `FrameView`
+///
+/// The layout of all items has been validated up front, each item is decoded
+/// when the iterator is advanced.
+pub struct ViewIter<'a, T> {
+    layout: Layout<'a>,
+    bytes: &'a [u8],
+    remaining: usize,
+    read: ReadFn<'a, T>,
+}
+
+/// Iterator over markers
+pub type Markers<'a> = ViewIter<'a, Marker>;
+/// Iterator over named marker sets
+pub type MarkerSets<'a> = ViewIter<'a, MarkerSetView<'a>>;
+/// Iterator over rigid bodies
+///
+/// # Note
+/// Rigid bodies are decoded into `RigidBody`, which allocates for the list
+/// of markers before `NatNet` 3.0.
+pub type RigidBodies<'a> = ViewIter<'a, RigidBody>;
+/// Iterator over skeletons
+pub type Skeletons<'a> = ViewIter<'a, SkeletonView<'a>>;
+/// Iterator over assets
+pub type Assets<'a> = ViewIter<'a, AssetView<'a>>;
+/// Iterator over labeled markers
+pub type LabeledMarkers<'a> = ViewIter<'a, LabeledMarker>;
+/// Iterator over force plates
+pub type ForcePlates<'a> = ViewIter<'a, ForcePlateView<'a>>;
+/// Iterator over devices
+pub type Devices<'a> = ViewIter<'a, DeviceView<'a>>;
+/// Iterator over analog channels, each channel is an iterator over samples
+pub type Channels<'a> = ViewIter<'a, Samples<'a>>;
+/// Iterator over samples of an analog channel
+pub type Samples<'a> = ViewIter<'a, f32>;
+
+/// Borrowed named marker set
+#[derive(Clone, Debug)]
+pub struct MarkerSetView<'a> {
+    /// Name of set
+    pub name: &'a str,
+    /// Markers in set
+    pub markers: Markers<'a>,
+}
+
+/// Borrowed `Skeleton`
+#[derive(Clone, Debug)]
+pub struct SkeletonView<'a> {
+    /// ID of skeleton
+    pub id: i32,
+    /// Rigid bodies comprising this skeleton
+    pub bones: RigidBodies<'a>,
+}
+
+/// Borrowed `Asset`
+#[derive(Clone, Debug)]
+pub struct AssetView<'a> {
+    /// ID of asset
+    pub id: i32,
+    /// Rigid bodies of this asset
+    pub rigid_bodies: RigidBodies<'a>,
+    /// Markers of this asset
+    pub markers: LabeledMarkers<'a>,
+}
+
+/// Borrowed `ForcePlate`
+#[derive(Clone, Debug)]
+pub struct ForcePlateView<'a> {
+    /// ID of plate
+    pub id: i32,
+    /// Channels from plate
+    pub channels: Channels<'a>,
+}
+
+/// Borrowed `Device`
+#[derive(Clone, Debug)]
+pub struct DeviceView<'a> {
+    /// ID of device
+    pub id: i32,
+    /// Channels from device
+    pub channels: Channels<'a>,
+}
+
+/// Borrowed `FrameOfData`
+///
+/// Created with `FrameView::new` or `NatNet::view`, see the module
+/// documentation.
+#[derive(Clone, Debug)]
+pub struct FrameView<'a> {
+    frame_number: i32,
+    marker_sets: MarkerSets<'a>,
+    other_markers: Markers<'a>,
+    rigid_bodies: RigidBodies<'a>,
+    skeletons: Skeletons<'a>,
+    assets: Option<Assets<'a>>,
+    labeled_markers: LabeledMarkers<'a>,
+    force_plates: Option<ForcePlates<'a>>,
+    devices: Option<Devices<'a>>,
+    latency: Option<f32>,
+    timecode: (u32, u32),
+    timestamp: Option<f64>,
+    stamp_camera_mid_exposure: Option<u64>,
+    stamp_data_received: Option<u64>,
+    stamp_transmit: Option<u64>,
+    precision_timestamp: Option<(u32, u32)>,
+    is_recording: Option<bool>,
+    tracked_models_changed: Option<bool>,
+}
+
+impl<'a, T> Clone for ViewIter<'a, T> {
+    fn clone(&self) -> ViewIter<'a, T> {
+        ViewIter {
+            layout: self.layout,
+            bytes: self.bytes,
+            remaining: self.remaining,
+            read: self.read,
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for ViewIter<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "ViewIter {{ remaining: {}, bytes: {} }}",
+               self.remaining,
+               self.bytes.len())
+    }
+}
+
+impl<'a, T> Iterator for ViewIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match (self.read)(self.layout, &mut self.bytes) {
+            Ok(item) => {
+                self.remaining -= 1;
+                Some(item)
+            }
+            Err(err) => {
+                // Can only happen if the layout was not validated correctly
+                error!("Could not read validated item: {}", err);
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for ViewIter<'a, T> {}
+
+impl<'a> FrameView<'a> {
+    /// Create a view of a raw `NatNet` frame of data message
+    ///
+    /// The message must include the `NatNet` header. The complete layout of
+    /// the frame is validated before this function returns.
+    pub fn new(ver: &'a Version, bytes: &'a [u8]) -> Result<FrameView<'a>> {
+        let mut bytes = bytes;
+        let msg_id = try!(bytes.read_u16::<LittleEndian>());
+        let num_bytes = try!(bytes.read_u16::<LittleEndian>()) as usize;
+        if msg_id != NatNetMsgType::FrameOfData as u16 {
+            return Err(ParseError::UnknownResponse(msg_id));
+        }
+        let mut frame = try!(take(&mut bytes, num_bytes));
+        let res = FrameView::read(Layout::new(ver), &mut frame).field("frame");
+        // Errors report their offset from the start of the message
+        res.map_err(|err| err.locate(msg_id, 4 + num_bytes - frame.len()))
+    }
+
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<FrameView<'a>> {
+        let frame_num = try!(bytes.read_i32::<LittleEndian>().field("frame_number"));
+        let sets = try!(section(layout, bytes, MarkerSetView::read, skip_read::<MarkerSetView>)
+            .field("marker_sets"));
+        let others = try!(section(layout, bytes, read_marker, skip_marker).field("other_markers"));
+        let bodies = try!(section(layout, bytes, read_rigid_body, skip_rigid_body)
+            .field("rigid_bodies"));
+        let skels = try!(section(layout, bytes, SkeletonView::read, skip_read::<SkeletonView>)
+            .field("skeletons"));
+        let assets = if layout.has_block_sizes {
+            Some(try!(section(layout, bytes, AssetView::read, skip_read::<AssetView>)
+                .field("assets")))
+        } else {
+            None
+        };
+        let labeled = try!(section(layout, bytes, read_labeled_marker, skip_labeled_marker)
+            .field("labeled_markers"));
+        let plates = if layout.has_force_plates {
+            Some(try!(section(layout, bytes, ForcePlateView::read, skip_read::<ForcePlateView>)
+                .field("force_plates")))
+        } else {
+            None
+        };
+        let devices = if layout.has_devices {
+            Some(try!(section(layout, bytes, DeviceView::read, skip_read::<DeviceView>)
+                .field("devices")))
+        } else {
+            None
+        };
+        // The rest of the frame is cheap to decode and read up front, see
+        // `FrameOfData::unpack` for details
+        let latency = if layout.has_body_markers {
+            Some(try!(bytes.read_f32::<LittleEndian>()))
+        } else {
+            None
+        };
+        let tc = try!(bytes.read_u32::<LittleEndian>());
+        let tcs = try!(bytes.read_u32::<LittleEndian>());
+        let ts = if layout.has_f64_timestamp {
+            Some(try!(bytes.read_f64::<LittleEndian>()))
+        } else if layout.has_params {
+            Some(try!(bytes.read_f32::<LittleEndian>()) as f64)
+        } else {
+            None
+        };
+        let (mid_exp, received, transmit) = if layout.has_residual {
+            (Some(try!(bytes.read_u64::<LittleEndian>())),
+             Some(try!(bytes.read_u64::<LittleEndian>())),
+             Some(try!(bytes.read_u64::<LittleEndian>())))
+        } else {
+            (None, None, None)
+        };
+        let precision_ts = if layout.has_block_sizes {
+            let secs = try!(bytes.read_u32::<LittleEndian>());
+            let frac = try!(bytes.read_u32::<LittleEndian>());
+            Some((secs, frac))
+        } else {
+            None
+        };
+        let (is_rec, tmc) = if layout.has_params {
+            let params = try!(bytes.read_i16::<LittleEndian>());
+            (Some(params & 0x01 > 0), Some(params & 0x02 > 0))
+        } else {
+            (None, None)
+        };
+        let eod = try!(bytes.read_i32::<LittleEndian>().field("end_of_data"));
+        if eod != 0 {
+            debug!("End of data tag, 0 != {}", eod);
+            return Err(ParseError::EndOfDataMismatch { found: eod }).field("end_of_data");
+        }
+        Ok(FrameView {
+            frame_number: frame_num,
+            marker_sets: sets,
+            other_markers: others,
+            rigid_bodies: bodies,
+            skeletons: skels,
+            assets: assets,
+            labeled_markers: labeled,
+            force_plates: plates,
+            devices: devices,
+            latency: latency,
+            timecode: (tc, tcs),
+            timestamp: ts,
+            stamp_camera_mid_exposure: mid_exp,
+            stamp_data_received: received,
+            stamp_transmit: transmit,
+            precision_timestamp: precision_ts,
+            is_recording: is_rec,
+            tracked_models_changed: tmc,
+        })
+    }
+
+    /// Current frame number
+    pub fn frame_number(&self) -> i32 {
+        self.frame_number
+    }
+
+    /// Named marker sets
+    pub fn marker_sets(&self) -> MarkerSets<'a> {
+        self.marker_sets.clone()
+    }
+
+    /// Unnamed markers
+    pub fn other_markers(&self) -> Markers<'a> {
+        self.other_markers.clone()
+    }
+
+    /// Rigid bodies
+    pub fn rigid_bodies(&self) -> RigidBodies<'a> {
+        self.rigid_bodies.clone()
+    }
+
+    /// Skeletons
+    pub fn skeletons(&self) -> Skeletons<'a> {
+        self.skeletons.clone()
+    }
+
+    /// Assets (NatNet >= 4.1)
+    pub fn assets(&self) -> Option<Assets<'a>> {
+        self.assets.clone()
+    }
+
+    /// Labeled markers
+    pub fn labeled_markers(&self) -> LabeledMarkers<'a> {
+        self.labeled_markers.clone()
+    }
+
+    /// Force plates (NatNet >= 2.9)
+    pub fn force_plates(&self) -> Option<ForcePlates<'a>> {
+        self.force_plates.clone()
+    }
+
+    /// Devices (NatNet >= 2.11)
+    pub fn devices(&self) -> Option<Devices<'a>> {
+        self.devices.clone()
+    }
+
+    /// Software latency (NatNet < 3.0)
+    pub fn latency(&self) -> Option<f32> {
+        self.latency
+    }
+
+    /// Timecode
+    pub fn timecode(&self) -> (u32, u32) {
+        self.timecode
+    }
+
+    /// Time stamp of data (NatNet >= 2.6)
+    pub fn timestamp(&self) -> Option<f64> {
+        self.timestamp
+    }
+
+    /// High resolution time stamp of camera mid exposure (NatNet >= 3.0)
+    pub fn stamp_camera_mid_exposure(&self) -> Option<u64> {
+        self.stamp_camera_mid_exposure
+    }
+
+    /// High resolution time stamp of when data was received (NatNet >= 3.0)
+    pub fn stamp_data_received(&self) -> Option<u64> {
+        self.stamp_data_received
+    }
+
+    /// High resolution time stamp of when data was transmitted (NatNet >= 3.0)
+    pub fn stamp_transmit(&self) -> Option<u64> {
+        self.stamp_transmit
+    }
+
+    /// Precision time stamp as seconds and fractional seconds (NatNet >= 4.1)
+    pub fn precision_timestamp(&self) -> Option<(u32, u32)> {
+        self.precision_timestamp
+    }
+
+    /// Is Motive recording data? (NatNet >= 2.6)
+    pub fn is_recording(&self) -> Option<bool> {
+        self.is_recording
+    }
+
+    /// Has the list of actively tracked models changed? (NatNet >= 2.6)
+    pub fn tracked_models_changed(&self) -> Option<bool> {
+        self.tracked_models_changed
+    }
+
+    /// Decode the complete view into an owned `FrameOfData`
+    pub fn to_owned(&self) -> FrameOfData {
+        FrameOfData {
+            frame_number: self.frame_number,
+            marker_sets: self.marker_sets()
+                .map(|set| (set.name.to_string(), set.markers.collect()))
+                .collect(),
+            other_markers: self.other_markers().collect(),
+            rigid_bodies: self.rigid_bodies().collect(),
+            skeletons: self.skeletons().map(|skel| skel.to_owned()).collect(),
+            assets: self.assets().map(|assets| assets.map(|asset| asset.to_owned()).collect()),
+            labeled_markers: self.labeled_markers().collect(),
+            force_plates: self.force_plates()
+                .map(|plates| plates.map(|plate| plate.to_owned()).collect()),
+            devices: self.devices().map(|devices| devices.map(|dev| dev.to_owned()).collect()),
+            latency: self.latency,
+            timecode: self.timecode,
+            timestamp: self.timestamp,
+            stamp_camera_mid_exposure: self.stamp_camera_mid_exposure,
+            stamp_data_received: self.stamp_data_received,
+            stamp_transmit: self.stamp_transmit,
+            precision_timestamp: self.precision_timestamp,
+            is_recording: self.is_recording,
+            tracked_models_changed: self.tracked_models_changed,
+        }
+    }
+}
+
+impl<'a> SkeletonView<'a> {
+    /// Decode into an owned `Skeleton`
+    pub fn to_owned(&self) -> Skeleton {
+        Skeleton {
+            id: self.id,
+            bones: self.bones.clone().collect(),
+        }
+    }
+}
+
+impl<'a> AssetView<'a> {
+    /// Decode into an owned `Asset`
+    pub fn to_owned(&self) -> Asset {
+        Asset {
+            id: self.id,
+            rigid_bodies: self.rigid_bodies.clone().collect(),
+            markers: self.markers.clone().collect(),
+        }
+    }
+}
+
+impl<'a> ForcePlateView<'a> {
+    /// Decode into an owned `ForcePlate`
+    pub fn to_owned(&self) -> ForcePlate {
+        ForcePlate {
+            id: self.id,
+            channels: self.channels.clone().map(|chan| chan.collect()).collect(),
+        }
+    }
+}
+
+impl<'a> DeviceView<'a> {
+    /// Decode into an owned `Device`
+    pub fn to_owned(&self) -> Device {
+        Device {
+            id: self.id,
+            channels: self.channels.clone().map(|chan| chan.collect()).collect(),
+        }
+    }
+}
+
+/// Helper function to split `num` bytes off the front of `bytes`
+fn take<'a>(bytes: &mut &'a [u8], num: usize) -> Result<&'a [u8]> {
+    if bytes.len() < num {
+        return Err(ParseError::NotEnoughBytes);
+    }
+    let (head, tail) = bytes.split_at(num);
+    *bytes = tail;
+    Ok(head)
+}
+
+/// Helper function to read a non-negative count
+fn read_count(bytes: &mut &[u8]) -> Result<usize> {
+    let num = try!(bytes.read_i32::<LittleEndian>());
+    if num < 0 {
+        debug!("Negative count {}", num);
+        return Err(ParseError::InvalidCount(num));
+    }
+    Ok(num as usize)
+}
+
+/// Helper function to borrow a C-String
+fn read_str<'a>(bytes: &mut &'a [u8]) -> Result<&'a str> {
+    let end = match bytes.iter().position(|b| *b == b'\0') {
+        Some(end) => end,
+        None => return Err(ParseError::NotEnoughBytes),
+    };
+    let s = try!(take(bytes, end + 1));
+    str::from_utf8(&s[..end]).map_err(|err| {
+        ParseError::StringError {
+            bytes: s[..end].to_vec(),
+            utf8_error: Some(err),
+        }
+    })
+}
+
+/// Validate `num` items and return an iterator over them
+fn items<'a, T>(layout: Layout<'a>,
+                bytes: &mut &'a [u8],
+                num: usize,
+                read: ReadFn<'a, T>,
+                skip: SkipFn<'a>)
+                -> Result<ViewIter<'a, T>> {
+    let start = *bytes;
+    for i in 0..num {
+        try!(skip(layout, bytes).index(i));
+    }
+    Ok(ViewIter {
+        layout: layout,
+        bytes: &start[..start.len() - bytes.len()],
+        remaining: num,
+        read: read,
+    })
+}
+
+/// Validate a data block of a frame and return an iterator over its items
+///
+/// In version 4.1 and later the size of the block is checked against the
+/// size given in the block header.
+fn section<'a, T>(layout: Layout<'a>,
+                  bytes: &mut &'a [u8],
+                  read: ReadFn<'a, T>,
+                  skip: SkipFn<'a>)
+                  -> Result<ViewIter<'a, T>> {
+    let num = try!(read_count(bytes));
+    if layout.has_block_sizes {
+        let size = try!(read_count(bytes));
+        let mut block = try!(take(bytes, size));
+        let iter = try!(items(layout, &mut block, num, read, skip));
+        if !block.is_empty() {
+            debug!("Data block has {} trailing bytes", block.len());
+            return Err(ParseError::BlockSizeMismatch {
+                size: size,
+                used: size - block.len(),
+            });
+        }
+        Ok(iter)
+    } else {
+        items(layout, bytes, num, read, skip)
+    }
+}
+
+/// Private trait for views, which validate their content when read
+trait View<'a>: Sized {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<Self>;
+}
+
+/// Skip an item by reading it, only used for items which do not allocate
+fn skip_read<'a, T: View<'a>>(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<()> {
+    T::read(layout, bytes).map(|_| ())
+}
+
+// Items are validated against the borrowed bytes before they are read, so
+// there is no need to limit them further
+fn read_marker(layout: Layout, bytes: &mut &[u8]) -> Result<Marker> {
+    Marker::unpack(layout.ver, &DecodeLimits::unlimited(), bytes)
+}
+
+// Rigid bodies and labeled markers are read here instead of with `Unpack`,
+// which compares versions for every item. See `RigidBody::unpack` and
+// `LabeledMarker::unpack` for the layout.
+fn read_rigid_body(layout: Layout, bytes: &mut &[u8]) -> Result<RigidBody> {
+    let limits = DecodeLimits::unlimited();
+    let id = try!(bytes.read_i32::<LittleEndian>());
+    let pos = try!(Marker::unpack(layout.ver, &limits, bytes));
+    let orient = try!(Quaternion::unpack(layout.ver, &limits, bytes));
+    let mut markers = Vec::new();
+    let mut ids = Vec::new();
+    let mut sizes = Vec::new();
+    if layout.has_body_markers {
+        let num = try!(read_count(bytes));
+        markers.reserve(num);
+        ids.reserve(num);
+        sizes.reserve(num);
+        for _ in 0..num {
+            markers.push(try!(Marker::unpack(layout.ver, &limits, bytes)));
+        }
+        for _ in 0..num {
+            ids.push(try!(bytes.read_i32::<LittleEndian>()));
+        }
+        for _ in 0..num {
+            sizes.push(try!(bytes.read_f32::<LittleEndian>()));
+        }
+    }
+    let err = try!(bytes.read_f32::<LittleEndian>());
+    let track = if layout.has_params {
+        let params = try!(bytes.read_i16::<LittleEndian>());
+        Some(params & 0x01 > 0)
+    } else {
+        None
+    };
+    Ok(RigidBody {
+        id: id,
+        position: pos,
+        orientation: orient,
+        markers: markers,
+        marker_ids: ids,
+        marker_sizes: sizes,
+        mean_error: err,
+        valid_track: track,
+    })
+}
+
+fn read_labeled_marker(layout: Layout, bytes: &mut &[u8]) -> Result<LabeledMarker> {
+    let id = try!(bytes.read_i32::<LittleEndian>());
+    let pos = try!(Marker::unpack(layout.ver, &DecodeLimits::unlimited(), bytes));
+    let size = try!(bytes.read_f32::<LittleEndian>());
+    let params = if layout.has_params {
+        Some(try!(bytes.read_i16::<LittleEndian>()))
+    } else {
+        None
+    };
+    let flag = |mask: i16| params.map(|p| p & mask > 0);
+    let (unl, act, res) = if layout.has_residual {
+        (flag(0x10), flag(0x20), Some(try!(bytes.read_f32::<LittleEndian>())))
+    } else {
+        (None, None, None)
+    };
+    Ok(LabeledMarker {
+        id: id,
+        position: pos,
+        size: size,
+        occluded: flag(0x01),
+        point_cloud_solved: flag(0x02),
+        model_solved: flag(0x04),
+        has_model: flag(0x08),
+        unlabeled: unl,
+        active_marker: act,
+        residual: res,
+    })
+}
+
+fn read_f32(_: Layout, bytes: &mut &[u8]) -> Result<f32> {
+    Ok(try!(bytes.read_f32::<LittleEndian>()))
+}
+
+fn skip_f32(_: Layout, bytes: &mut &[u8]) -> Result<()> {
+    take(bytes, 4).map(|_| ())
+}
+
+fn skip_marker(_: Layout, bytes: &mut &[u8]) -> Result<()> {
+    take(bytes, 12).map(|_| ())
+}
+
+fn skip_rigid_body(layout: Layout, bytes: &mut &[u8]) -> Result<()> {
+    // ID, position and orientation
+    try!(take(bytes, 4 + 12 + 16));
+    if layout.has_body_markers {
+        // Markers, IDs and sizes
+        let num = try!(read_count(bytes));
+        let size = match num.checked_mul(12 + 4 + 4) {
+            Some(size) => size,
+            None => return Err(ParseError::NotEnoughBytes),
+        };
+        try!(take(bytes, size));
+    }
+    // Mean error
+    try!(take(bytes, 4));
+    if layout.has_params {
+        try!(take(bytes, 2));
+    }
+    Ok(())
+}
+
+fn skip_labeled_marker(layout: Layout, bytes: &mut &[u8]) -> Result<()> {
+    // ID, position and size
+    try!(take(bytes, 4 + 12 + 4));
+    if layout.has_params {
+        try!(take(bytes, 2));
+    }
+    if layout.has_residual {
+        try!(take(bytes, 4));
+    }
+    Ok(())
+}
+
+impl<'a> View<'a> for MarkerSetView<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<MarkerSetView<'a>> {
+        let name = try!(read_str(bytes));
+        let num = try!(read_count(bytes));
+        Ok(MarkerSetView {
+            name: name,
+            markers: try!(items(layout, bytes, num, read_marker, skip_marker)),
+        })
+    }
+}
+
+impl<'a> View<'a> for SkeletonView<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<SkeletonView<'a>> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num = try!(read_count(bytes));
+        Ok(SkeletonView {
+            id: id,
+            bones: try!(items(layout, bytes, num, read_rigid_body, skip_rigid_body)),
+        })
+    }
+}
+
+impl<'a> View<'a> for AssetView<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<AssetView<'a>> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num_bodies = try!(read_count(bytes));
+        let bodies = try!(items(layout, bytes, num_bodies, read_rigid_body, skip_rigid_body));
+        let num_markers = try!(read_count(bytes));
+        let markers = try!(items(layout,
+                                 bytes,
+                                 num_markers,
+                                 read_labeled_marker,
+                                 skip_labeled_marker));
+        Ok(AssetView {
+            id: id,
+            rigid_bodies: bodies,
+            markers: markers,
+        })
+    }
+}
+
+impl<'a> View<'a> for Samples<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<Samples<'a>> {
+        let num = try!(read_count(bytes));
+        items(layout, bytes, num, read_f32, skip_f32)
+    }
+}
+
+impl<'a> View<'a> for ForcePlateView<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<ForcePlateView<'a>> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num = try!(read_count(bytes));
+        Ok(ForcePlateView {
+            id: id,
+            channels: try!(items(layout, bytes, num, Samples::read, skip_read::<Samples>)),
+        })
+    }
+}
+
+impl<'a> View<'a> for DeviceView<'a> {
+    fn read(layout: Layout<'a>, bytes: &mut &'a [u8]) -> Result<DeviceView<'a>> {
+        let id = try!(bytes.read_i32::<LittleEndian>());
+        let num = try!(read_count(bytes));
+        Ok(DeviceView {
+            id: id,
+            channels: try!(items(layout, bytes, num, Samples::read, skip_read::<Samples>)),
+        })
+    }
+}
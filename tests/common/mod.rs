@@ -0,0 +1,143 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use byteorder::{WriteBytesExt, LittleEndian};
+use natnet_decode::{FrameOfData, NatNetResponse};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Read a test file into memory
+pub fn read_fixture<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+/// The frame of data in a message
+pub fn frame_of(msg: NatNetResponse) -> FrameOfData {
+    match msg {
+        NatNetResponse::FrameOfData(frame) => frame,
+        msg => panic!("Expected frame of data, got {:?}", msg),
+    }
+}
+
+/// Handcrafted `NatNet` 3.0 frame with a single item in each list
+pub fn frame_3_0() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.write_i32::<LittleEndian>(42).unwrap();
+    // Marker sets
+    body.write_i32::<LittleEndian>(1).unwrap();
+    body.extend_from_slice(b"all\0");
+    body.write_i32::<LittleEndian>(1).unwrap();
+    for v in &[1.0, 2.0, 3.0] {
+        body.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    // Other markers
+    body.write_i32::<LittleEndian>(0).unwrap();
+    // Rigid bodies, without marker data
+    body.write_i32::<LittleEndian>(1).unwrap();
+    body.write_i32::<LittleEndian>(1).unwrap();
+    for v in &[0.1, 0.2, 0.3, 0.0, 0.0, 0.0, 1.0, 0.5] {
+        body.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    body.write_i16::<LittleEndian>(0x01).unwrap();
+    // Skeletons
+    body.write_i32::<LittleEndian>(0).unwrap();
+    // Labeled markers, with residual
+    body.write_i32::<LittleEndian>(1).unwrap();
+    body.write_i32::<LittleEndian>(7).unwrap();
+    for v in &[1.0, 2.0, 3.0, 0.01] {
+        body.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    body.write_i16::<LittleEndian>(0x30).unwrap();
+    body.write_f32::<LittleEndian>(0.25).unwrap();
+    // Force plates and devices
+    body.write_i32::<LittleEndian>(0).unwrap();
+    body.write_i32::<LittleEndian>(0).unwrap();
+    // Timecode, timestamp and high resolution timestamps
+    body.write_u32::<LittleEndian>(1).unwrap();
+    body.write_u32::<LittleEndian>(2).unwrap();
+    body.write_f64::<LittleEndian>(3.5).unwrap();
+    for v in &[100, 200, 300] {
+        body.write_u64::<LittleEndian>(*v).unwrap();
+    }
+    body.write_i16::<LittleEndian>(0x01).unwrap();
+    body.write_i32::<LittleEndian>(0).unwrap();
+
+    let mut msg = Vec::new();
+    msg.write_u16::<LittleEndian>(7).unwrap();
+    msg.write_u16::<LittleEndian>(body.len() as u16).unwrap();
+    msg.extend_from_slice(&body);
+    msg
+}
+
+/// Handcrafted `NatNet` 4.1 frame with an asset and a precision timestamp
+///
+/// Each data block is prefixed by the number of items and its size in bytes.
+pub fn frame_4_1() -> Vec<u8> {
+    fn block(body: &mut Vec<u8>, num: i32, items: &[u8]) {
+        body.write_i32::<LittleEndian>(num).unwrap();
+        body.write_i32::<LittleEndian>(items.len() as i32).unwrap();
+        body.extend_from_slice(items);
+    }
+    let mut rigid_body = Vec::new();
+    rigid_body.write_i32::<LittleEndian>(1).unwrap();
+    for v in &[0.1, 0.2, 0.3, 0.0, 0.0, 0.0, 1.0, 0.5] {
+        rigid_body.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    rigid_body.write_i16::<LittleEndian>(0x01).unwrap();
+    let mut labeled = Vec::new();
+    labeled.write_i32::<LittleEndian>(7).unwrap();
+    for v in &[1.0, 2.0, 3.0, 0.01] {
+        labeled.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    labeled.write_i16::<LittleEndian>(0x30).unwrap();
+    labeled.write_f32::<LittleEndian>(0.25).unwrap();
+
+    let mut body = Vec::new();
+    body.write_i32::<LittleEndian>(42).unwrap();
+    // Marker sets
+    let mut sets = b"all\0".to_vec();
+    sets.write_i32::<LittleEndian>(1).unwrap();
+    for v in &[1.0, 2.0, 3.0] {
+        sets.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    block(&mut body, 1, &sets);
+    // Other markers
+    block(&mut body, 0, &[]);
+    // Rigid bodies
+    block(&mut body, 1, &rigid_body);
+    // Skeletons
+    block(&mut body, 0, &[]);
+    // Assets, with the rigid body and labeled marker of the frame
+    let mut asset = Vec::new();
+    asset.write_i32::<LittleEndian>(3).unwrap();
+    asset.write_i32::<LittleEndian>(1).unwrap();
+    asset.extend_from_slice(&rigid_body);
+    asset.write_i32::<LittleEndian>(1).unwrap();
+    asset.extend_from_slice(&labeled);
+    block(&mut body, 1, &asset);
+    // Labeled markers
+    block(&mut body, 1, &labeled);
+    // Force plates and devices
+    block(&mut body, 0, &[]);
+    block(&mut body, 0, &[]);
+    // Timecode, timestamp, high resolution and precision timestamps
+    body.write_u32::<LittleEndian>(1).unwrap();
+    body.write_u32::<LittleEndian>(2).unwrap();
+    body.write_f64::<LittleEndian>(3.5).unwrap();
+    for v in &[100, 200, 300] {
+        body.write_u64::<LittleEndian>(*v).unwrap();
+    }
+    body.write_u32::<LittleEndian>(10).unwrap();
+    body.write_u32::<LittleEndian>(20).unwrap();
+    body.write_i16::<LittleEndian>(0x01).unwrap();
+    body.write_i32::<LittleEndian>(0).unwrap();
+
+    let mut msg = Vec::new();
+    msg.write_u16::<LittleEndian>(7).unwrap();
+    msg.write_u16::<LittleEndian>(body.len() as u16).unwrap();
+    msg.extend_from_slice(&body);
+    msg
+}
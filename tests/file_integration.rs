@@ -1,11 +1,26 @@
+extern crate byteorder;
 extern crate env_logger;
+extern crate nalgebra;
 extern crate natnet_decode;
 extern crate semver;
 
-use natnet_decode::{NatNet, NatNetResponse};
+mod common;
+
+use byteorder::{WriteBytesExt, LittleEndian};
+use common::{frame_3_0, frame_4_1, frame_of, read_fixture};
+use natnet_decode::{Asset, AutoNatNet, Command, CommandResponse, ConnectOptions, DecodeLimits,
+                    Device, Dispatcher, ForcePlate, MessageHeader, NatNet, NatNetMsgType,
+                    NatNetRequest, NatNetResponse, ParseError, ServerDescription, SizeCheck};
+use natnet_decode::index::{FrameIndex, IndexedReader};
+use natnet_decode::model;
+use natnet_decode::recording::RecordingWriter;
 use semver::Version;
-use std::fs::File;
-use std::io::BufReader;
+use std::convert::TryFrom;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::process;
+use std::time::SystemTime;
 
 #[test]
 fn verions() {
@@ -41,18 +56,17 @@ fn parse_2_5() {
     let parser = NatNet::new(Version::parse("2.5.0").unwrap());
     for i in 1..3 {
         let f_name = format!("tests/data/frame-motive-1.5.0-00{}.bin", i);
-        if let NatNetResponse::FrameOfData(frame) = test_parse(&parser, f_name) {
-            assert!(frame.marker_sets.contains_key("all"));
-            assert!(frame.marker_sets.contains_key("Rigid Body 1"));
-            assert_eq!(frame.other_markers.len(), 2);
-            assert_eq!(frame.rigid_bodies.len(), 1);
-            assert_eq!(frame.skeletons.len(), 0);
-            assert_eq!(frame.labeled_markers.len(), 3);
-            assert!(frame.force_plates.is_none());
-            assert!(frame.timestamp.is_none());
-            assert!(frame.is_recording.is_none());
-            assert!(frame.tracked_models_changed.is_none());
-        }
+        let frame = frame_of(test_parse(&parser, f_name));
+        assert!(frame.marker_sets.contains_key("all"));
+        assert!(frame.marker_sets.contains_key("Rigid Body 1"));
+        assert_eq!(frame.other_markers.len(), 2);
+        assert_eq!(frame.rigid_bodies.len(), 1);
+        assert_eq!(frame.skeletons.len(), 0);
+        assert_eq!(frame.labeled_markers.len(), 3);
+        assert!(frame.force_plates.is_none());
+        assert!(frame.timestamp.is_none());
+        assert!(frame.is_recording.is_none());
+        assert!(frame.tracked_models_changed.is_none());
     }
 }
 
@@ -61,18 +75,17 @@ fn parse_2_7() {
     let parser = NatNet::new(Version::parse("2.7.0").unwrap());
     for i in 1..3 {
         let f_name = format!("tests/data/frame-motive-1.7.2-00{}.bin", i);
-        if let NatNetResponse::FrameOfData(frame) = test_parse(&parser, f_name) {
-            assert!(frame.marker_sets.contains_key("all"));
-            assert!(frame.marker_sets.contains_key("Rigid Body 1"));
-            assert_eq!(frame.other_markers.len(), 2);
-            assert_eq!(frame.rigid_bodies.len(), 1);
-            assert_eq!(frame.skeletons.len(), 0);
-            assert_eq!(frame.labeled_markers.len(), 3);
-            assert!(frame.force_plates.is_none());
-            assert!(frame.timestamp.is_some());
-            assert!(frame.is_recording.is_some());
-            assert!(frame.tracked_models_changed.is_some());
-        }
+        let frame = frame_of(test_parse(&parser, f_name));
+        assert!(frame.marker_sets.contains_key("all"));
+        assert!(frame.marker_sets.contains_key("Rigid Body 1"));
+        assert_eq!(frame.other_markers.len(), 2);
+        assert_eq!(frame.rigid_bodies.len(), 1);
+        assert_eq!(frame.skeletons.len(), 0);
+        assert_eq!(frame.labeled_markers.len(), 3);
+        assert!(frame.force_plates.is_none());
+        assert!(frame.timestamp.is_some());
+        assert!(frame.is_recording.is_some());
+        assert!(frame.tracked_models_changed.is_some());
     }
 }
 
@@ -81,17 +94,1062 @@ fn parse_2_9() {
     let parser = NatNet::new(Version::parse("2.9.0").unwrap());
     for i in 1..3 {
         let f_name = format!("tests/data/frame-motive-1.9.0-00{}.bin", i);
-        if let NatNetResponse::FrameOfData(frame) = test_parse(&parser, f_name) {
-            assert!(frame.marker_sets.contains_key("all"));
-            assert!(frame.marker_sets.contains_key("Triangle"));
-            assert_eq!(frame.other_markers.len(), 0);
-            assert_eq!(frame.rigid_bodies.len(), 1);
-            assert_eq!(frame.skeletons.len(), 0);
-            assert_eq!(frame.labeled_markers.len(), 3);
-            assert!(frame.force_plates.is_some());
-            assert!(frame.timestamp.is_some());
-            assert!(frame.is_recording.is_some());
-            assert!(frame.tracked_models_changed.is_some());
+        let frame = frame_of(test_parse(&parser, f_name));
+        assert!(frame.marker_sets.contains_key("all"));
+        assert!(frame.marker_sets.contains_key("Triangle"));
+        assert_eq!(frame.other_markers.len(), 0);
+        assert_eq!(frame.rigid_bodies.len(), 1);
+        assert_eq!(frame.skeletons.len(), 0);
+        assert_eq!(frame.labeled_markers.len(), 3);
+        assert!(frame.force_plates.is_some());
+        assert!(frame.devices.is_none());
+        assert!(frame.timestamp.is_some());
+        assert!(frame.is_recording.is_some());
+        assert!(frame.tracked_models_changed.is_some());
+    }
+}
+
+#[test]
+fn round_trip() {
+    let files = vec![("1.5.0", "2.5.0"), ("1.7.2", "2.7.0"), ("1.9.0", "2.9.0")];
+    for &(motive, natnet) in files.iter() {
+        let version = Version::parse(natnet).unwrap();
+        for i in 0..3 {
+            let f_name = format!("tests/data/frame-motive-{}-00{}.bin", motive, i);
+            let raw = read_fixture(f_name);
+            let msg = NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap();
+            let mut packed = Vec::new();
+            NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+            assert_eq!(raw, packed);
+        }
+    }
+}
+
+#[test]
+fn parse_3_0() {
+    let version = Version::parse("3.0.0").unwrap();
+    let raw = frame_3_0();
+    let frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    assert_eq!(frame.frame_number, 42);
+    assert!(frame.marker_sets.contains_key("all"));
+    assert_eq!(frame.rigid_bodies.len(), 1);
+    assert!(frame.rigid_bodies[0].markers.is_empty());
+    assert_eq!(frame.rigid_bodies[0].valid_track, Some(true));
+    assert_eq!(frame.labeled_markers.len(), 1);
+    assert_eq!(frame.labeled_markers[0].residual, Some(0.25));
+    assert_eq!(frame.labeled_markers[0].unlabeled, Some(true));
+    assert_eq!(frame.labeled_markers[0].active_marker, Some(true));
+    assert_eq!(frame.devices, Some(vec![]));
+    assert!(frame.latency.is_none());
+    assert_eq!(frame.timestamp, Some(3.5));
+    assert_eq!(frame.stamp_camera_mid_exposure, Some(100));
+    assert_eq!(frame.stamp_data_received, Some(200));
+    assert_eq!(frame.stamp_transmit, Some(300));
+    assert_eq!(frame.is_recording, Some(true));
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &NatNetResponse::FrameOfData(frame), &mut packed).unwrap();
+    assert_eq!(raw, packed);
+}
+
+#[test]
+fn parse_4_x() {
+    let raw = frame_3_0();
+    let msg = NatNet::unpack_with(&Version::parse("3.0.0").unwrap(), &mut raw.as_slice()).unwrap();
+    // Layout of 4.0 is unchanged from 3.0
+    let mut packed = Vec::new();
+    NatNet::pack_with(&Version::parse("4.0.0").unwrap(), &msg, &mut packed).unwrap();
+    assert_eq!(raw, packed);
+
+    let version = Version::parse("4.1.0").unwrap();
+    let parser = NatNet::new(version.clone());
+    let raw = frame_4_1();
+    let frame = frame_of(parser.unpack(&mut raw.as_slice()).unwrap());
+    assert_eq!(frame.frame_number, 42);
+    assert_eq!(frame.marker_sets["all"].len(), 1);
+    assert_eq!(frame.rigid_bodies[0].id, 1);
+    assert_eq!(frame.rigid_bodies[0].mean_error, 0.5);
+    assert_eq!(frame.labeled_markers[0].id, 7);
+    assert_eq!(frame.labeled_markers[0].residual, Some(0.25));
+    assert_eq!(frame.assets,
+               Some(vec![Asset {
+                             id: 3,
+                             rigid_bodies: frame.rigid_bodies.clone(),
+                             markers: frame.labeled_markers.clone(),
+                         }]));
+    assert_eq!(frame.force_plates, Some(vec![]));
+    assert_eq!(frame.devices, Some(vec![]));
+    assert_eq!(frame.stamp_transmit, Some(300));
+    assert_eq!(frame.precision_timestamp, Some((10, 20)));
+    assert_eq!(frame.is_recording, Some(true));
+    assert_eq!(parser.view(&raw).unwrap().to_owned(), frame);
+    let mut packed = Vec::new();
+    parser.pack(&NatNetResponse::FrameOfData(frame), &mut packed).unwrap();
+    assert_eq!(packed, raw);
+
+    // Items must fill the size given for their block
+    let mut wrong = raw.clone();
+    (&mut wrong[12..16]).write_i32::<LittleEndian>(24).unwrap();
+    for err in vec![parser.unpack(&mut wrong.as_slice()).unwrap_err(),
+                    parser.view(&wrong).unwrap_err()] {
+        match *err.kind() {
+            ParseError::BlockSizeMismatch { size: 24, used: 20 } => {}
+            _ => panic!("Expected block size mismatch, got {:?}", err),
+        }
+    }
+    (&mut wrong[12..16]).write_i32::<LittleEndian>(16).unwrap();
+    assert!(parser.unpack(&mut wrong.as_slice()).is_err());
+    assert!(parser.view(&wrong).is_err());
+
+    let marker = model::Marker {
+        name: "Marker".to_string(),
+        id: 1,
+        position: nalgebra::Point3::new(1.0, 2.0, 3.0),
+        size: 0.01,
+        params: 0,
+    };
+    let asset = model::Asset {
+        name: "Asset".to_string(),
+        asset_type: 1,
+        id: 3,
+        rigid_bodies: vec![],
+        markers: vec![marker],
+    };
+    let models = NatNetResponse::ModelDef(vec![model::DataSet::Asset(asset)]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &models, &mut packed).unwrap();
+    assert_eq!(models, NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap());
+}
+
+#[test]
+fn force_plate_description() {
+    let version = Version::parse("3.0.0").unwrap();
+    let mut cal_mat = [[0.0; 12]; 12];
+    for (i, row) in cal_mat.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    let plate = model::ForcePlate {
+        id: 2,
+        serial_number: "FP-1234".to_string(),
+        width: 0.4,
+        length: 0.6,
+        origin: nalgebra::Vector3::new(0.0, 0.0, -0.04),
+        calibration_matrix: cal_mat,
+        corners: [nalgebra::Point3::new(0.0, 0.0, 0.0),
+                  nalgebra::Point3::new(0.4, 0.0, 0.0),
+                  nalgebra::Point3::new(0.4, 0.6, 0.0),
+                  nalgebra::Point3::new(0.0, 0.6, 0.0)],
+        plate_type: 1,
+        channel_data_type: 0,
+        channel_names: vec!["Fx".to_string(), "Fy".to_string(), "Fz".to_string()],
+    };
+    let msg = NatNetResponse::ModelDef(vec![model::DataSet::ForcePlate(plate.clone())]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    let models = match NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap() {
+        NatNetResponse::ModelDef(models) => models,
+        _ => unreachable!(),
+    };
+    let data = ForcePlate {
+        id: 2,
+        channels: vec![vec![0.0]; 3],
+    };
+    assert_eq!(data.description(&models), Some(&plate));
+    let other = ForcePlate {
+        id: 3,
+        channels: vec![],
+    };
+    assert!(other.description(&models).is_none());
+}
+
+#[test]
+fn devices() {
+    let version = Version::parse("3.0.0").unwrap();
+    let raw = frame_3_0();
+    let mut frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    let data = Device {
+        id: 5,
+        channels: vec![vec![0.1, 0.2, 0.3], vec![0.4, 0.5, 0.6]],
+    };
+    frame.devices = Some(vec![data.clone()]);
+    let msg = NatNetResponse::FrameOfData(frame);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    assert_eq!(msg, NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap());
+
+    let device = model::Device {
+        id: 5,
+        name: "EMG".to_string(),
+        serial_number: "EMG-0001".to_string(),
+        device_type: 1,
+        channel_data_type: 0,
+        channel_names: vec!["Left".to_string(), "Right".to_string()],
+    };
+    let msg = NatNetResponse::ModelDef(vec![model::DataSet::Device(device.clone())]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    let models = match NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap() {
+        NatNetResponse::ModelDef(models) => models,
+        _ => unreachable!(),
+    };
+    assert_eq!(data.description(&models), Some(&device));
+}
+
+#[test]
+fn camera_description() {
+    let version = Version::parse("3.0.0").unwrap();
+    let camera = model::Camera {
+        name: "Prime 13 #1".to_string(),
+        position: nalgebra::Point3::new(1.0, 2.5, -1.0),
+        orientation: nalgebra::Quaternion::new(0.0, 0.0, 0.0, 1.0),
+    };
+    let mut body = Vec::new();
+    body.write_i32::<LittleEndian>(1).unwrap();
+    body.write_i32::<LittleEndian>(5).unwrap();
+    body.extend_from_slice(b"Prime 13 #1\0");
+    for v in &[1.0, 2.5, -1.0, 0.0, 0.0, 0.0, 1.0] {
+        body.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    let mut raw = Vec::new();
+    raw.write_u16::<LittleEndian>(5).unwrap();
+    raw.write_u16::<LittleEndian>(body.len() as u16).unwrap();
+    raw.extend_from_slice(&body);
+    let msg = NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap();
+    assert_eq!(msg, NatNetResponse::ModelDef(vec![model::DataSet::Camera(camera)]));
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    assert_eq!(raw, packed);
+}
+
+#[test]
+fn unknown_types() {
+    let set = model::DataSet::MarkerSet(model::MarkerSet {
+        name: "all".to_string(),
+        markers: vec!["a".to_string(), "b".to_string()],
+    });
+    let unknown = model::DataSet::Unknown {
+        type_id: 42,
+        raw: vec![1, 2, 3, 4],
+    };
+    // Datasets carry their size in 4.1 so decoding continues after unknowns
+    let version = Version::parse("4.1.0").unwrap();
+    let msg = NatNetResponse::ModelDef(vec![set.clone(), unknown.clone(), set.clone()]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    assert_eq!(msg, NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap());
+    // Fields added to a known dataset are skipped using its size
+    let sets = NatNetResponse::ModelDef(vec![set.clone(), set.clone()]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &sets, &mut packed).unwrap();
+    assert_eq!(&packed[12..16], &[12, 0, 0, 0]);
+    (&mut packed[12..16]).write_i32::<LittleEndian>(15).unwrap();
+    let num_bytes = packed.len() as u16 - 4 + 3;
+    (&mut packed[2..4]).write_u16::<LittleEndian>(num_bytes).unwrap();
+    let rest = packed.split_off(28);
+    packed.extend_from_slice(&[1, 2, 3]);
+    packed.extend_from_slice(&rest);
+    let strict = NatNet::new(version.clone()).with_size_check(SizeCheck::Strict);
+    assert_eq!(sets, strict.unpack(&mut packed.as_slice()).unwrap());
+
+    // Before 4.1 unknown datasets consume the rest of the message
+    let version = Version::parse("3.0.0").unwrap();
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    // Trailing message which must not be consumed
+    packed.extend_from_slice(&[100, 0, 0, 0]);
+    let mut bytes = packed.as_slice();
+    match NatNet::unpack_with(&version, &mut bytes).unwrap() {
+        NatNetResponse::ModelDef(models) => {
+            assert_eq!(models.len(), 2);
+            assert_eq!(models[0], set);
+            match models[1] {
+                model::DataSet::Unknown { type_id, ref raw } => {
+                    assert_eq!(type_id, 42);
+                    // Raw bytes of unknown dataset and the following marker set
+                    assert_eq!(raw.len(), 4 + (4 + 4 + 4 + 2 * 2));
+                }
+                _ => assert!(false),
+            }
+        }
+        _ => assert!(false),
+    }
+    assert_eq!(NatNet::unpack_with(&version, &mut bytes).unwrap(),
+               NatNetResponse::UnrecognizedRequest);
+
+    let raw = vec![200, 0, 3, 0, 1, 2, 3];
+    let msg = NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap();
+    assert_eq!(msg,
+               NatNetResponse::Unknown {
+                   msg_id: 200,
+                   raw: vec![1, 2, 3],
+               });
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    assert_eq!(raw, packed);
+}
+
+#[test]
+fn rigid_body_description() {
+    let body = model::RigidBody {
+        name: "Body".to_string(),
+        id: 2,
+        parent_id: -1,
+        offset: nalgebra::Vector3::new(0.0, 0.0, 0.0),
+        marker_positions: Some(vec![nalgebra::Vector3::new(0.1, 0.0, 0.0),
+                                    nalgebra::Vector3::new(0.0, 0.1, 0.0)]),
+        marker_labels: Some(vec![0, 0]),
+        marker_names: Some(vec!["Marker1".to_string(), "Marker2".to_string()]),
+    };
+    let msg = NatNetResponse::ModelDef(vec![model::DataSet::RigidBody(body.clone())]);
+    let version = Version::parse("4.0.0").unwrap();
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    assert_eq!(msg, NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap());
+
+    // Marker names are not part of 3.0
+    let version = Version::parse("3.0.0").unwrap();
+    let mut packed = Vec::new();
+    NatNet::pack_with(&version, &msg, &mut packed).unwrap();
+    match NatNet::unpack_with(&version, &mut packed.as_slice()).unwrap() {
+        NatNetResponse::ModelDef(models) => {
+            match models[0] {
+                model::DataSet::RigidBody(ref desc) => {
+                    assert_eq!(desc.marker_positions, body.marker_positions);
+                    assert_eq!(desc.marker_labels, body.marker_labels);
+                    assert!(desc.marker_names.is_none());
+                }
+                _ => assert!(false),
+            }
+        }
+        _ => assert!(false),
+    }
+
+    // Labeled markers refer to rigid body markers through their ID
+    let raw = frame_3_0();
+    let frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    let mut marker = frame.labeled_markers[0].clone();
+    marker.id = (body.id << 16) | 2;
+    assert_eq!(marker.model_id(), body.id);
+    assert_eq!(marker.member_id(), 2);
+}
+
+#[test]
+fn detect_version() {
+    let files = vec![("1.5.0", "2.5.0"), ("1.7.2", "2.7.0"), ("1.9.0", "2.9.0")];
+    for &(motive, natnet) in files.iter() {
+        let version = Version::parse(natnet).unwrap();
+        for i in 1..3 {
+            let f_name = format!("tests/data/frame-motive-{}-00{}.bin", motive, i);
+            let raw = read_fixture(f_name);
+            assert!(NatNet::detect(&raw).contains(&version));
+        }
+        // Ping responses are not frames of data
+        let f_name = format!("tests/data/frame-motive-{}-000.bin", motive);
+        let raw = read_fixture(f_name);
+        assert!(NatNet::detect(&raw).is_empty());
+    }
+    // 3.0 and 4.0 share the same frame layout
+    assert_eq!(NatNet::detect(&frame_3_0()),
+               vec![Version::parse("3.0.0").unwrap(), Version::parse("4.0.0").unwrap()]);
+}
+
+#[test]
+fn auto_version() {
+    let mut raw = Vec::new();
+    for i in 0..3 {
+        let f_name = format!("tests/data/frame-motive-1.9.0-00{}.bin", i);
+        raw.extend(read_fixture(f_name));
+    }
+    let mut parser = AutoNatNet::new();
+    let mut bytes = raw.as_slice();
+    // Frames before a ping response can not be decoded
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    match parser.unpack(&mut frame.as_slice()) {
+        Err(ParseError::UnknownVersion) => {}
+        _ => assert!(false),
+    }
+    assert!(parser.version().is_none());
+    match parser.unpack(&mut bytes).unwrap() {
+        NatNetResponse::Ping(sender) => assert_eq!(parser.version(), Some(&sender.natnet_version)),
+        _ => assert!(false),
+    }
+    for _ in 1..3 {
+        assert!(frame_of(parser.unpack(&mut bytes).unwrap()).force_plates.is_some());
+    }
+    assert!(bytes.is_empty());
+}
+
+#[test]
+fn frame_view() {
+    let files = vec![("1.5.0", "2.5.0"), ("1.7.2", "2.7.0"), ("1.9.0", "2.9.0")];
+    for &(motive, natnet) in files.iter() {
+        let parser = NatNet::new(Version::parse(natnet).unwrap());
+        for i in 1..3 {
+            let f_name = format!("tests/data/frame-motive-{}-00{}.bin", motive, i);
+            let raw = read_fixture(f_name);
+            let view = parser.view(&raw).unwrap();
+            let names = view.marker_sets().map(|set| set.name).collect::<Vec<_>>();
+            assert!(names.contains(&"all"));
+            assert_eq!(view.rigid_bodies().len(), 1);
+            assert_eq!(NatNetResponse::FrameOfData(view.to_owned()),
+                       parser.unpack(&mut raw.as_slice()).unwrap());
+        }
+    }
+    // Ping responses can not be viewed as frames
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    assert!(NatNet::new(Version::parse("2.9.0").unwrap()).view(&raw).is_err());
+
+    // Version 4.1 with all optional data present
+    let raw = frame_3_0();
+    let version = Version::parse("3.0.0").unwrap();
+    let mut frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    let asset = Asset {
+        id: 3,
+        rigid_bodies: frame.rigid_bodies.clone(),
+        markers: frame.labeled_markers.clone(),
+    };
+    frame.assets = Some(vec![asset]);
+    let plate = ForcePlate {
+        id: 1,
+        channels: vec![vec![1.0, 2.0], vec![3.0]],
+    };
+    frame.force_plates = Some(vec![plate]);
+    let device = Device {
+        id: 2,
+        channels: vec![vec![4.0]],
+    };
+    frame.devices = Some(vec![device]);
+    frame.precision_timestamp = Some((10, 20));
+    let parser = NatNet::new(Version::parse("4.1.0").unwrap());
+    let mut packed = Vec::new();
+    parser.pack(&NatNetResponse::FrameOfData(frame.clone()), &mut packed).unwrap();
+    let view = parser.view(&packed).unwrap();
+    assert_eq!(view.to_owned(), frame);
+    // Truncated frames are rejected up front
+    let len = packed.len();
+    assert!(parser.view(&packed[..len - 4]).is_err());
+}
+
+#[test]
+fn decode_limits() {
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let version = Version::parse("2.9.0").unwrap();
+    // Default limits never reject a valid message
+    let parser = NatNet::new(version.clone());
+    assert_eq!(*parser.limits(), DecodeLimits::default());
+    assert!(parser.unpack(&mut raw.as_slice()).is_ok());
+
+    let limits = DecodeLimits { max_marker_sets: 0, ..DecodeLimits::default() };
+    let parser = NatNet::new(version.clone()).with_limits(limits);
+    match *parser.unpack(&mut raw.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, max, .. } => {
+            assert_eq!(limit, "max_marker_sets");
+            assert_eq!(max, 0);
+        }
+        _ => assert!(false),
+    }
+    let limits = DecodeLimits { max_string_length: 2, ..DecodeLimits::default() };
+    let parser = NatNet::new(version.clone()).with_limits(limits);
+    match *parser.unpack(&mut raw.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, .. } => assert_eq!(limit, "max_string_length"),
+        _ => assert!(false),
+    }
+    let limits = DecodeLimits { max_message_size: 16, ..DecodeLimits::default() };
+    let parser = NatNet::new(version.clone()).with_limits(limits);
+    match *parser.unpack(&mut raw.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, .. } => assert_eq!(limit, "max_message_size"),
+        _ => assert!(false),
+    }
+
+    // Huge counts are rejected before anything is read
+    let mut raw = Vec::new();
+    raw.write_u16::<LittleEndian>(7).unwrap();
+    raw.write_u16::<LittleEndian>(8).unwrap();
+    raw.write_i32::<LittleEndian>(1).unwrap();
+    raw.write_i32::<LittleEndian>(i32::max_value()).unwrap();
+    match *NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, value, .. } => {
+            assert_eq!(limit, "max_marker_sets");
+            assert_eq!(value, i32::max_value() as u64);
+        }
+        _ => assert!(false),
+    }
+
+    // Force plate samples are limited over all channels
+    let plate = ForcePlate {
+        id: 1,
+        channels: vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+    };
+    let ver = Version::parse("3.0.0").unwrap();
+    let mut frame = frame_of(NatNet::unpack_with(&ver, &mut frame_3_0().as_slice()).unwrap());
+    frame.force_plates = Some(vec![plate]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&ver, &NatNetResponse::FrameOfData(frame.clone()), &mut packed).unwrap();
+    let limits = DecodeLimits { max_force_plate_samples: 3, ..DecodeLimits::default() };
+    match *NatNet::unpack_with_limits(&ver, &limits, &mut packed.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, value, .. } => {
+            assert_eq!(limit, "max_force_plate_samples");
+            assert_eq!(value, 4);
+        }
+        _ => assert!(false),
+    }
+
+    // Device samples have a limit of their own
+    frame.force_plates = Some(vec![]);
+    frame.devices = Some(vec![Device {
+                                  id: 2,
+                                  channels: vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+                              }]);
+    let mut packed = Vec::new();
+    NatNet::pack_with(&ver, &NatNetResponse::FrameOfData(frame), &mut packed).unwrap();
+    assert!(NatNet::unpack_with_limits(&ver, &limits, &mut packed.as_slice()).is_ok());
+    let limits = DecodeLimits { max_device_samples: 3, ..DecodeLimits::default() };
+    match *NatNet::unpack_with_limits(&ver, &limits, &mut packed.as_slice()).unwrap_err().kind() {
+        ParseError::LimitExceeded { limit, value, .. } => {
+            assert_eq!(limit, "max_device_samples");
+            assert_eq!(value, 4);
+        }
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn corrupt_input() {
+    // Decoding arbitrary bytes must never panic
+    let mut inputs = Vec::new();
+    for f_name in ["tests/data/frame-motive-1.5.0-001.bin",
+                   "tests/data/frame-motive-1.9.0-000.bin",
+                   "tests/data/frame-motive-1.9.0-001.bin"]
+        .iter() {
+        let raw = read_fixture(f_name);
+        inputs.push(raw);
+    }
+    inputs.push(frame_3_0());
+    // Sender name without null byte
+    let mut raw = Vec::new();
+    raw.write_u16::<LittleEndian>(1).unwrap();
+    raw.write_u16::<LittleEndian>(264).unwrap();
+    raw.extend(std::iter::repeat(b'a').take(264));
+    inputs.push(raw);
+
+    let versions = natnet_decode::SUPPORTED_VERSIONS.iter()
+        .map(|v| Version::parse(v).unwrap())
+        .collect::<Vec<_>>();
+    let check = |raw: &[u8]| {
+        for ver in &versions {
+            let _ = NatNet::unpack_with(ver, &mut &raw[..]);
+            let parser = NatNet::new(ver.clone());
+            if let Ok(view) = parser.view(raw) {
+                let _ = view.to_owned();
+            }
+        }
+        let _ = NatNet::detect(raw);
+        let _ = AutoNatNet::new().unpack(&mut &raw[..]);
+    };
+    for raw in &inputs {
+        for len in 0..raw.len() {
+            check(&raw[..len]);
+        }
+        for i in 0..raw.len() {
+            for val in [0x7f, 0xff].iter() {
+                let mut corrupt = raw.clone();
+                corrupt[i] = *val;
+                check(&corrupt);
+            }
+        }
+    }
+}
+
+#[test]
+fn error_context() {
+    let ver = Version::parse("2.5.0").unwrap();
+    let mut frame = frame_of(NatNet::unpack_with(&Version::parse("3.0.0").unwrap(),
+                                                 &mut frame_3_0().as_slice())
+        .unwrap());
+    frame.rigid_bodies[0].markers = vec![nalgebra::Point3::new(1.0, 2.0, 3.0); 3];
+    frame.rigid_bodies[0].marker_ids = vec![1, 2, 3];
+    frame.rigid_bodies[0].marker_sizes = vec![0.1, 0.2, 0.3];
+    let mut packed = Vec::new();
+    NatNet::pack_with(&ver, &NatNetResponse::FrameOfData(frame), &mut packed).unwrap();
+
+    // Truncated messages report the field and offset where decoding stopped
+    let (len, err) = (0..packed.len())
+        .filter_map(|len| NatNet::unpack_with(&ver, &mut &packed[..len]).err().map(|e| (len, e)))
+        .find(|&(_, ref err)| err.path() == Some("frame.rigid_bodies[0].marker_sizes[2]"))
+        .unwrap();
+    match *err.kind() {
+        ParseError::NotEnoughBytes => {}
+        _ => assert!(false),
+    }
+    assert_eq!(err.msg_id(), Some(7));
+    assert_eq!(err.offset(), Some(len));
+    assert!(format!("{}", err).contains("frame.rigid_bodies[0].marker_sizes[2]"));
+
+    // Invalid End-Of-Data marker
+    let len = packed.len();
+    packed[len - 4] = 1;
+    let err = NatNet::unpack_with(&ver, &mut packed.as_slice()).unwrap_err();
+    match *err.kind() {
+        ParseError::EndOfDataMismatch { found } => assert_eq!(found, 1),
+        _ => assert!(false),
+    }
+    assert_eq!(err.path(), Some("frame.end_of_data"));
+    assert_eq!(err.offset(), Some(len));
+
+    // Negative counts
+    let mut raw = Vec::new();
+    raw.write_u16::<LittleEndian>(7).unwrap();
+    raw.write_u16::<LittleEndian>(8).unwrap();
+    raw.write_i32::<LittleEndian>(1).unwrap();
+    raw.write_i32::<LittleEndian>(-1).unwrap();
+    let err = NatNet::unpack_with(&ver, &mut raw.as_slice()).unwrap_err();
+    match *err.kind() {
+        ParseError::InvalidCount(num) => assert_eq!(num, -1),
+        _ => assert!(false),
+    }
+    assert_eq!(err.path(), Some("frame.marker_sets"));
+    assert_eq!(err.offset(), Some(12));
+
+    // Negative dataset types
+    let mut raw = Vec::new();
+    raw.write_u16::<LittleEndian>(5).unwrap();
+    raw.write_u16::<LittleEndian>(8).unwrap();
+    raw.write_i32::<LittleEndian>(1).unwrap();
+    raw.write_i32::<LittleEndian>(-2).unwrap();
+    let err = NatNet::unpack_with(&ver, &mut raw.as_slice()).unwrap_err();
+    match *err.kind() {
+        ParseError::InvalidDataSetType(t) => assert_eq!(t, -2),
+        _ => assert!(false),
+    }
+    assert_eq!(err.path(), Some("models[0]"));
+
+    // Strings which are not UTF-8 keep their bytes
+    let raw = [8, 0, 3, 0, b'a', 0xff, 0];
+    let err = NatNet::unpack_with(&ver, &mut &raw[..]).unwrap_err();
+    match *err.kind() {
+        ParseError::StringError { ref bytes, ref utf8_error } => {
+            assert_eq!(*bytes, vec![b'a', 0xff]);
+            assert_eq!(utf8_error.unwrap().valid_up_to(), 1);
+        }
+        _ => assert!(false),
+    }
+    assert_eq!(err.path(), Some("message"));
+}
+
+#[test]
+fn size_check() {
+    let version = Version::parse("2.9.0").unwrap();
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    let num_bytes = frame.len() - 4;
+
+    // Trailing bytes in the payload followed by another message
+    let mut raw = frame.clone();
+    raw.extend_from_slice(&[1, 2, 3]);
+    (&mut raw[2..4]).write_u16::<LittleEndian>(num_bytes as u16 + 3).unwrap();
+    raw.extend_from_slice(&ping);
+    let lenient = NatNet::new(version.clone());
+    assert_eq!(lenient.size_check(), SizeCheck::Lenient);
+    let mut bytes = raw.as_slice();
+    assert!(lenient.unpack(&mut bytes).is_ok());
+    assert_eq!(bytes, ping.as_slice());
+    let strict = NatNet::new(version.clone()).with_size_check(SizeCheck::Strict);
+    let mut bytes = raw.as_slice();
+    let err = strict.unpack(&mut bytes).unwrap_err();
+    match *err.kind() {
+        ParseError::SizeMismatch { declared, consumed } => {
+            assert_eq!(declared, num_bytes + 3);
+            assert_eq!(consumed, num_bytes);
+        }
+        _ => assert!(false),
+    }
+    assert_eq!(bytes, ping.as_slice());
+    assert!(strict.unpack(&mut bytes).is_ok());
+
+    // Header declaring fewer bytes than the layout needs
+    let mut raw = frame.clone();
+    (&mut raw[2..4]).write_u16::<LittleEndian>(num_bytes as u16 - 4).unwrap();
+    for parser in &[&lenient, &strict] {
+        let mut bytes = raw.as_slice();
+        let err = parser.unpack(&mut bytes).unwrap_err();
+        match *err.kind() {
+            ParseError::SizeMismatch { declared, consumed } => {
+                assert_eq!(declared, num_bytes - 4);
+                assert_eq!(consumed, num_bytes);
+            }
+            _ => assert!(false),
+        }
+        assert_eq!(err.path(), Some("frame.end_of_data"));
+        // The source is never read past the end of the message
+        assert_eq!(bytes.len(), 4);
+    }
+
+    // A message failing to decode is skipped up to its declared end
+    let mut raw = frame.clone();
+    (&mut raw[8..12]).write_i32::<LittleEndian>(-1).unwrap();
+    raw.extend_from_slice(&ping);
+    for parser in &[&lenient, &strict] {
+        let mut bytes = raw.as_slice();
+        match *parser.unpack(&mut bytes).unwrap_err().kind() {
+            ParseError::InvalidCount(-1) => {}
+            _ => assert!(false),
+        }
+        assert_eq!(bytes, ping.as_slice());
+        match parser.unpack(&mut bytes).unwrap() {
+            NatNetResponse::Ping(_) => {}
+            _ => assert!(false),
+        }
+    }
+}
+
+#[test]
+fn dispatch() {
+    let version = Version::parse("2.9.0").unwrap();
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+
+    let header = natnet_decode::peek_header(&frame).unwrap();
+    assert_eq!(header,
+               MessageHeader {
+                   msg_type: NatNetMsgType::FrameOfData,
+                   num_bytes: frame.len() as u16 - 4,
+               });
+    assert_eq!(header.message_len(), frame.len());
+    assert_eq!(natnet_decode::peek_header(&ping).unwrap().msg_type,
+               NatNetMsgType::PingResponse);
+    assert!(natnet_decode::peek_header(&frame[..3]).is_err());
+    assert_eq!(NatNetMsgType::try_from(100).unwrap(),
+               NatNetMsgType::UnrecognizedRequest);
+    match NatNetMsgType::try_from(42) {
+        Err(ParseError::UnknownResponse(42)) => {}
+        _ => assert!(false),
+    }
+
+    // Ping, unknown message and frame of data back to back
+    let mut raw = ping.clone();
+    raw.extend_from_slice(&[42, 0, 3, 0, 1, 2, 3]);
+    raw.extend_from_slice(&frame);
+    let frames = Dispatcher::new(NatNet::new(version.clone()))
+        .subscribe(NatNetMsgType::FrameOfData);
+    assert!(frames.is_subscribed(NatNetMsgType::FrameOfData));
+    assert!(!frames.is_subscribed(NatNetMsgType::PingResponse));
+    let mut bytes = raw.as_slice();
+    assert!(frames.dispatch(&mut bytes).unwrap().is_none());
+    assert_eq!(bytes.len(), raw.len() - ping.len());
+    assert!(frames.dispatch(&mut bytes).unwrap().is_none());
+    assert_eq!(bytes, frame.as_slice());
+    match frames.dispatch(&mut bytes).unwrap() {
+        Some(NatNetResponse::FrameOfData(_)) => {}
+        _ => assert!(false),
+    }
+    assert!(bytes.is_empty());
+
+    // A subscribed message failing to decode leaves the source at the next
+    let mut bad = frame.clone();
+    (&mut bad[8..12]).write_i32::<LittleEndian>(-1).unwrap();
+    bad.extend_from_slice(&frame);
+    let mut bytes = bad.as_slice();
+    assert!(frames.dispatch(&mut bytes).is_err());
+    assert_eq!(bytes, frame.as_slice());
+    match frames.dispatch(&mut bytes).unwrap() {
+        Some(NatNetResponse::FrameOfData(_)) => {}
+        _ => assert!(false),
+    }
+
+    // Messages of other types are left in the source
+    let mut bytes = raw.as_slice();
+    assert!(NatNet::unpack_type_with(NatNetMsgType::FrameOfData, &version, &mut bytes).is_none());
+    assert_eq!(bytes.len(), raw.len());
+    match NatNet::unpack_type_with(NatNetMsgType::PingResponse, &version, &mut bytes) {
+        Some(Ok(NatNetResponse::Ping(_))) => {}
+        _ => assert!(false),
+    }
+    assert_eq!(bytes.len(), raw.len() - ping.len());
+
+    let pings = frames.unsubscribe(NatNetMsgType::FrameOfData)
+        .subscribe(NatNetMsgType::PingResponse);
+    let mut bytes = raw.as_slice();
+    match pings.dispatch(&mut bytes).unwrap() {
+        Some(NatNetResponse::Ping(sender)) => assert_eq!(sender.name, "NatNetLib"),
+        _ => assert!(false),
+    }
+    assert!(pings.dispatch(&mut bytes).unwrap().is_none());
+    assert!(pings.dispatch(&mut bytes).unwrap().is_none());
+    assert!(bytes.is_empty());
+
+    // A skipped message cut short is an error
+    let mut bytes = &frame[..frame.len() - 1];
+    match pings.dispatch(&mut bytes) {
+        Err(ParseError::NotEnoughBytes) => {}
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn message_stream() {
+    let parser = NatNet::new(Version::parse("2.9.0").unwrap());
+    let mut raw = Vec::new();
+    let mut offsets = Vec::new();
+    for i in 0..3 {
+        offsets.push(raw.len());
+        let f_name = format!("tests/data/frame-motive-1.9.0-00{}.bin", i);
+        raw.extend(read_fixture(f_name));
+    }
+
+    let mut messages = parser.messages(raw.as_slice());
+    for offset in offsets.iter() {
+        assert!(messages.next().unwrap().is_ok());
+        assert_eq!(messages.offset(), *offset);
+    }
+    assert!(messages.next().is_none());
+    assert!(messages.next().is_none());
+    assert_eq!(parser.messages(&raw[..0]).count(), 0);
+
+    // Truncated tail
+    for cut in [1, offsets[2] - offsets[1] - 2].iter() {
+        let mut messages = parser.messages(&raw[..raw.len() - cut]);
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_ok());
+        match messages.next().unwrap().unwrap_err().kind() {
+            &ParseError::NotEnoughBytes => assert_eq!(messages.offset(), offsets[2]),
+            _ => assert!(false),
+        }
+        assert!(messages.next().is_none());
+    }
+
+    // Corrupt count in the middle message
+    let mut corrupt = raw.clone();
+    for b in corrupt[offsets[1] + 8..offsets[1] + 12].iter_mut() {
+        *b = 0xff;
+    }
+    let mut messages = parser.messages(corrupt.as_slice());
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().unwrap().is_err());
+    assert_eq!(messages.offset(), offsets[1]);
+    assert!(messages.next().unwrap().is_ok());
+    assert_eq!(messages.offset(), offsets[2]);
+    assert!(messages.next().is_none());
+
+    // Corrupt size in the middle message
+    let mut corrupt = raw.clone();
+    corrupt[offsets[1] + 2] = 0xff;
+    corrupt[offsets[1] + 3] = 0xff;
+    let mut messages = parser.messages(corrupt.as_slice());
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().unwrap().is_ok());
+    assert_eq!(messages.offset(), offsets[2]);
+    assert!(messages.next().is_none());
+}
+
+#[test]
+fn commands() {
+    let cmd = Command::SetRecordTakeName("take_1".to_string());
+    let bytes: Vec<u8> = NatNetRequest::Command(cmd.clone()).into();
+    assert_eq!(bytes, cmd.encode());
+    assert_eq!(&bytes[..4], &[2, 0, 25, 0]);
+    assert_eq!(&bytes[4..], b"SetRecordTakeName,take_1\0");
+    let cmd = Command::SetProperty {
+        node: "".to_string(),
+        name: "Recording Delay".to_string(),
+        value: "3".to_string(),
+    };
+    assert_eq!(cmd.to_string(), "SetProperty,,Recording Delay,3");
+    assert_eq!(Command::SetPlaybackStartFrame(100).to_string(),
+               "SetPlaybackStartFrame,100");
+    assert_eq!(&Command::TimelinePlay.encode()[4..], b"TimelinePlay\0");
+
+    // Responses are unpacked from the wire and decoded per command
+    let version = Version::parse("2.9.0").unwrap();
+    let response = |msg: NatNetResponse| {
+        let mut bytes = Vec::new();
+        NatNet::pack_with(&version, &msg, &mut bytes).unwrap();
+        NatNet::unpack_with(&version, &mut bytes.as_slice()).unwrap()
+    };
+    let start = Command::StartRecording;
+    assert_eq!(start.decode_response(&response(NatNetResponse::Response(0))).unwrap(),
+               CommandResponse::Success);
+    assert_eq!(start.decode_response(&response(NatNetResponse::Response(1))).unwrap(),
+               CommandResponse::Failure(1));
+    assert_eq!(start.decode_response(&response(NatNetResponse::UnrecognizedRequest)).unwrap(),
+               CommandResponse::Unrecognized);
+    assert!(start.decode_response(&response(NatNetResponse::ResponseString("ok".to_string())))
+        .is_err());
+    let get = Command::GetProperty {
+        node: "".to_string(),
+        name: "Recording Delay".to_string(),
+    };
+    for value in ["3", "3.0", "3.00"].iter() {
+        let msg = response(NatNetResponse::ResponseString(value.to_string()));
+        assert_eq!(get.decode_response(&msg).unwrap(),
+                   CommandResponse::Property(value.to_string()));
+    }
+    assert!(get.decode_response(&NatNetResponse::MessageString("3".to_string())).is_err());
+}
+
+#[test]
+fn connection_messages() {
+    // Requests
+    let keep_alive: Vec<u8> = NatNetRequest::KeepAlive.into();
+    assert_eq!(keep_alive, vec![10, 0, 0, 0]);
+    let disconnect: Vec<u8> = NatNetRequest::Disconnect.into();
+    assert_eq!(disconnect, vec![9, 0, 0, 0]);
+    let options = ConnectOptions {
+        bitstream_version: Some(Version::parse("4.1.0").unwrap()),
+        ..ConnectOptions::default()
+    };
+    let connect: Vec<u8> = NatNetRequest::Connect(options).into();
+    assert_eq!(connect.len(), 4 + 269);
+    assert_eq!(&connect[..4], &[0, 0, 13, 1]);
+    assert_eq!(&connect[4..14], b"NatNetLib\0");
+    assert_eq!(&connect[268..], &[0, 4, 1, 0, 0]);
+    assert_eq!(NatNetMsgType::try_from(11).unwrap(),
+               NatNetMsgType::DisconnectByTimeout);
+
+    // Disconnect messages from the server
+    let version = Version::parse("3.0.0").unwrap();
+    for &(raw, ref msg) in &[([9, 0, 0, 0], NatNetResponse::Disconnect),
+                             ([11, 0, 0, 0], NatNetResponse::DisconnectByTimeout)] {
+        assert_eq!(&NatNet::unpack_with(&version, &mut &raw[..]).unwrap(), msg);
+        let mut packed = Vec::new();
+        NatNet::pack_with(&version, msg, &mut packed).unwrap();
+        assert_eq!(packed, raw);
+    }
+
+    // Server description extends the ping response
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    let version = Version::parse("2.9.0").unwrap();
+    let sender = match NatNet::unpack_with(&version, &mut ping.as_slice()).unwrap() {
+        NatNetResponse::Ping(sender) => sender,
+        _ => panic!("Expected ping response"),
+    };
+    let desc = ServerDescription {
+        sender: sender,
+        high_resolution_clock_frequency: 3_330_000,
+        data_port: 1511,
+        is_multicast: true,
+        multicast_group: "239.255.42.99".parse().unwrap(),
+    };
+    let mut raw = Vec::new();
+    NatNet::pack_with(&version,
+                      &NatNetResponse::ServerInfo(desc.clone()),
+                      &mut raw)
+        .unwrap();
+    assert_eq!(raw.len(), ping.len() + 15);
+    assert_eq!(&raw[4..ping.len()], &ping[4..]);
+    assert_eq!(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap(),
+               NatNetResponse::ServerInfo(desc.clone()));
+    let mut parser = AutoNatNet::new();
+    assert!(parser.unpack(&mut raw.as_slice()).is_ok());
+    assert_eq!(parser.version(), Some(&desc.sender.natnet_version));
+}
+
+#[test]
+fn frame_index() {
+    let version = Version::parse("2.9.0").unwrap();
+    let parser = NatNet::new(version.clone());
+    let mut frame = frame_of(test_parse(&parser,
+                                        "tests/data/frame-motive-1.9.0-001.bin".to_string()));
+    let pack = |version: &Version, frame: &natnet_decode::FrameOfData| {
+        let mut bytes = Vec::new();
+        NatNet::pack_with(version, &NatNetResponse::FrameOfData(frame.clone()), &mut bytes)
+            .unwrap();
+        bytes
+    };
+
+    // Messages stored back to back, with other messages and garbage between
+    let path = env::temp_dir().join(format!("natnet-decode-index-{}.bin", process::id()));
+    let mut bytes = Vec::new();
+    for i in 0..200 {
+        frame.frame_number = 100 + i;
+        frame.timestamp = Some(i as f64 / 100.0);
+        bytes.extend(pack(&version, &frame));
+        if i == 50 {
+            NatNet::pack_with(&version, &NatNetResponse::ModelDef(Vec::new()), &mut bytes)
+                .unwrap();
+            bytes.extend_from_slice(&[0xff; 7]);
         }
     }
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    let sidecar = FrameIndex::sidecar_path(&path);
+    let _ = fs::remove_file(&sidecar);
+    let mut reader = IndexedReader::open_messages(parser.clone(), &path).unwrap();
+    assert_eq!(reader.index().entries().len(), 200);
+    assert!(sidecar.exists());
+    reader.seek_frame(150);
+    assert_eq!(reader.next_frame().unwrap().unwrap().frame_number, 150);
+    assert_eq!(reader.next_frame().unwrap().unwrap().frame_number, 151);
+    reader.seek_time(1.234);
+    assert_eq!(reader.next_frame().unwrap().unwrap().frame_number, 224);
+    let range = reader.read_frames(120..125).unwrap();
+    assert_eq!(range.iter().map(|f| f.frame_number).collect::<Vec<_>>(),
+               vec![120, 121, 122, 123, 124]);
+    reader.seek_frame(1000);
+    assert!(reader.next_frame().unwrap().is_none());
+
+    // The stored index is used, and rebuilt when corrupt or out of date
+    let index = reader.index().clone();
+    assert_eq!(FrameIndex::open_messages(&parser, &path).unwrap(), index);
+    File::create(&sidecar).unwrap().write_all(b"NNIDX").unwrap();
+    assert_eq!(FrameIndex::open_messages(&parser, &path).unwrap(), index);
+    frame.frame_number = 300;
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(&pack(&version, &frame))
+        .unwrap();
+    let index = FrameIndex::open_messages(&parser, &path).unwrap();
+    assert_eq!(index.entries().len(), 201);
+    assert_eq!(index.find_frame(300), 200);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&sidecar).unwrap();
+
+    // A recording which changes version in the middle
+    let path = env::temp_dir().join(format!("natnet-decode-index-{}.nnrec", process::id()));
+    let sender = natnet_decode::Sender {
+        name: "Mock".to_string(),
+        version: Version::parse("1.9.0").unwrap(),
+        natnet_version: version.clone(),
+    };
+    let mut writer = RecordingWriter::new(File::create(&path).unwrap(), &sender, &[]).unwrap();
+    for i in 0..50 {
+        frame.frame_number = i;
+        writer.write_packet(SystemTime::now(), &pack(&version, &frame)).unwrap();
+    }
+    let new_version = Version::parse("3.0.0").unwrap();
+    let models = vec![model::DataSet::MarkerSet(model::MarkerSet {
+                          name: "Triangle".to_string(),
+                          markers: vec!["1".to_string()],
+                      })];
+    writer.write_sender(SystemTime::now(),
+                      &natnet_decode::Sender { natnet_version: new_version.clone(), ..sender })
+        .unwrap();
+    writer.write_models(SystemTime::now(), &models).unwrap();
+    let mut new_frame =
+        frame_of(NatNet::unpack_with(&new_version, &mut frame_3_0().as_slice()).unwrap());
+    for i in 50..100 {
+        new_frame.frame_number = i;
+        writer.write_packet(SystemTime::now(), &pack(&new_version, &new_frame)).unwrap();
+    }
+    drop(writer);
+    let sidecar = FrameIndex::sidecar_path(&path);
+    let _ = fs::remove_file(&sidecar);
+    let mut reader = IndexedReader::open_recording(&path).unwrap();
+    assert_eq!(reader.index().entries().len(), 100);
+    reader.seek_frame(75);
+    new_frame.frame_number = 75;
+    assert_eq!(reader.next_frame().unwrap().unwrap(), new_frame);
+    reader.seek_frame(10);
+    frame.frame_number = 10;
+    assert_eq!(reader.next_frame().unwrap().unwrap(), frame);
+    assert_eq!(reader.read_frames(48..52).unwrap().len(), 4);
+
+    // A corrupt record in the middle is an error, and no index is stored
+    let corrupt = reader.index().entries()[25].offset;
+    fs::remove_file(&sidecar).unwrap();
+    let mut bytes = read_fixture(&path);
+    bytes[corrupt as usize + 20] ^= 0xff;
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    match FrameIndex::open_recording(&path) {
+        Err(ParseError::CorruptRecording { offset }) => assert_eq!(offset, corrupt),
+        res => panic!("Expected corrupt recording, got {:?}", res),
+    }
+    assert!(!sidecar.exists());
+    fs::remove_file(&path).unwrap();
 }
@@ -0,0 +1,750 @@
+extern crate byteorder;
+#[cfg(feature = "tokio")]
+extern crate futures_util;
+extern crate natnet_decode;
+extern crate semver;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+mod common;
+
+use common::{frame_3_0, frame_of, read_fixture};
+use natnet_decode::{Command, CommandResponse, DataType, NatNet, NatNetRequest, NatNetResponse,
+                    ParseError, ServerDescription};
+use natnet_decode::client::{Client, ClientConfig};
+use natnet_decode::mock::{Fault, MockServer, RandomFaults};
+use natnet_decode::model;
+use natnet_decode::recording::{Record, RecordingReader, RecordingWriter};
+use natnet_decode::replay::{Destination, ReplayConfig, Replayer};
+use natnet_decode::server::{Server, ServerConfig};
+use semver::Version;
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::net::UdpSocket;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Client of a server on the loopback interface with command port `port`
+///
+/// Frames of data are received on a port of their own, instead of from a
+/// multicast group.
+fn loopback_config(port: u16) -> ClientConfig {
+    ClientConfig {
+        command_port: port,
+        data_port: 0,
+        multicast_group: None,
+        interface: "127.0.0.1".parse().unwrap(),
+        timeout: Duration::from_secs(5),
+        frame_timeout: Some(Duration::from_secs(5)),
+        ..ClientConfig::default()
+    }
+}
+
+#[test]
+fn loopback_client() {
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let version = Version::parse("2.9.0").unwrap();
+
+    // Stand-in server answering the requests of a single client
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let reply = ping.clone();
+    let ver = version.clone();
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        let (_, client) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..2], &[0, 0]);
+        server.send_to(&reply, client).unwrap();
+        let (len, _) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[4, 0, 0, 0]);
+        let mut models = Vec::new();
+        NatNet::pack_with(&ver, &NatNetResponse::ModelDef(Vec::new()), &mut models).unwrap();
+        server.send_to(&models, client).unwrap();
+        let (len, _) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[4..len], b"StartRecording\0");
+        // Unrelated messages are dropped while waiting for the response
+        let mut msg = Vec::new();
+        NatNet::pack_with(&ver, &NatNetResponse::MessageString("hi".to_string()), &mut msg)
+            .unwrap();
+        server.send_to(&msg, client).unwrap();
+        let mut msg = Vec::new();
+        NatNet::pack_with(&ver, &NatNetResponse::Response(0), &mut msg).unwrap();
+        server.send_to(&msg, client).unwrap();
+        server
+    });
+
+    let config = ClientConfig {
+        frame_timeout: Some(Duration::from_millis(300)),
+        server_timeout: Some(Duration::from_millis(50)),
+        ..loopback_config(port)
+    };
+    let mut client = Client::connect(config).unwrap();
+    assert_eq!(client.sender().name, "NatNetLib");
+    assert_eq!(*client.parser().version(), version);
+    assert!(client.server_description().is_none());
+    assert!(client.models().is_empty());
+    assert_eq!(client.request(Command::StartRecording).unwrap(),
+               CommandResponse::Success);
+    let server = handle.join().unwrap();
+
+    let data = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = ("127.0.0.1", client.data_addr().unwrap().port());
+    data.send_to(&ping, addr).unwrap();
+    data.send_to(&frame, addr).unwrap();
+    let expected = frame_of(NatNet::unpack_with(&version, &mut frame.as_slice()).unwrap());
+    assert_eq!(client.recv_frame().unwrap(), expected);
+
+    // A quiet server is not taken for a lost server in multicast mode
+    match client.recv_frame() {
+        Err(ParseError::IO(ref err)) if err.kind() == io::ErrorKind::TimedOut => {}
+        res => panic!("Expected time out, got {:?}", res),
+    }
+    server.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    assert!(server.recv_from(&mut [0u8; 1024]).is_err());
+}
+
+#[test]
+fn unicast_client() {
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let version = Version::parse("2.9.0").unwrap();
+    let sender = match NatNet::unpack_with(&version, &mut ping.as_slice()).unwrap() {
+        NatNetResponse::Ping(sender) => sender,
+        _ => panic!("Expected ping response"),
+    };
+    let desc = ServerDescription {
+        sender: sender,
+        high_resolution_clock_frequency: 3_330_000,
+        data_port: 1511,
+        is_multicast: false,
+        multicast_group: "0.0.0.0".parse().unwrap(),
+    };
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let port = server.local_addr().unwrap().port();
+    let data = frame.clone();
+    let info = desc.clone();
+    let handle = thread::spawn(move || {
+        let pack = |msg: NatNetResponse| {
+            let mut bytes = Vec::new();
+            NatNet::pack_with(&version, &msg, &mut bytes).unwrap();
+            bytes
+        };
+        // Next request other than a keep-alive, and the number of keep-alives
+        let recv = || {
+            let mut buf = [0u8; 1024];
+            let mut keep_alives = 0;
+            loop {
+                let (len, client) = server.recv_from(&mut buf).unwrap();
+                if &buf[..len] == &[10, 0, 0, 0] {
+                    keep_alives += 1;
+                } else {
+                    return (buf[..len].to_vec(), client, keep_alives);
+                }
+            }
+        };
+        for i in 0..2 {
+            // Handshake, subscription and model definitions
+            let (req, client, keep_alives) = recv();
+            // Keep-alives are sent until the client gives up on the server
+            assert_eq!(keep_alives > 0, i > 0);
+            assert_eq!(&req[..2], &[0, 0]);
+            assert_eq!(req[4 + 264], 1);
+            server.send_to(&pack(NatNetResponse::ServerInfo(info.clone())), client).unwrap();
+            let (req, client, _) = recv();
+            assert_eq!(&req[4..], b"SubscribeToData,RigidBody,body\0");
+            server.send_to(&pack(NatNetResponse::Response(0)), client).unwrap();
+            let (req, client, _) = recv();
+            assert_eq!(req, vec![4, 0, 0, 0]);
+            server.send_to(&pack(NatNetResponse::ModelDef(Vec::new())), client).unwrap();
+            server.send_to(&data, client).unwrap();
+        }
+        let (req, _, _) = recv();
+        assert_eq!(req, vec![9, 0, 0, 0]);
+    });
+
+    let config = ClientConfig {
+        unicast: true,
+        keep_alive: Duration::from_millis(20),
+        subscriptions: vec![(DataType::RigidBody, "body".to_string())],
+        server_timeout: Some(Duration::from_millis(200)),
+        ..loopback_config(port)
+    };
+    let mut client = Client::connect(config).unwrap();
+    assert_eq!(client.server_description(), Some(&desc));
+    assert_eq!(client.data_addr().unwrap(), client.command_addr().unwrap());
+    assert!(client.recv_frame().is_ok());
+    // The server goes quiet after the first frame
+    assert!(client.recv_frame().is_ok());
+    drop(client);
+    handle.join().unwrap();
+
+    // Keep-alives are sent while the client is busy with other things
+    let mock = MockServer::start(desc.sender.clone()).unwrap();
+    let client = Client::connect(ClientConfig {
+            unicast: true,
+            keep_alive: Duration::from_millis(20),
+            ..loopback_config(mock.command_addr().port())
+        })
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let keep_alives = mock.requests().iter().filter(|req| **req == [10, 0, 0, 0]).count();
+    assert!(keep_alives >= 3, "Only {} keep-alives sent", keep_alives);
+    drop(client);
+}
+
+#[test]
+fn loopback_server() {
+    let version = Version::parse("2.9.0").unwrap();
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    let models = vec![model::DataSet::MarkerSet(model::MarkerSet {
+                          name: "Triangle".to_string(),
+                          markers: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+                      })];
+
+    // Multicast server sending to a plain socket instead of a group
+    let data = UdpSocket::bind("127.0.0.1:0").unwrap();
+    data.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut config = ServerConfig {
+        command_addr: "127.0.0.1:0".parse().unwrap(),
+        data_addr: Some(data.local_addr().unwrap()),
+        interface: "127.0.0.1".parse().unwrap(),
+        models: models.clone(),
+        ..ServerConfig::default()
+    };
+    config.sender.natnet_version = version.clone();
+    let server = Server::start(config).unwrap();
+    let client = Client::connect(loopback_config(server.command_addr().port())).unwrap();
+    assert_eq!(client.sender().name, "NatNetDecode");
+    assert_eq!(*client.parser().version(), version);
+    assert_eq!(client.models(), models.as_slice());
+    assert!(server.clients().is_empty());
+    server.publish(frame.clone());
+    let mut buf = vec![0u8; 65536];
+    let len = data.recv(&mut buf).unwrap();
+    assert_eq!(NatNet::unpack_with(&version, &mut &buf[..len]).unwrap(),
+               NatNetResponse::FrameOfData(frame.clone()));
+    drop(client);
+    drop(server);
+
+    // Unicast server using `NatNet` 3.0
+    let version = Version::parse("3.0.0").unwrap();
+    let frame = frame_of(NatNet::unpack_with(&version, &mut frame_3_0().as_slice()).unwrap());
+    let mut config = ServerConfig {
+        command_addr: "127.0.0.1:0".parse().unwrap(),
+        data_addr: None,
+        unicast: true,
+        ..ServerConfig::default()
+    };
+    config.sender.natnet_version = version.clone();
+    let server = Server::start(config).unwrap();
+    let mut client = Client::connect(ClientConfig {
+            unicast: true,
+            ..loopback_config(server.command_addr().port())
+        })
+        .unwrap();
+    let desc = client.server_description().unwrap().clone();
+    assert_eq!(desc.sender.natnet_version, version);
+    assert!(!desc.is_multicast);
+    assert_eq!(server.clients(), vec![client.command_addr().unwrap()]);
+    server.publish(frame.clone());
+    assert_eq!(client.recv_frame().unwrap(), frame);
+    let addr = client.command_addr().unwrap();
+    drop(client);
+    // The disconnect message removes the client
+    for _ in 0..100 {
+        if !server.clients().contains(&addr) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(server.clients().is_empty());
+
+    // A ping with a payload of any size connects
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let ping: Vec<u8> = NatNetRequest::Ping(CString::new("Ping").unwrap()).into();
+    socket.send_to(&ping, server.command_addr()).unwrap();
+    let mut buf = [0; 1024];
+    socket.recv_from(&mut buf).unwrap();
+    assert_eq!(server.clients(), vec![socket.local_addr().unwrap()]);
+}
+
+#[test]
+fn mock_server() {
+    let version = Version::parse("2.9.0").unwrap();
+    let frames: Vec<_> = (1..3)
+        .map(|i| {
+            let raw = read_fixture(format!("tests/data/frame-motive-1.9.0-00{}.bin", i));
+            frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap())
+        })
+        .collect();
+    let sender = natnet_decode::Sender {
+        name: "Mock".to_string(),
+        version: Version::parse("1.9.0").unwrap(),
+        natnet_version: version.clone(),
+    };
+    let mock = MockServer::start(sender.clone()).unwrap();
+    mock.respond(&Command::StartRecording, NatNetResponse::Response(0));
+    let mut client = Client::connect(loopback_config(mock.command_addr().port())).unwrap();
+    assert_eq!(client.sender(), &sender);
+    assert_eq!(client.request(Command::StartRecording).unwrap(),
+               CommandResponse::Success);
+    assert_eq!(client.request(Command::StartRecording).unwrap(),
+               CommandResponse::Unrecognized);
+    assert_eq!(mock.requests().len(), 4);
+
+    let data_port = client.data_addr().unwrap().port();
+    mock.set_data_addr(Some(format!("127.0.0.1:{}", data_port).parse().unwrap()));
+    let first = "tests/data/frame-motive-1.9.0-001.bin";
+    let second = "tests/data/frame-motive-1.9.0-002.bin";
+    mock.inject(Fault::Truncate(20));
+    assert_eq!(mock.replay(first).unwrap(), 1);
+    mock.replay(second).unwrap();
+    assert!(client.recv_frame().is_err());
+    assert_eq!(client.recv_frame().unwrap(), frames[1]);
+    mock.inject(Fault::Drop);
+    mock.replay(first).unwrap();
+    mock.replay(second).unwrap();
+    assert_eq!(client.recv_frame().unwrap(), frames[1]);
+    mock.inject(Fault::Duplicate);
+    mock.replay(first).unwrap();
+    assert_eq!(client.recv_frame().unwrap(), frames[0]);
+    assert_eq!(client.recv_frame().unwrap(), frames[0]);
+    mock.inject(Fault::Reorder);
+    mock.replay(first).unwrap();
+    mock.replay(second).unwrap();
+    assert_eq!(client.recv_frame().unwrap(), frames[1]);
+    assert_eq!(client.recv_frame().unwrap(), frames[0]);
+
+    // The server changes version and disconnects the client, which connects
+    // again and decodes frames using the new version
+    let version = Version::parse("3.0.0").unwrap();
+    mock.set_sender(natnet_decode::Sender { natnet_version: version.clone(), ..sender });
+    let frame = frame_of(NatNet::unpack_with(&version, &mut frame_3_0().as_slice()).unwrap());
+    mock.send_raw(&[9, 0, 0, 0]).unwrap();
+    mock.send(&NatNetResponse::FrameOfData(frame.clone())).unwrap();
+    assert_eq!(client.recv_frame().unwrap(), frame);
+    assert_eq!(*client.parser().version(), version);
+    assert_eq!(client.server_description().unwrap().data_port, data_port);
+    drop(client);
+
+    // Random faults are the same for the same seed
+    let data = UdpSocket::bind("127.0.0.1:0").unwrap();
+    data.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    mock.set_data_addr(Some(data.local_addr().unwrap()));
+    let run = || {
+        mock.set_random_faults(Some(RandomFaults {
+            seed: 42,
+            drop: 0.2,
+            duplicate: 0.1,
+            reorder: 0.1,
+            truncate: 0.1,
+        }));
+        let mut frame = frame.clone();
+        for i in 0..50 {
+            frame.frame_number = i;
+            mock.send(&NatNetResponse::FrameOfData(frame.clone())).unwrap();
+        }
+        mock.set_random_faults(None);
+        mock.flush().unwrap();
+        let parser = NatNet::new(version.clone());
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 65536];
+        while let Ok(len) = data.recv(&mut buf) {
+            received.push(match parser.unpack(&mut &buf[..len]) {
+                Ok(NatNetResponse::FrameOfData(frame)) => Some(frame.frame_number),
+                _ => None,
+            });
+        }
+        received
+    };
+    let received = run();
+    assert!(received.len() != 50 || received.windows(2).any(|w| w[0] >= w[1]));
+    assert_eq!(received, run());
+}
+
+#[test]
+fn recording() {
+    let version = Version::parse("2.9.0").unwrap();
+    let parser = NatNet::new(version.clone());
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let mut frame = frame_of(parser.unpack(&mut raw.as_slice()).unwrap());
+    let sender = natnet_decode::Sender {
+        name: "Mock".to_string(),
+        version: Version::parse("1.9.0").unwrap(),
+        natnet_version: version.clone(),
+    };
+    let models = vec![model::DataSet::MarkerSet(model::MarkerSet {
+                          name: "Triangle".to_string(),
+                          markers: vec!["1".to_string()],
+                      })];
+    let mock = MockServer::start(sender.clone()).unwrap();
+    mock.set_models(models.clone());
+    let mut client = Client::connect(loopback_config(mock.command_addr().port())).unwrap();
+    let data_port = client.data_addr().unwrap().port();
+    mock.set_data_addr(Some(format!("127.0.0.1:{}", data_port).parse().unwrap()));
+
+    let path = env::temp_dir().join(format!("natnet-decode-recording-{}.nnrec", process::id()));
+    let mut writer = RecordingWriter::from_client(File::create(&path).unwrap(), &client).unwrap();
+    mock.replay("tests/data/frame-motive-1.9.0-001.bin").unwrap();
+    assert_eq!(writer.record(&mut client).unwrap(), frame);
+    // New model definitions are recorded when the tracked models change
+    let changed = vec![model::DataSet::MarkerSet(model::MarkerSet {
+                           name: "Square".to_string(),
+                           markers: vec!["1".to_string(), "2".to_string()],
+                       })];
+    mock.set_models(changed.clone());
+    frame.tracked_models_changed = Some(true);
+    mock.send(&NatNetResponse::FrameOfData(frame.clone())).unwrap();
+    writer.record(&mut client).unwrap();
+    drop(writer);
+    drop(client);
+
+    let read = |path: &Path| {
+        let reader = RecordingReader::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        assert_eq!(reader.sender(), &sender);
+        assert_eq!(reader.models(), models.as_slice());
+        reader.map(|msg| msg.unwrap()).collect::<Vec<_>>()
+    };
+    let messages = read(&path);
+    assert_eq!(messages.len(), 3);
+    assert!(messages.windows(2).all(|w| w[0].0 <= w[1].0));
+    match messages[0].1 {
+        NatNetResponse::FrameOfData(ref f) => assert_eq!(f.tracked_models_changed, Some(false)),
+        _ => panic!("Expected frame of data"),
+    }
+    assert_eq!(messages[1].1, NatNetResponse::FrameOfData(frame.clone()));
+    assert_eq!(messages[2].1, NatNetResponse::ModelDef(changed));
+
+    // A crash while writing leaves an incomplete record, which is ignored and
+    // cut off before appending
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[3, 1, 2, 3, 4, 5, 6, 7, 8, 200, 0, 0, 0, 7, 0])
+        .unwrap();
+    let mut reader = RecordingReader::new(File::open(&path).unwrap()).unwrap();
+    while reader.next_record().unwrap().is_some() {}
+    assert!(reader.is_incomplete());
+    assert_eq!(reader.offset(), len);
+    let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let mut writer = RecordingWriter::append(file).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-002.bin");
+    writer.write_packet(SystemTime::now(), &raw).unwrap();
+    drop(writer);
+    let mut reader = RecordingReader::new(File::open(&path).unwrap()).unwrap();
+    let mut records = Vec::new();
+    while let Some((_, record)) = reader.next_record().unwrap() {
+        records.push(record);
+    }
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3], Record::Packet(raw));
+    assert!(!reader.is_incomplete());
+
+    // Corruption elsewhere is detected
+    let mut bytes = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes[len as usize - 10] ^= 0xff;
+    let results: Vec<_> = RecordingReader::new(bytes.as_slice()).unwrap().collect();
+    assert_eq!(results.len(), 3);
+    match results[2] {
+        Err(ParseError::CorruptRecording { .. }) => {}
+        ref res => panic!("Expected corrupt recording, got {:?}", res),
+    }
+
+    // So is a last record whose payload never reached the disk, which is
+    // cut off as well
+    bytes[len as usize - 10] ^= 0xff;
+    for b in &mut bytes[len as usize + 13..] {
+        *b = 0;
+    }
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    let mut reader = RecordingReader::new(File::open(&path).unwrap()).unwrap();
+    while reader.next_record().unwrap().is_some() {}
+    assert!(reader.is_incomplete());
+    assert_eq!(reader.offset(), len);
+    let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    drop(RecordingWriter::append(file).unwrap());
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay() {
+    let version = Version::parse("2.9.0").unwrap();
+    let parser = NatNet::new(version.clone());
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let mut frame = frame_of(parser.unpack(&mut raw.as_slice()).unwrap());
+    let sender = natnet_decode::Sender {
+        name: "Mock".to_string(),
+        version: Version::parse("1.9.0").unwrap(),
+        natnet_version: version.clone(),
+    };
+    let models = vec![model::DataSet::MarkerSet(model::MarkerSet {
+                          name: "Triangle".to_string(),
+                          markers: vec!["1".to_string()],
+                      })];
+    // Ten frames recorded 20ms apart
+    let path = env::temp_dir().join(format!("natnet-decode-replay-{}.nnrec", process::id()));
+    let mut writer = RecordingWriter::new(File::create(&path).unwrap(), &sender, &models)
+        .unwrap();
+    let start = SystemTime::now();
+    for i in 0..10 {
+        frame.frame_number = i;
+        let mut bytes = Vec::new();
+        NatNet::pack_with(&version, &NatNetResponse::FrameOfData(frame.clone()), &mut bytes)
+            .unwrap();
+        writer.write_packet(start + Duration::from_millis(20 * i as u64), &bytes).unwrap();
+    }
+    drop(writer);
+    let frame_number = |mut bytes: &[u8]| frame_of(parser.unpack(&mut bytes).unwrap()).frame_number;
+
+    // Twice as fast as recorded, to a channel
+    let (tx, rx) = mpsc::channel();
+    let config = ReplayConfig {
+        speed: 2.0,
+        command_addr: None,
+        ..ReplayConfig::default()
+    };
+    let replayer = Replayer::open(&path, Destination::Channel(tx), config.clone()).unwrap();
+    let first = rx.recv().unwrap();
+    let started = Instant::now();
+    let rest: Vec<_> = rx.iter().collect();
+    let elapsed = started.elapsed();
+    assert_eq!(frame_number(&first), 0);
+    assert_eq!(rest.iter().map(|b| frame_number(b)).collect::<Vec<_>>(),
+               (1..10).collect::<Vec<_>>());
+    assert!(elapsed >= Duration::from_millis(80), "Replay took {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "Replay took {:?}", elapsed);
+    replayer.wait();
+    assert!(replayer.is_finished());
+    assert_eq!(replayer.sent(), 10);
+    drop(replayer);
+
+    // Paused, stepping one packet at a time
+    let (tx, rx) = mpsc::channel();
+    let replayer = Replayer::open(&path,
+                                  Destination::Channel(tx),
+                                  ReplayConfig { paused: true, ..config.clone() })
+        .unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    replayer.step();
+    assert_eq!(frame_number(&rx.recv_timeout(Duration::from_secs(5)).unwrap()), 0);
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    replayer.step();
+    assert_eq!(frame_number(&rx.recv_timeout(Duration::from_secs(5)).unwrap()), 1);
+    assert!(replayer.is_paused());
+    replayer.set_speed(20.0);
+    replayer.resume();
+    assert_eq!(rx.iter().count(), 8);
+    drop(replayer);
+
+    // Looping over UDP, answering requests from the recorded header
+    let data = UdpSocket::bind("127.0.0.1:0").unwrap();
+    data.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let replayer = Replayer::open(&path,
+                                  Destination::Udp(data.local_addr().unwrap()),
+                                  ReplayConfig {
+                                      speed: 10.0,
+                                      looping: true,
+                                      command_addr: Some("127.0.0.1:0".parse().unwrap()),
+                                      ..config
+                                  })
+        .unwrap();
+    let client = Client::connect(loopback_config(replayer.command_addr().unwrap().port())).unwrap();
+    assert_eq!(client.sender(), &sender);
+    assert_eq!(client.models(), models.as_slice());
+    let mut buf = vec![0u8; 65536];
+    let received: Vec<_> = (0..15)
+        .map(|_| {
+            let len = data.recv(&mut buf).unwrap();
+            frame_number(&buf[..len])
+        })
+        .collect();
+    assert_eq!(received, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4]);
+    assert!(!replayer.is_finished());
+    drop(client);
+    drop(replayer);
+
+    // A corrupt record ends playback, also when looping
+    let mut bytes = read_fixture(&path);
+    let corrupt = {
+        let mut reader = RecordingReader::new(bytes.as_slice()).unwrap();
+        for _ in 0..5 {
+            reader.next_record().unwrap();
+        }
+        reader.offset() as usize
+    };
+    bytes[corrupt + 20] ^= 0xff;
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let replayer = Replayer::open(&path,
+                                  Destination::Channel(tx),
+                                  ReplayConfig {
+                                      speed: 10.0,
+                                      looping: true,
+                                      command_addr: None,
+                                      ..config
+                                  })
+        .unwrap();
+    replayer.wait();
+    drop(replayer);
+    assert_eq!(rx.iter().map(|b| frame_number(&b)).collect::<Vec<_>>(),
+               (0..5).collect::<Vec<_>>());
+
+    // Looping over a recording without packets finishes, and can be dropped
+    drop(RecordingWriter::new(File::create(&path).unwrap(), &sender, &models).unwrap());
+    let (tx, rx) = mpsc::channel();
+    let replayer = Replayer::open(&path,
+                                  Destination::Channel(tx),
+                                  ReplayConfig {
+                                      looping: true,
+                                      command_addr: None,
+                                      ..config
+                                  })
+        .unwrap();
+    replayer.wait();
+    drop(replayer);
+    assert_eq!(rx.iter().count(), 0);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_client() {
+    use futures_util::future;
+    use futures_util::stream::StreamExt;
+    use natnet_decode::async_client::AsyncClient;
+
+    let ping = read_fixture("tests/data/frame-motive-1.9.0-000.bin");
+    let frame = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let version = Version::parse("2.9.0").unwrap();
+
+    // Stand-in unicast server, frames of data are sent between the replies
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let port = server.local_addr().unwrap().port();
+    let data = frame.clone();
+    let handle = thread::spawn(move || {
+        let pack = |msg: NatNetResponse| {
+            let mut bytes = Vec::new();
+            NatNet::pack_with(&version, &msg, &mut bytes).unwrap();
+            bytes
+        };
+        let recv = || {
+            let mut buf = [0u8; 1024];
+            loop {
+                let (len, client) = server.recv_from(&mut buf).unwrap();
+                if &buf[..len] != &[10, 0, 0, 0] {
+                    return (buf[..len].to_vec(), client);
+                }
+            }
+        };
+        let (req, client) = recv();
+        assert_eq!(&req[..2], &[0, 0]);
+        server.send_to(&ping, client).unwrap();
+        let (req, _) = recv();
+        assert_eq!(req, vec![4, 0, 0, 0]);
+        server.send_to(&pack(NatNetResponse::ModelDef(Vec::new())), client).unwrap();
+        // Two commands in flight at once
+        let (first, _) = recv();
+        let (second, _) = recv();
+        assert_eq!(&first[4..], b"GetProperty,,Frame Rate\0");
+        assert_eq!(&second[4..], b"StartRecording\0");
+        server.send_to(&data, client).unwrap();
+        server.send_to(&pack(NatNetResponse::ResponseString("120.0".to_string())), client)
+            .unwrap();
+        server.send_to(&data, client).unwrap();
+        server.send_to(&pack(NatNetResponse::Response(0)), client).unwrap();
+        server.send_to(&data, client).unwrap();
+        // A late reply is not taken for the reply to the next command
+        let (late, _) = recv();
+        assert_eq!(&late[4..], b"StopRecording\0");
+        thread::sleep(Duration::from_millis(300));
+        let (next, _) = recv();
+        assert_eq!(&next[4..], b"LiveMode\0");
+        server.send_to(&pack(NatNetResponse::Response(1)), client).unwrap();
+        server.send_to(&pack(NatNetResponse::Response(0)), client).unwrap();
+        let (req, _) = recv();
+        assert_eq!(req, vec![9, 0, 0, 0]);
+    });
+
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let config = ClientConfig {
+        unicast: true,
+        ..loopback_config(port)
+    };
+    let mut client = rt.block_on(AsyncClient::connect(config)).unwrap();
+    assert_eq!(client.sender().unwrap().name, "NatNetLib");
+    let get = Command::GetProperty {
+        node: "".to_string(),
+        name: "Frame Rate".to_string(),
+    };
+    let timeout = Duration::from_secs(5);
+    let (first, second) = rt.block_on(future::join(client.request(get, timeout),
+                                                   client.request(Command::StartRecording,
+                                                                  timeout)));
+    assert_eq!(first.unwrap(), CommandResponse::Property("120.0".to_string()));
+    assert_eq!(second.unwrap(), CommandResponse::Success);
+    for _ in 0..3 {
+        assert!(rt.block_on(client.next()).unwrap().is_ok());
+    }
+
+    let requester = client.requester();
+    let late = rt.block_on(requester.request(Command::StopRecording, Duration::from_millis(200)));
+    assert!(late.is_err());
+    assert_eq!(rt.block_on(requester.request(Command::LiveMode, timeout)).unwrap(),
+               CommandResponse::Success);
+    drop(client);
+    drop(requester);
+    handle.join().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_frame_buffer() {
+    use futures_util::stream::StreamExt;
+    use natnet_decode::async_client::AsyncClient;
+
+    let version = Version::parse("2.9.0").unwrap();
+    let raw = read_fixture("tests/data/frame-motive-1.9.0-001.bin");
+    let mut frame = frame_of(NatNet::unpack_with(&version, &mut raw.as_slice()).unwrap());
+    let mock = MockServer::start(natnet_decode::Sender {
+            name: "Mock".to_string(),
+            version: Version::parse("1.9.0").unwrap(),
+            natnet_version: version,
+        })
+        .unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    // Timers are created outside of `block_on`
+    let _guard = rt.enter();
+    let mut client = rt.block_on(AsyncClient::connect(loopback_config(mock.command_addr().port())))
+        .unwrap();
+    let data_port = client.data_addr().unwrap().port();
+    mock.set_data_addr(Some(format!("127.0.0.1:{}", data_port).parse().unwrap()));
+
+    // A client which does not keep up gets the most recent frames
+    for i in 0..20 {
+        frame.frame_number = i;
+        mock.send(&NatNetResponse::FrameOfData(frame.clone())).unwrap();
+    }
+    rt.block_on(tokio::time::sleep(Duration::from_millis(200)));
+    let received: Vec<_> = (0..16)
+        .map(|_| rt.block_on(client.next()).unwrap().unwrap().frame_number)
+        .collect();
+    assert_eq!(received, (4..20).collect::<Vec<_>>());
+    let next = rt.block_on(tokio::time::timeout(Duration::from_millis(100), client.next()));
+    assert!(next.is_err());
+}